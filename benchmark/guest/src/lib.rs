@@ -2,7 +2,7 @@
 extern crate alloc;
 
 use pingpong::{Logger, SpiConfigurator, Timer, run_benchmark_matrix};
-use wasi_embedded_hal::WasiSpiDevice;
+use wasi_embedded_hal::{BitOrder, BusConfig, Mode, WasiSpiDevice};
 
 wit_bindgen::generate!({
     path: "wit",
@@ -23,9 +23,13 @@ impl Timer for WasiBenchEnv {
 }
 
 impl SpiConfigurator<WasiSpiDevice> for WasiBenchEnv {
-    type Error = ();
-    fn set_baud_rate(&mut self, _spi: &mut WasiSpiDevice, baud: u32) -> Result<(), Self::Error> {
-        crate::wasi::benchmark::bench_utils::set_baud_rate(baud);
+    type Error = wasi_embedded_hal::WasiSpiError;
+    fn set_baud_rate(&mut self, spi: &mut WasiSpiDevice, baud: u32) -> Result<(), Self::Error> {
+        spi.configure(BusConfig {
+            frequency: baud,
+            mode: Mode::Mode0,
+            bit_order: BitOrder::MsbFirst,
+        })?;
         Ok(())
     }
 }
@@ -2,11 +2,12 @@
 use linux_embedded_hal::SpidevDevice;
 use linux_embedded_hal::spidev::{SpiModeFlags, SpidevOptions};
 use pingpong::{Logger, SpiConfigurator, Timer, run_benchmark_matrix};
-use spi::{ErasedSpiDevice, SpiCtx, SpiView};
+use runtime::EmbeddedHost;
+use spi::{BitOrder, BusConfig, Mode, Reconfigurable, SpiConfigBounds, SpiHardware, SpiLimits};
 use std::time::Instant;
 use wasmtime::{
-    Config, Engine, Store,
-    component::{Component, HasSelf, Linker, ResourceTable},
+    Config, Engine,
+    component::{Component, HasSelf},
 };
 
 // ----- Native Benchmark Implementations -----
@@ -46,39 +47,10 @@ wasmtime::component::bindgen!({
     world: "benchmark-app",
 });
 
-struct HostState {
-    spi_ctx: SpiCtx,
-    app_start_time: Instant,
-    spi_path: String,
-}
-
-impl SpiView for HostState {
-    fn spi_ctx(&mut self) -> &mut SpiCtx {
-        &mut self.spi_ctx
-    }
-}
-
-impl crate::my::timer::timer::Host for HostState {
-    fn now_micros(&mut self) -> u64 {
-        self.app_start_time.elapsed().as_micros() as u64
-    }
-}
-
-impl crate::wasi::benchmark::bench_utils::Host for HostState {
-    fn set_baud_rate(&mut self, baud: u32) {
-        // Open a secondary handle just to configure the device ioctls
-        if let Ok(mut spi) = SpidevDevice::open(&self.spi_path) {
-            let options = SpidevOptions::new()
-                .bits_per_word(8)
-                .max_speed_hz(baud)
-                .mode(SpiModeFlags::SPI_MODE_0)
-                .build();
-            let _ = spi.configure(&options);
-        }
-    }
-
+// The benchmark's own log import goes through the runtime's logger
+impl crate::wasi::benchmark::bench_utils::Host for EmbeddedHost {
     fn log(&mut self, msg: String) {
-        println!("{}", msg);
+        EmbeddedHost::log(self, &msg);
     }
 }
 
@@ -125,34 +97,44 @@ fn main() -> anyhow::Result<()> {
         )?;
 
         let spi = SpidevDevice::open(spi_path).unwrap();
-        let mut spi_hardware: Vec<(String, Box<dyn ErasedSpiDevice + Send + 'static>)> = vec![];
-        spi_hardware.push(("bench".to_string(), Box::new(spi)));
-
-        let state = HostState {
-            spi_ctx: SpiCtx {
-                table: ResourceTable::new(),
-                hardware: spi_hardware,
+        let spi = Reconfigurable::new(spi, |dev: &mut SpidevDevice, config: &BusConfig| {
+            NativeBenchEnv
+                .set_baud_rate(dev, config.frequency)
+                .map_err(|e| spi::Error::Other(e.to_string()))
+        });
+        let spi_hardware = vec![SpiHardware {
+            name: "bench".to_string(),
+            limits: SpiLimits::default(),
+            // The guest sweeps the same baud rates as the native run, always in mode 0
+            bounds: SpiConfigBounds {
+                min_frequency: 100_000,
+                max_frequency: 32_000_000,
+                modes: vec![Mode::Mode0],
+                bit_orders: vec![BitOrder::MsbFirst],
             },
-            app_start_time: Instant::now(),
-            spi_path: spi_path.to_string(),
-        };
-
-        let mut store = Store::new(&engine, state);
-        let mut linker = Linker::new(&engine);
+            device: Box::new(spi),
+        }];
+
+        let app_start_time = Instant::now();
+        let host = EmbeddedHost::builder()
+            .spi(spi_hardware)
+            .delay(linux_embedded_hal::Delay)
+            .logger(|msg| println!("{}", msg))
+            .clock(move || app_start_time.elapsed().as_micros() as u64)
+            .build();
 
-        spi::add_to_linker(&mut linker)?;
+        let mut linker = EmbeddedHost::linker(&engine)?;
 
         // Use HasSelf to satisfy the trait bounds for type inference
-        crate::my::timer::timer::add_to_linker::<HostState, HasSelf<HostState>>(
-            &mut linker,
-            |state| state,
-        )?;
-        crate::wasi::benchmark::bench_utils::add_to_linker::<HostState, HasSelf<HostState>>(
+        crate::wasi::benchmark::bench_utils::add_to_linker::<EmbeddedHost, HasSelf<EmbeddedHost>>(
             &mut linker,
-            |state| state,
+            |host| host,
         )?;
 
-        let app = BenchmarkApp::instantiate(&mut store, &component, &linker)?;
+        let (mut store, app) =
+            host.instantiate(&linker, &component, |store, component, linker| {
+                BenchmarkApp::instantiate(store, component, linker)
+            })?;
 
         // Starts the run matrix directly inside the guest
         app.call_run_pingpong(&mut store)?;
@@ -10,14 +10,13 @@ use embassy_rp::spi::{Blocking, Config as SpiConfig, Spi};
 use embassy_time::{Delay, Instant};
 use embedded_alloc::Heap;
 use embedded_hal_bus::spi::ExclusiveDevice;
-use wasmtime::component::{Component, HasSelf, Linker, ResourceTable};
-use wasmtime::{Config, Engine, Store};
+use wasmtime::component::{Component, HasSelf};
+use wasmtime::{Config, Engine};
 use {defmt_rtt as _, panic_probe as _};
 
-use delay::{DelayCtx, DelayView};
-use gpio::{GpioCtx, GpioView};
 use pingpong::{Logger, SpiConfigurator, Timer, run_benchmark_matrix};
-use spi::{ErasedSpiDevice, SpiCtx, SpiView};
+use runtime::EmbeddedHost;
+use spi::{BitOrder, BusConfig, Mode, Reconfigurable, SpiConfigBounds, SpiHardware, SpiLimits};
 
 wasmtime::component::bindgen!({
     path: "../guest/wit",
@@ -33,51 +32,10 @@ static HEAP: Heap = Heap::empty();
 type PicoSpiBus = Spi<'static, embassy_rp::peripherals::SPI0, Blocking>;
 type PicoSpiDevice = ExclusiveDevice<PicoSpiBus, Output<'static>, Delay>;
 
-pub struct HostState {
-    pub spi_ctx: SpiCtx,
-    pub gpio_ctx: GpioCtx,
-    pub delay_ctx: DelayCtx,
-    // A backdoor to reconfigure the SPI baud rate from the Wasm runtime
-    pub spi_raw_ptr: *mut PicoSpiDevice,
-}
-
-impl SpiView for HostState {
-    fn spi_ctx(&mut self) -> &mut SpiCtx {
-        &mut self.spi_ctx
-    }
-}
-impl GpioView for HostState {
-    fn gpio_ctx(&mut self) -> &mut GpioCtx {
-        &mut self.gpio_ctx
-    }
-}
-impl DelayView for HostState {
-    fn delay_ctx(&mut self) -> &mut DelayCtx {
-        &mut self.delay_ctx
-    }
-}
-
 // --- WASI Imports for Wasm Benchmark ---
-impl crate::my::timer::timer::Host for HostState {
-    fn now_micros(&mut self) -> u64 {
-        Instant::now().as_micros()
-    }
-}
-
-impl crate::wasi::benchmark::bench_utils::Host for HostState {
-    fn set_baud_rate(&mut self, baud: u32) {
-        unsafe {
-            if !self.spi_raw_ptr.is_null() {
-                let mut config = SpiConfig::default();
-                config.frequency = baud;
-                // Safely reach into the type-erased bus and set the new hardware baud rate
-                (*self.spi_raw_ptr).bus_mut().set_config(&config);
-            }
-        }
-    }
-
+impl crate::wasi::benchmark::bench_utils::Host for EmbeddedHost {
     fn log(&mut self, msg: String) {
-        defmt::info!("{=str}", msg.as_str());
+        EmbeddedHost::log(self, &msg);
     }
 }
 
@@ -177,15 +135,25 @@ async fn main(_spawner: Spawner) {
     // 2. Run Wasm Context via Pulley
     // =====================================
     {
-        // Box the SPI device and get a stable pointer for the baud rate backdoor
-        let mut boxed_spi = alloc::boxed::Box::new(spi_device);
-        let spi_raw_ptr = boxed_spi.as_mut() as *mut PicoSpiDevice;
-
-        let mut spi_hardware: alloc::vec::Vec<(
-            String,
-            alloc::boxed::Box<dyn ErasedSpiDevice + Send + 'static>,
-        )> = alloc::vec::Vec::new();
-        spi_hardware.push(("bench".into(), boxed_spi));
+        // The guest sweeps the baud rate through `configure`, reusing the native configurator
+        let spi_device =
+            Reconfigurable::new(spi_device, |dev: &mut PicoSpiDevice, config: &BusConfig| {
+                NativeBenchEnv
+                    .set_baud_rate(dev, config.frequency)
+                    .map_err(|_| spi::Error::Other("Failed to set baud rate".into()))
+            });
+
+        let spi_hardware = alloc::vec![SpiHardware {
+            name: "bench".into(),
+            limits: SpiLimits::default(),
+            bounds: SpiConfigBounds {
+                min_frequency: 100_000,
+                max_frequency: 32_000_000,
+                modes: alloc::vec![Mode::Mode0],
+                bit_orders: alloc::vec![BitOrder::MsbFirst],
+            },
+            device: alloc::boxed::Box::new(spi_device),
+        }];
 
         let mut config = Config::new();
         config.target("pulley32").unwrap();
@@ -200,42 +168,28 @@ async fn main(_spawner: Spawner) {
 
         let engine = Engine::new(&config).expect("Engine failed");
 
-        let host_state = HostState {
-            spi_ctx: SpiCtx {
-                table: ResourceTable::new(),
-                hardware: spi_hardware,
-            },
-            // CHANGED Vec::new() to BTreeMap::new() HERE
-            gpio_ctx: GpioCtx {
-                pins: alloc::collections::BTreeMap::new(),
-            },
-            delay_ctx: DelayCtx {
-                delay: alloc::boxed::Box::new(Delay),
-            },
-            spi_raw_ptr,
-        };
-
-        let mut store = Store::new(&engine, host_state);
-        let mut linker = Linker::new(&engine);
+        let host = EmbeddedHost::builder()
+            .spi(spi_hardware)
+            .delay(Delay)
+            .logger(|msg| defmt::info!("{=str}", msg))
+            .clock(|| Instant::now().as_micros())
+            .build();
 
-        spi::add_to_linker(&mut linker).unwrap();
-        gpio::add_to_linker(&mut linker).unwrap();
-        delay::add_to_linker(&mut linker).unwrap();
-        crate::my::timer::timer::add_to_linker::<HostState, HasSelf<HostState>>(
-            &mut linker,
-            |state| state,
-        )
-        .unwrap();
-        crate::wasi::benchmark::bench_utils::add_to_linker::<HostState, HasSelf<HostState>>(
+        let mut linker = EmbeddedHost::linker(&engine).unwrap();
+        crate::wasi::benchmark::bench_utils::add_to_linker::<EmbeddedHost, HasSelf<EmbeddedHost>>(
             &mut linker,
-            |state| state,
+            |host| host,
         )
         .unwrap();
 
         let guest_bytes = include_bytes!("benchmark_guest.pulley");
         let component = unsafe { Component::deserialize(&engine, guest_bytes) }.unwrap();
 
-        let app = BenchmarkApp::instantiate(&mut store, &component, &linker).unwrap();
+        let (mut store, app) = host
+            .instantiate(&linker, &component, |store, component, linker| {
+                BenchmarkApp::instantiate(store, component, linker)
+            })
+            .unwrap();
 
         // Starts the run matrix directly inside the guest
         let _ = app.call_run_pingpong(&mut store);
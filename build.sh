@@ -3,10 +3,14 @@ set -e
 
 # Get the target from the first argument, default to "pico" if none provided
 TARGET=${1:-pico}
+# The Linux targets take a second argument: "hardware" (default) or "sim" for the [sim] devices
+BACKEND=${2:-hardware}
+# and a third: "real" (default) or "virtual" time, which needs "sim"
+CLOCK=${3:-real}
 
 if [[ "$TARGET" != "pico" && "$TARGET" != "linux" && "$TARGET" != "bench-linux" && "$TARGET" != "bench-pico" && "$TARGET" != "physics-pico" && "$TARGET" != "physics-linux" ]]; then
   echo "❌ Invalid target: $TARGET"
-  echo "Usage: ./build.sh [pico|linux|bench-linux|bench-pico|physics-pico|physics-linux]"
+  echo "Usage: ./build.sh [pico|linux|bench-linux|bench-pico|physics-pico|physics-linux] [hardware|sim] [real|virtual]"
   exit 1
 fi
 
@@ -104,7 +108,7 @@ if [[ "$TARGET" == "physics-pico" || "$TARGET" == "physics-linux" ]]; then
     echo "🧩 Running compiler (Pulley) for Physics Demo"
     echo "========================================"
     # Overwrite guest.pulley so the pico host automatically picks it up without code changes
-    cargo run -p compiler -- physics_guest.component.wasm host/src/guest.pulley
+    cargo run -p compiler -- physics_guest.component.wasm host/src/guest.pulley host/policy.toml
 
     echo
     echo "========================================"
@@ -118,7 +122,7 @@ if [[ "$TARGET" == "physics-pico" || "$TARGET" == "physics-linux" ]]; then
     echo "========================================"
     echo "🚀 Running Linux host for Physics Demo (release)"
     echo "========================================"
-    cargo run -p linux-host --release -- --policy-file linux-host/policy.toml physics_guest.component.wasm
+    cargo run -p linux-host --release -- --backend "$BACKEND" --clock "$CLOCK" --policy-file linux-host/policy.toml physics_guest.component.wasm
   fi
 
   echo
@@ -149,7 +153,7 @@ if [ "$TARGET" = "pico" ]; then
   echo "🧩 Running compiler (Pulley)"
   echo "========================================"
   # Pass standard input and output dynamically
-  cargo run -p compiler -- guest.component.wasm host/src/guest.pulley
+  cargo run -p compiler -- guest.component.wasm host/src/guest.pulley host/policy.toml
 
   echo
   echo "========================================"
@@ -163,7 +167,7 @@ elif [ "$TARGET" = "linux" ]; then
   echo "========================================"
   echo "🚀 Running Linux host (release)"
   echo "========================================"
-  cargo run -p linux-host --release -- --policy-file linux-host/policy.toml guest.component.wasm
+  cargo run -p linux-host --release -- --backend "$BACKEND" --clock "$CLOCK" --policy-file linux-host/policy.toml guest.component.wasm
 fi
 
 echo
@@ -1,3 +1,5 @@
+use anyhow::Context;
+use policy::{manifest, pico};
 use std::env;
 use std::fs;
 use std::path::Path;
@@ -6,12 +8,16 @@ use wasmtime::{Config, Engine};
 fn main() -> anyhow::Result<()> {
     let args: Vec<String> = env::args().collect();
     if args.len() < 3 {
-        eprintln!("Usage: {} <input_wasm> <output_pulley>", args[0]);
+        eprintln!(
+            "Usage: {} <input_wasm> <output_pulley> [policy_toml]",
+            args[0]
+        );
         std::process::exit(1);
     }
 
     let input_path = Path::new(&args[1]);
     let output_path = Path::new(&args[2]);
+    let policy_path = args.get(3).map(Path::new);
 
     println!("Compiling {:?} for Pulley...", input_path);
 
@@ -32,6 +38,25 @@ fn main() -> anyhow::Result<()> {
 
     // 3. Read input, precompile, and write output
     let wasm_bytes = fs::read(input_path)?;
+
+    // The firmware can't report a missing label until it boots, so catch it here
+    if let Some(policy_path) = policy_path {
+        let policy = pico::Policy::from_toml(&fs::read_to_string(policy_path)?)
+            .with_context(|| format!("Invalid policy file {:?}", policy_path))?;
+        match manifest::check_component(&wasm_bytes, &policy.granted())
+            .with_context(|| format!("{:?} cannot run under {:?}", input_path, policy_path))?
+        {
+            Some(diff) if !diff.unused.is_empty() => {
+                println!(
+                    "Note: the guest doesn't use everything the policy grants:\n{}",
+                    diff
+                )
+            }
+            Some(_) => {}
+            None => println!("Note: {:?} has no manifest to check", input_path),
+        }
+    }
+
     let serialized = engine.precompile_component(&wasm_bytes)?;
 
     fs::write(output_path, &serialized)?;
@@ -23,6 +23,11 @@ use embedded_graphics::{
     text::Text,
 };
 
+wasi_embedded_hal::manifest! {
+    spi: ["screen"],
+    gpio: ["RES", "VBATC", "VDDC", "DC"],
+}
+
 // A simple particle for our physics simulation
 struct Particle {
     x: f32,
@@ -39,10 +44,10 @@ impl Guest for MainApp {
         log("System Starting: 0G Physics Demo");
         let mut delay = WasiDelay;
 
-        let mut oled_res = WasiOutputPin::new("RES");
-        let mut oled_vbatc = WasiOutputPin::new("VBATC");
-        let mut oled_vddc = WasiOutputPin::new("VDDC");
-        let oled_dc = WasiOutputPin::new("DC");
+        let mut oled_res = WasiOutputPin::open("RES").expect("Failed RES pin");
+        let mut oled_vbatc = WasiOutputPin::open("VBATC").expect("Failed VBATC pin");
+        let mut oled_vddc = WasiOutputPin::open("VDDC").expect("Failed VDDC pin");
+        let oled_dc = WasiOutputPin::open("DC").expect("Failed DC pin");
 
         // Screen bootup sequence
         let _ = oled_vddc.set_low();
@@ -0,0 +1,3 @@
+fn main() {
+    wasi_embedded_hal::codegen::from_manifest("manifest.toml");
+}
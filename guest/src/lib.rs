@@ -8,7 +8,7 @@ extern crate alloc;
 
 use crate::my::debug::logging::log;
 use alloc::format;
-use wasi_embedded_hal::{WasiDelay, WasiOutputPin, WasiSpiDevice};
+use wasi_embedded_hal::WasiDelay;
 
 use embedded_hal::digital::OutputPin;
 
@@ -23,6 +23,9 @@ use embedded_graphics::{
     text::Text,
 };
 
+// `Board`, generated from manifest.toml by build.rs
+include!(concat!(env!("OUT_DIR"), "/board.rs"));
+
 struct MainApp;
 
 impl Guest for MainApp {
@@ -30,10 +33,14 @@ impl Guest for MainApp {
         log("System Starting...");
         let mut delay = WasiDelay;
 
-        let mut oled_res = WasiOutputPin::new("RES");
-        let mut oled_vbatc = WasiOutputPin::new("VBATC");
-        let mut oled_vddc = WasiOutputPin::new("VDDC");
-        let oled_dc = WasiOutputPin::new("DC");
+        let Board {
+            sensor: sensor_spi,
+            screen: screen_spi,
+            res: mut oled_res,
+            vbatc: mut oled_vbatc,
+            vddc: mut oled_vddc,
+            dc: oled_dc,
+        } = Board::open().expect("Failed to open board");
 
         // Screen bootup sequence
         let _ = oled_vddc.set_low();
@@ -41,9 +48,6 @@ impl Guest for MainApp {
         embedded_hal::delay::DelayNs::delay_us(&mut delay, 10);
         let _ = oled_res.set_high();
 
-        let screen_spi = WasiSpiDevice::open("screen").expect("Failed screen SPI");
-        let sensor_spi = WasiSpiDevice::open("sensor").expect("Failed sensor SPI");
-
         let mut display = Ssd1306::new(
             SPIInterface::new(screen_spi, oled_dc),
             DisplaySize128x32,
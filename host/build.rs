@@ -8,34 +8,74 @@
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
 
+use policy::SpiMode;
+use policy::pico::{Policy, SpiDevice};
 use quote::{format_ident, quote};
-use serde::Deserialize;
 use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use std::path::PathBuf;
 
-#[derive(Deserialize)]
-struct Policy {
-    spi: Option<BTreeMap<String, SpiConfig>>,
-    gpio: Option<BTreeMap<String, GpioConfig>>,
+fn spi_mode(mode: SpiMode) -> proc_macro2::TokenStream {
+    match mode {
+        SpiMode::Mode0 => quote!(spi::Mode::Mode0),
+        SpiMode::Mode1 => quote!(spi::Mode::Mode1),
+        SpiMode::Mode2 => quote!(spi::Mode::Mode2),
+        SpiMode::Mode3 => quote!(spi::Mode::Mode3),
+    }
 }
 
-#[derive(Deserialize)]
-struct SpiConfig {
-    block: String,
-    sck: u8,
-    mosi: u8,
-    miso: u8,
-    cs: u8,
-    frequency: u32,
-    mode: u8,
+/// The embassy settings for a device's initial frequency and mode.
+fn rp_config(config: &SpiDevice) -> proc_macro2::TokenStream {
+    let freq = config.frequency;
+    let mode = spi_mode(config.mode);
+    quote! {
+        spi_bus::rp_config(&spi::BusConfig {
+            frequency: #freq,
+            mode: #mode,
+            bit_order: spi::BitOrder::MsbFirst,
+        })
+        .unwrap()
+    }
 }
 
-#[derive(Deserialize)]
-struct GpioConfig {
-    pin: u8,
-    initial: String,
+/// Registers the `device` binding in scope under the policy label.
+fn push_spi_device(name: &str, config: &SpiDevice) -> proc_macro2::TokenStream {
+    let (min_freq, max_freq) = config.frequency_range();
+    let modes = config
+        .allowed_modes()
+        .into_iter()
+        .map(spi_mode)
+        .collect::<Vec<_>>();
+
+    let mut limit_overrides = quote! {};
+    if let Some(max) = config.max_bytes_per_op {
+        limit_overrides.extend(quote! { limits.max_bytes_per_op = #max; });
+    }
+    if let Some(max) = config.max_ops_per_transaction {
+        limit_overrides.extend(quote! { limits.max_ops_per_transaction = #max; });
+    }
+    if let Some(max) = config.max_bytes_per_transaction {
+        limit_overrides.extend(quote! { limits.max_bytes_per_transaction = #max; });
+    }
+
+    quote! {
+        #[allow(unused_mut)]
+        let mut limits = spi::SpiLimits::default();
+        #limit_overrides
+
+        spi_hardware.push(spi::SpiHardware {
+            name: alloc::string::String::from(#name),
+            limits,
+            bounds: spi::SpiConfigBounds {
+                min_frequency: #min_freq,
+                max_frequency: #max_freq,
+                modes: alloc::vec![#(#modes),*],
+                bit_orders: alloc::vec![spi::BitOrder::MsbFirst],
+            },
+            device: alloc::boxed::Box::new(device),
+        });
+    }
 }
 
 fn main() {
@@ -48,67 +88,279 @@ fn main() {
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 
     let policy_str = fs::read_to_string("policy.toml").expect("CRITICAL: policy.toml is missing!");
-    let policy: Policy =
-        toml::from_str(&policy_str).expect("CRITICAL: Failed to parse policy.toml");
+    // Also checks every pin against the RP2350 function table, before any `PIN_n` is generated
+    let policy = Policy::from_toml(&policy_str)
+        .unwrap_or_else(|err| panic!("CRITICAL: policy.toml is invalid: {}", err));
 
     let mut spi_initializations = quote! {
-        let mut spi_hardware: alloc::vec::Vec<(alloc::string::String, alloc::boxed::Box<dyn spi::ErasedSpiDevice + Send>)> = alloc::vec::Vec::new();
+        let mut spi_hardware: alloc::vec::Vec<spi::SpiHardware> = alloc::vec::Vec::new();
     };
 
-    // SPI
-    for (name, config) in policy.spi.into_iter().flatten() {
-        let sck_pin = format_ident!("PIN_{}", config.sck);
-        let mosi_pin = format_ident!("PIN_{}", config.mosi);
-        let miso_pin = format_ident!("PIN_{}", config.miso);
-        let cs_pin = format_ident!("PIN_{}", config.cs);
+    // SPI: group devices per block, so a block shared by several chip-selects is only taken once
+    let spi_buses = &policy.spi_bus;
+    let mut devices_per_bus: BTreeMap<&str, Vec<(&str, &SpiDevice)>> = BTreeMap::new();
+    for (name, config) in &policy.spi {
+        devices_per_bus
+            .entry(config.bus.as_str())
+            .or_default()
+            .push((name, config));
+    }
 
-        let block_num: u8 = config.block.trim_start_matches("SPI").parse().unwrap();
-        let block_ident = format_ident!("{}", config.block);
+    for (block, devices) in devices_per_bus {
+        let bus = &spi_buses[block];
+        let sck_pin = format_ident!("PIN_{}", bus.sck);
+        let mosi_pin = format_ident!("PIN_{}", bus.mosi);
+        let miso_pin = format_ident!("PIN_{}", bus.miso);
+
+        let block_num: u8 = block
+            .trim_start_matches("SPI")
+            .parse()
+            .expect("CRITICAL: SPI bus names must be SPI0 or SPI1");
+        let block_ident = format_ident!("{}", block);
         let spi_ident = format_ident!("spi{}", block_num);
-        let cs_ident = format_ident!("cs{}", block_num);
+        let initial_config = rp_config(devices[0].1);
+
+        spi_initializations.extend(quote! {
+            let #spi_ident = embassy_rp::spi::Spi::new_blocking(
+                $p.#block_ident, $p.#sck_pin, $p.#mosi_pin, $p.#miso_pin, #initial_config
+            );
+        });
+
+        if let [(name, config)] = devices.as_slice() {
+            let cs_pin = format_ident!("PIN_{}", config.cs);
+            let push = push_spi_device(name, config);
+
+            spi_initializations.extend(quote! {
+                let cs = embassy_rp::gpio::Output::new($p.#cs_pin, embassy_rp::gpio::Level::High);
+                let device = embedded_hal_bus::spi::ExclusiveDevice::new_no_delay(#spi_ident, cs).unwrap();
+                let device = spi::Reconfigurable::new(device, spi_bus::apply_spi_config);
+                #push
+            });
+            continue;
+        }
 
+        spi_initializations.extend(quote! {
+            let #spi_ident = spi_bus::share_bus(#spi_ident);
+        });
+
+        for (name, config) in devices {
+            let cs_pin = format_ident!("PIN_{}", config.cs);
+            let device_config = rp_config(config);
+            let push = push_spi_device(name, config);
+
+            spi_initializations.extend(quote! {
+                let cs = embassy_rp::gpio::Output::new($p.#cs_pin, embassy_rp::gpio::Level::High);
+                let device = spi_bus::SharedSpiDevice::new(#spi_ident, cs, #device_config);
+                #push
+            });
+        }
+    }
+
+    // I2C: each block is a single bus, the guest picks the target address per call
+    let mut i2c_initializations = quote! {
+        #[allow(unused_mut)]
+        let mut i2c_hardware: alloc::vec::Vec<i2c::I2cHardware> = alloc::vec::Vec::new();
+    };
+
+    for (name, config) in &policy.i2c {
+        let block_ident = format_ident!("{}", config.block);
+        let sda_pin = format_ident!("PIN_{}", config.sda);
+        let scl_pin = format_ident!("PIN_{}", config.scl);
         let freq = config.frequency;
-        let (pol, pha) = match config.mode {
-            0 => (quote!(embassy_rp::spi::Polarity::IdleLow), quote!(embassy_rp::spi::Phase::CaptureOnFirstTransition)),
-            1 => (quote!(embassy_rp::spi::Polarity::IdleLow), quote!(embassy_rp::spi::Phase::CaptureOnSecondTransition)),
-            2 => (quote!(embassy_rp::spi::Polarity::IdleHigh), quote!(embassy_rp::spi::Phase::CaptureOnFirstTransition)),
-            3 => (quote!(embassy_rp::spi::Polarity::IdleHigh), quote!(embassy_rp::spi::Phase::CaptureOnSecondTransition)),
-            _ => panic!("CRITICAL: SPI mode must be 0, 1, 2, or 3"),
+        let addresses = &config.addresses;
+
+        let mut limit_overrides = quote! {};
+        if let Some(max) = config.max_bytes_per_op {
+            limit_overrides.extend(quote! { limits.max_bytes_per_op = #max; });
+        }
+        if let Some(max) = config.max_ops_per_transaction {
+            limit_overrides.extend(quote! { limits.max_ops_per_transaction = #max; });
+        }
+        if let Some(max) = config.max_bytes_per_transaction {
+            limit_overrides.extend(quote! { limits.max_bytes_per_transaction = #max; });
+        }
+
+        i2c_initializations.extend(quote! {
+            let mut config = embassy_rp::i2c::Config::default();
+            config.frequency = #freq;
+            let bus = embassy_rp::i2c::I2c::new_blocking($p.#block_ident, $p.#scl_pin, $p.#sda_pin, config);
+
+            #[allow(unused_mut)]
+            let mut limits = i2c::I2cLimits::default();
+            #limit_overrides
+
+            i2c_hardware.push(i2c::I2cHardware {
+                name: alloc::string::String::from(#name),
+                addresses: alloc::vec![#(#addresses),*],
+                limits,
+                bus: alloc::boxed::Box::new(bus),
+            });
+        });
+    }
+
+    // UART
+    let mut uart_initializations = quote! {
+        #[allow(unused_mut)]
+        let mut uart_hardware: alloc::vec::Vec<uart::UartHardware> = alloc::vec::Vec::new();
+    };
+
+    for (name, config) in &policy.uart {
+        let block_ident = format_ident!("{}", config.block);
+        let tx_pin = format_ident!("PIN_{}", config.tx);
+        let rx_pin = format_ident!("PIN_{}", config.rx);
+        let baud = config.baud_rate;
+
+        let limit_override = match config.max_bytes_per_op {
+            Some(max) => quote! { limits.max_bytes_per_op = #max; },
+            None => quote! {},
         };
 
-        spi_initializations.extend(quote! {
-            let mut rp_config = embassy_rp::spi::Config::default();
-            rp_config.frequency = #freq;
-            rp_config.polarity = #pol;
-            rp_config.phase = #pha;
+        uart_initializations.extend(quote! {
+            let mut config = embassy_rp::uart::Config::default();
+            config.baudrate = #baud;
+            let port = embassy_rp::uart::BufferedUart::new(
+                $p.#block_ident,
+                $p.#tx_pin,
+                $p.#rx_pin,
+                uart_port::Irqs,
+                uart_port::ring_buffer(),
+                uart_port::ring_buffer(),
+                config,
+            );
 
-            let #spi_ident = embassy_rp::spi::Spi::new_blocking(
-                $p.#block_ident, $p.#sck_pin, $p.#mosi_pin, $p.#miso_pin, rp_config
+            #[allow(unused_mut)]
+            let mut limits = uart::UartLimits::default();
+            #limit_override
+
+            uart_hardware.push(uart::UartHardware {
+                name: alloc::string::String::from(#name),
+                limits,
+                port: alloc::boxed::Box::new(uart_port::BufferedPort(port)),
+            });
+        });
+    }
+
+    // PWM: GPIO n drives output A (even n) or B (odd n) of slice (n / 2) % 8
+    let mut pwm_initializations = quote! {
+        #[allow(unused_mut)]
+        let mut pwm_hardware: alloc::vec::Vec<pwm::PwmHardware> = alloc::vec::Vec::new();
+    };
+    for (name, config) in &policy.pwm {
+        let slice_ident = format_ident!("PWM_SLICE{}", config.slice());
+        let pin_ident = format_ident!("PIN_{}", config.pin);
+        let (constructor, output) = match config.pin % 2 {
+            0 => (quote!(new_output_a), quote!(pwm_slice::Output::A)),
+            _ => (quote!(new_output_b), quote!(pwm_slice::Output::B)),
+        };
+
+        let freq = config.frequency;
+        let (min_freq, max_freq) = config.frequency_range();
+        let (min_duty, max_duty) = config.duty_range();
+
+        pwm_initializations.extend(quote! {
+            let slice = embassy_rp::pwm::Pwm::#constructor(
+                $p.#slice_ident, $p.#pin_ident, embassy_rp::pwm::Config::default()
+            );
+            pwm_hardware.push(pwm::PwmHardware {
+                name: alloc::string::String::from(#name),
+                bounds: pwm::PwmBounds {
+                    min_frequency: #min_freq,
+                    max_frequency: #max_freq,
+                    min_duty_percent: #min_duty,
+                    max_duty_percent: #max_duty,
+                },
+                channel: alloc::boxed::Box::new(pwm_slice::PwmSlice::new(slice, #output, #freq)),
+            });
+        });
+    }
+
+    // ADC: one converter shared by all channels
+    let mut adc_initializations = quote! {
+        #[allow(unused_mut)]
+        let mut adc_hardware: alloc::vec::Vec<adc::AdcHardware> = alloc::vec::Vec::new();
+    };
+    if !policy.adc.is_empty() {
+        adc_initializations.extend(quote! {
+            let shared_adc = adc_input::share_adc(
+                embassy_rp::adc::Adc::new_blocking($p.ADC, embassy_rp::adc::Config::default())
             );
-            let #cs_ident = embassy_rp::gpio::Output::new($p.#cs_pin, embassy_rp::gpio::Level::High);
-            
-            let device = embedded_hal_bus::spi::ExclusiveDevice::new_no_delay(#spi_ident, #cs_ident).unwrap();
-            spi_hardware.push((alloc::string::String::from(#name), alloc::boxed::Box::new(device)));
+        });
+    }
+
+    for (name, config) in &policy.adc {
+        let pin_ident = format_ident!("PIN_{}", config.pin);
+        // The Pico 2 feeds ADC_VREF from its 3.3 V rail unless the board says otherwise
+        let reference_mv = config.reference_mv.unwrap_or(3300);
+
+        let limit_override = match config.max_samples_per_burst {
+            Some(max) => quote! { limits.max_samples_per_burst = #max; },
+            None => quote! {},
+        };
+
+        adc_initializations.extend(quote! {
+            let channel = embassy_rp::adc::Channel::new_pin($p.#pin_ident, embassy_rp::gpio::Pull::None);
+
+            #[allow(unused_mut)]
+            let mut limits = adc::AdcLimits::default();
+            #limit_override
+
+            adc_hardware.push(adc::AdcHardware {
+                name: alloc::string::String::from(#name),
+                reference_mv: #reference_mv,
+                resolution_bits: 12,
+                limits,
+                channel: alloc::boxed::Box::new(adc_input::AdcInput::new(shared_adc, channel)),
+            });
         });
     }
 
     // GPIO
-    let mut gpio_inserts = quote! { 
-        let mut gpio_map: alloc::collections::BTreeMap<alloc::string::String, alloc::boxed::Box<dyn gpio::ErasedOutputPin + Send>> = alloc::collections::BTreeMap::new(); 
+    let mut gpio_inserts = quote! {
+        let mut gpio_map: alloc::collections::BTreeMap<alloc::string::String, alloc::boxed::Box<dyn gpio::ErasedOutputPin + Send>> = alloc::collections::BTreeMap::new();
     };
-    
-    for (name, config) in policy.gpio.into_iter().flatten() {
+
+    for (name, config) in &policy.gpio {
         let pin_ident = format_ident!("PIN_{}", config.pin);
-        let level = match config.initial.as_str() {
-            "High" => quote! { embassy_rp::gpio::Level::High },
-            "Low" => quote! { embassy_rp::gpio::Level::Low },
-            _ => panic!("Invalid GPIO level"),
+        let level = match config.initial {
+            policy::Level::High => quote! { embassy_rp::gpio::Level::High },
+            policy::Level::Low => quote! { embassy_rp::gpio::Level::Low },
         };
-        gpio_inserts.extend(quote! { 
+        gpio_inserts.extend(quote! {
             gpio_map.insert(
-                alloc::string::String::from(#name), 
+                alloc::string::String::from(#name),
                 alloc::boxed::Box::new(embassy_rp::gpio::Output::new($p.#pin_ident, #level))
-            ); 
+            );
+        });
+    }
+
+    let mut input_inserts = quote! {
+        #[allow(unused_mut)]
+        let mut input_map: alloc::collections::BTreeMap<alloc::string::String, alloc::boxed::Box<dyn gpio::ErasedEdgePin + Send>> = alloc::collections::BTreeMap::new();
+    };
+
+    for (name, config) in &policy.gpio_input {
+        let pin_ident = format_ident!("PIN_{}", config.pin);
+        let pull = match config.pull {
+            policy::Pull::Up => quote! { embassy_rp::gpio::Pull::Up },
+            policy::Pull::Down => quote! { embassy_rp::gpio::Pull::Down },
+            policy::Pull::None => quote! { embassy_rp::gpio::Pull::None },
+        };
+        let input =
+            quote! { gpio_input::EdgeInput(embassy_rp::gpio::Input::new($p.#pin_ident, #pull)) };
+        let input = match config.debounce_ms {
+            Some(ms) => {
+                let period_us = ms
+                    .checked_mul(1000)
+                    .expect("CRITICAL: debounce_ms is too long");
+                quote! { gpio::Debounced::new(#input, embassy_time::Delay, #period_us) }
+            }
+            None => input,
+        };
+        input_inserts.extend(quote! {
+            input_map.insert(
+                alloc::string::String::from(#name),
+                alloc::boxed::Box::new(#input)
+            );
         });
     }
 
@@ -116,8 +368,13 @@ fn main() {
         macro_rules! configure_hardware {
             ($p:expr) => {{
                 #spi_initializations
+                #i2c_initializations
+                #uart_initializations
+                #pwm_initializations
+                #adc_initializations
                 #gpio_inserts
-                (spi_hardware, gpio_map) // Cleanly return just the two hardware collections
+                #input_inserts
+                (spi_hardware, i2c_hardware, uart_hardware, pwm_hardware, adc_hardware, gpio_map, input_map) // Cleanly return just the hardware collections
             }}
         }
     };
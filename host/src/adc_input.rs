@@ -0,0 +1,32 @@
+//! Analog inputs built by `configure_hardware!`. The RP2350 has a single ADC
+//! multiplexed over its input pins, so every channel borrows it per conversion.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use embassy_rp::adc::{Adc, Blocking, Channel};
+
+type SharedAdc = Mutex<RefCell<Adc<'static, Blocking>>>;
+
+/// Leaks the converter so channels built from it can live as long as the firmware.
+pub fn share_adc(adc: Adc<'static, Blocking>) -> &'static SharedAdc {
+    alloc::boxed::Box::leak(alloc::boxed::Box::new(Mutex::new(RefCell::new(adc))))
+}
+
+pub struct AdcInput {
+    adc: &'static SharedAdc,
+    channel: Channel<'static>,
+}
+
+impl AdcInput {
+    pub fn new(adc: &'static SharedAdc, channel: Channel<'static>) -> Self {
+        Self { adc, channel }
+    }
+}
+
+impl adc::ErasedAdc for AdcInput {
+    fn read(&mut self) -> Result<u16, adc::Error> {
+        critical_section::with(|cs| self.adc.borrow_ref_mut(cs).blocking_read(&mut self.channel))
+            .map_err(|e| adc::Error::Other(alloc::format!("{:?}", e)))
+    }
+}
@@ -0,0 +1,58 @@
+//! Input pins built by `configure_hardware!`. Edge waits sample the pin on the
+//! host side instead of polling it across the Wasm boundary.
+//!
+//! Host calls run outside the executor, so an edge wait polls the pin's
+//! `wait_for_*_edge` future with a no-op waker until it completes or an
+//! `Instant` deadline passes. The GPIO interrupt latches the edge, so pulses
+//! shorter than a poll are still seen; no embassy `Timer` is involved, which
+//! would panic in the integrated timer queue when polled this way.
+
+use core::convert::Infallible;
+use core::pin::pin;
+use core::task::{Context, Waker};
+
+use embassy_rp::gpio::Input;
+use embassy_time::{Duration, Instant};
+use embedded_hal::digital::{ErrorType, InputPin};
+use gpio::{Edge, Error};
+
+pub struct EdgeInput(pub Input<'static>);
+
+impl ErrorType for EdgeInput {
+    type Error = Infallible;
+}
+
+impl InputPin for EdgeInput {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.0.is_high())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.0.is_low())
+    }
+}
+
+impl gpio::ErasedEdgePin for EdgeInput {
+    fn wait_for_edge(&mut self, edge: Edge, timeout_ms: u32) -> Result<bool, Error> {
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.into());
+        Ok(match edge {
+            Edge::Rising => poll_until(self.0.wait_for_rising_edge(), deadline),
+            Edge::Falling => poll_until(self.0.wait_for_falling_edge(), deadline),
+            Edge::Any => poll_until(self.0.wait_for_any_edge(), deadline),
+        })
+    }
+}
+
+/// Polls `wait` until it completes, or returns false once `deadline` passes.
+fn poll_until(wait: impl Future<Output = ()>, deadline: Instant) -> bool {
+    let mut wait = pin!(wait);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if wait.as_mut().poll(&mut cx).is_ready() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+    }
+}
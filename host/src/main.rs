@@ -7,13 +7,24 @@ use defmt::info;
 use embassy_executor::Spawner;
 use embassy_time::Delay;
 use embedded_alloc::Heap;
-use wasmtime::component::{Component, HasSelf, Linker, ResourceTable};
-use wasmtime::{Config, Engine, Store};
+use wasmtime::component::Component;
+use wasmtime::{Config, Engine};
 use {defmt_rtt as _, panic_probe as _};
 
-use delay::{DelayCtx, DelayView};
-use gpio::{GpioCtx, GpioView};
-use spi::{SpiCtx, SpiView};
+use gpio::GpioCtx;
+use runtime::EmbeddedHost;
+
+// Only referenced from `configure_hardware!`, and only for the peripherals the policy uses
+#[allow(dead_code)]
+mod adc_input;
+#[allow(dead_code)]
+mod gpio_input;
+#[allow(dead_code)]
+mod pwm_slice;
+#[allow(dead_code)]
+mod spi_bus;
+#[allow(dead_code)]
+mod uart_port;
 
 wasmtime::component::bindgen!({
     path: "../guest-physics/wit",
@@ -28,42 +39,6 @@ static HEAP: Heap = Heap::empty();
 // Macro generated by build.rs
 include!(concat!(env!("OUT_DIR"), "/hardware_policy.rs"));
 
-pub struct HostState {
-    pub spi_ctx: SpiCtx,
-    pub gpio_ctx: GpioCtx,
-    pub delay_ctx: DelayCtx,
-}
-
-impl my::debug::logging::Host for HostState {
-    fn log(&mut self, msg: alloc::string::String) {
-        defmt::info!("[Guest] {}", msg.as_str());
-    }
-}
-
-impl my::clock::time::Host for HostState {
-    fn now_ms(&mut self) -> u64 {
-        embassy_time::Instant::now().as_millis()
-    }
-}
-
-impl SpiView for HostState {
-    fn spi_ctx(&mut self) -> &mut SpiCtx {
-        &mut self.spi_ctx
-    }
-}
-
-impl GpioView for HostState {
-    fn gpio_ctx(&mut self) -> &mut GpioCtx {
-        &mut self.gpio_ctx
-    }
-}
-
-impl DelayView for HostState {
-    fn delay_ctx(&mut self) -> &mut DelayCtx {
-        &mut self.delay_ctx
-    }
-}
-
 // --- Wasmtime TLS Hooks ---
 static mut TLS_PTR: *mut u8 = core::ptr::null_mut();
 #[unsafe(no_mangle)]
@@ -104,29 +79,37 @@ async fn main(_spawner: Spawner) {
     // Dynamically expand hardware pins based on policy.toml
     // ====================================================
 
-    let (spi_hardware, gpio_map) = configure_hardware!(p);
-
-    let host_state = HostState {
-        spi_ctx: SpiCtx {
-            table: ResourceTable::new(),
-            hardware: spi_hardware,
-        },
-        gpio_ctx: GpioCtx { pins: gpio_map },
-        delay_ctx: DelayCtx {
-            delay: alloc::boxed::Box::new(Delay),
-        },
-    };
-
-    let mut store = Store::new(&engine, host_state);
-    let mut linker = Linker::new(&engine);
-
-    spi::add_to_linker(&mut linker).unwrap();
-    gpio::add_to_linker(&mut linker).unwrap();
-    delay::add_to_linker(&mut linker).unwrap();
-    my::debug::logging::add_to_linker::<HostState, HasSelf<HostState>>(&mut linker, |state| state)
-        .unwrap();
-    my::clock::time::add_to_linker::<HostState, HasSelf<HostState>>(&mut linker, |state| state)
-        .unwrap();
+    let (
+        spi_hardware,
+        i2c_hardware,
+        uart_hardware,
+        pwm_hardware,
+        adc_hardware,
+        gpio_map,
+        input_map,
+    ) = configure_hardware!(p);
+
+    let host = EmbeddedHost::builder()
+        .spi(spi_hardware)
+        .i2c(i2c_hardware)
+        .uart(uart_hardware)
+        .pwm(pwm_hardware)
+        .adc(adc_hardware)
+        .gpio(
+            GpioCtx::new(gpio_map, input_map).on_first_denial(|label, err| {
+                defmt::warn!(
+                    "GPIO request for '{}' denied: {}",
+                    label,
+                    defmt::Debug2Format(err)
+                )
+            }),
+        )
+        .delay(Delay)
+        .logger(|msg| defmt::info!("[Guest] {}", msg))
+        .clock(|| embassy_time::Instant::now().as_micros())
+        .build();
+
+    let linker = EmbeddedHost::linker(&engine).unwrap();
 
     let guest_bytes = include_bytes!("guest.pulley");
     info!(
@@ -137,7 +120,11 @@ async fn main(_spawner: Spawner) {
     let component = unsafe { Component::deserialize(&engine, guest_bytes) }.unwrap();
 
     info!("Instantiating...");
-    let app = App::instantiate(&mut store, &component, &linker).unwrap();
+    let (mut store, app) = host
+        .instantiate(&linker, &component, |store, component, linker| {
+            App::instantiate(store, component, linker)
+        })
+        .unwrap();
 
     info!("Starting guest...");
     app.call_run(&mut store).unwrap();
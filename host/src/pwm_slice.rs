@@ -0,0 +1,90 @@
+//! PWM channels built by `configure_hardware!`. Each channel owns a whole
+//! RP2350 PWM slice, so changing its frequency cannot disturb another channel.
+
+use core::convert::Infallible;
+
+use embassy_rp::pwm::{Config, Pwm};
+use embedded_hal::pwm::{ErrorType, SetDutyCycle};
+
+/// Which of the slice's two outputs the pin is wired to.
+#[derive(Clone, Copy)]
+pub enum Output {
+    A,
+    B,
+}
+
+pub struct PwmSlice {
+    pwm: Pwm<'static>,
+    config: Config,
+    output: Output,
+}
+
+impl PwmSlice {
+    /// Starts the slice disabled at `frequency` with a 0% duty cycle.
+    pub fn new(pwm: Pwm<'static>, output: Output, frequency: u32) -> Self {
+        let mut slice = Self {
+            pwm,
+            config: Config::default(),
+            output,
+        };
+        slice.config.enable = false;
+        slice.config.compare_a = 0;
+        slice.config.compare_b = 0;
+        slice.apply_frequency(frequency);
+        slice
+    }
+
+    fn compare(&mut self) -> &mut u16 {
+        match self.output {
+            Output::A => &mut self.config.compare_a,
+            Output::B => &mut self.config.compare_b,
+        }
+    }
+
+    /// Picks the smallest integer divider that lets the 16-bit counter span one
+    /// period, rescales the compare value and returns the resulting frequency.
+    fn apply_frequency(&mut self, hz: u32) -> u32 {
+        let clk = u64::from(embassy_rp::clocks::clk_sys_freq());
+        let counts = clk / u64::from(hz.max(1));
+        let divider = counts.div_ceil(1 << 16).clamp(1, 255);
+        let top = (counts / divider).clamp(2, 1 << 16) - 1;
+
+        let old_top = u64::from(self.config.top);
+        let compare = u64::from(*self.compare()) * (top + 1) / (old_top + 1);
+
+        self.config.divider = (divider as u8).into();
+        self.config.top = top as u16;
+        *self.compare() = compare.min(top) as u16;
+        self.pwm.set_config(&self.config);
+
+        (clk / (divider * (top + 1))) as u32
+    }
+}
+
+impl ErrorType for PwmSlice {
+    type Error = Infallible;
+}
+
+impl SetDutyCycle for PwmSlice {
+    fn max_duty_cycle(&self) -> u16 {
+        self.config.top
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        *self.compare() = duty;
+        self.pwm.set_config(&self.config);
+        Ok(())
+    }
+}
+
+impl pwm::PwmChannel for PwmSlice {
+    fn set_frequency(&mut self, hz: u32) -> Result<u32, pwm::Error> {
+        Ok(self.apply_frequency(hz))
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), pwm::Error> {
+        self.config.enable = enabled;
+        self.pwm.set_config(&self.config);
+        Ok(())
+    }
+}
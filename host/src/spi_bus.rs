@@ -0,0 +1,86 @@
+//! Glue between the SPI devices built by `configure_hardware!` and the
+//! embassy drivers, including blocks shared between several chip-selects.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use embassy_rp::gpio::Output;
+use embassy_rp::spi::{Blocking, Config, Instance, Phase, Polarity, Spi};
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+use embedded_hal_bus::spi::{CriticalSectionDevice, ExclusiveDevice, NoDelay};
+
+type Bus<T> = Spi<'static, T, Blocking>;
+type SharedBus<T> = Mutex<RefCell<Bus<T>>>;
+
+/// Translates a bus configuration into the embassy settings for an RP2350 SPI block.
+pub fn rp_config(config: &spi::BusConfig) -> Result<Config, spi::Error> {
+    // The RP2350 SPI blocks only shift out MSB first
+    if config.bit_order != spi::BitOrder::MsbFirst {
+        return Err(spi::Error::UnsupportedConfig);
+    }
+
+    let (polarity, phase) = match config.mode {
+        spi::Mode::Mode0 => (Polarity::IdleLow, Phase::CaptureOnFirstTransition),
+        spi::Mode::Mode1 => (Polarity::IdleLow, Phase::CaptureOnSecondTransition),
+        spi::Mode::Mode2 => (Polarity::IdleHigh, Phase::CaptureOnFirstTransition),
+        spi::Mode::Mode3 => (Polarity::IdleHigh, Phase::CaptureOnSecondTransition),
+    };
+
+    let mut rp_config = Config::default();
+    rp_config.frequency = config.frequency;
+    rp_config.polarity = polarity;
+    rp_config.phase = phase;
+    Ok(rp_config)
+}
+
+/// Applies a guest-requested configuration to a device that has its block to itself.
+pub fn apply_spi_config<T: Instance, CS>(
+    dev: &mut ExclusiveDevice<Bus<T>, CS, NoDelay>,
+    config: &spi::BusConfig,
+) -> Result<(), spi::Error> {
+    dev.bus_mut().set_config(&rp_config(config)?);
+    Ok(())
+}
+
+/// Moves a block into a mutex that lives for the rest of the program, so several devices can borrow it.
+pub fn share_bus<T: Instance + 'static>(bus: Bus<T>) -> &'static SharedBus<T> {
+    alloc::boxed::Box::leak(alloc::boxed::Box::new(Mutex::new(RefCell::new(bus))))
+}
+
+/// One chip-select on a shared block. Its neighbours may run at another
+/// frequency or mode, so the block is switched to this device's settings
+/// before every transaction.
+pub struct SharedSpiDevice<T: Instance + 'static> {
+    bus: &'static SharedBus<T>,
+    device: CriticalSectionDevice<'static, Bus<T>, Output<'static>, NoDelay>,
+    config: Config,
+}
+
+impl<T: Instance + 'static> SharedSpiDevice<T> {
+    pub fn new(bus: &'static SharedBus<T>, cs: Output<'static>, config: Config) -> Self {
+        Self {
+            bus,
+            device: CriticalSectionDevice::new_no_delay(bus, cs).unwrap(),
+            config,
+        }
+    }
+}
+
+impl<T: Instance + 'static> ErrorType for SharedSpiDevice<T> {
+    type Error =
+        <CriticalSectionDevice<'static, Bus<T>, Output<'static>, NoDelay> as ErrorType>::Error;
+}
+
+impl<T: Instance + 'static> SpiDevice for SharedSpiDevice<T> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        critical_section::with(|cs| self.bus.borrow_ref_mut(cs).set_config(&self.config));
+        self.device.transaction(operations)
+    }
+}
+
+impl<T: Instance + 'static> spi::ConfigurableSpiDevice for SharedSpiDevice<T> {
+    fn configure(&mut self, config: &spi::BusConfig) -> Result<(), spi::Error> {
+        self.config = rp_config(config)?;
+        Ok(())
+    }
+}
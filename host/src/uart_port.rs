@@ -0,0 +1,63 @@
+//! UART ports built by `configure_hardware!`. Received bytes are buffered by
+//! the UART interrupt, so a guest read only waits while the buffer is empty.
+//!
+//! Host calls run outside the executor, so the port is driven through the
+//! blocking `embedded-io` traits and read timeouts spin against an `Instant`
+//! deadline, like the edge waits in `gpio_input`.
+
+use embassy_rp::bind_interrupts;
+use embassy_rp::peripherals::{UART0, UART1};
+use embassy_rp::uart::{self as rp_uart, BufferedInterruptHandler};
+use embassy_time::{Duration, Instant};
+use embedded_io::{Read, ReadReady, Write};
+use uart::Error;
+
+bind_interrupts!(pub struct Irqs {
+    UART0_IRQ => BufferedInterruptHandler<UART0>;
+    UART1_IRQ => BufferedInterruptHandler<UART1>;
+});
+
+/// Size of the transmit and receive ring buffers of each port.
+const BUFFER_SIZE: usize = 256;
+
+/// A ring buffer for a port that lives as long as the firmware.
+pub fn ring_buffer() -> &'static mut [u8] {
+    alloc::vec![0u8; BUFFER_SIZE].leak()
+}
+
+fn map_uart_error(err: rp_uart::Error) -> Error {
+    match err {
+        rp_uart::Error::Overrun => Error::Overrun,
+        rp_uart::Error::Parity => Error::Parity,
+        rp_uart::Error::Framing => Error::FrameFormat,
+        err => Error::Other(alloc::format!("{:?}", err)),
+    }
+}
+
+pub struct BufferedPort<U>(pub U);
+
+impl<U> uart::ErasedUart for BufferedPort<U>
+where
+    U: Read<Error = rp_uart::Error> + ReadReady + Write<Error = rp_uart::Error>,
+{
+    fn read(&mut self, buf: &mut [u8], timeout_ms: u32) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.into());
+        while !self.0.read_ready().map_err(map_uart_error)? {
+            if Instant::now() >= deadline {
+                return Ok(0);
+            }
+        }
+        self.0.read(buf).map_err(map_uart_error)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.0.write_all(data).map_err(map_uart_error)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.0.flush().map_err(map_uart_error)
+    }
+}
@@ -0,0 +1,184 @@
+//! Generates a typed `Board` for a guest from a `policy.toml` or a manifest file,
+//! so every label becomes a struct field and a typo is a compile error. Call it
+//! from the guest's build script:
+//!
+//! ```ignore
+//! fn main() {
+//!     wasi_embedded_hal::codegen::from_manifest("manifest.toml");
+//! }
+//! ```
+//!
+//! and include the result at the guest's crate root:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/board.rs"));
+//!
+//! let board = Board::open().expect("Failed to open board");
+//! let spi = board.screen; // WasiSpiDevice
+//! ```
+//!
+//! The generated file also embeds the labels with [`manifest!`](crate::manifest),
+//! so hosts check the guest against their policy before running it.
+
+use policy::manifest::Manifest;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// Generates `$OUT_DIR/board.rs` with one field per label the policy grants.
+pub fn from_policy(path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    let manifest = Manifest::from_policy(&read(path))
+        .unwrap_or_else(|err| panic!("{} is invalid: {}", path.display(), err));
+    write(path, &manifest);
+}
+
+/// Generates `$OUT_DIR/board.rs` from a manifest file, a TOML table of label
+/// lists such as `spi = ["screen"]` and `gpio = ["RES", "DC"]`.
+pub fn from_manifest(path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    let manifest = Manifest::from_toml(&read(path))
+        .unwrap_or_else(|err| panic!("{} is invalid: {}", path.display(), err));
+    write(path, &manifest);
+}
+
+fn read(path: &Path) -> String {
+    println!("cargo:rerun-if-changed={}", path.display());
+    fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("failed to read {}: {}", path.display(), err))
+}
+
+fn write(source: &Path, manifest: &Manifest) {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is set for build scripts"));
+    let code = render(manifest, &source.display().to_string());
+    fs::write(out_dir.join("board.rs"), code).expect("failed to write board.rs");
+}
+
+/// The generated source for `manifest`; `source` is only named in comments.
+pub fn render(manifest: &Manifest, source: &str) -> String {
+    let sections = [
+        ("spi", "WasiSpiDevice", "Spi", &manifest.spi),
+        ("i2c", "WasiI2c", "I2c", &manifest.i2c),
+        ("uart", "WasiUart", "Uart", &manifest.uart),
+        ("pwm", "WasiPwm", "Pwm", &manifest.pwm),
+        ("adc", "WasiAdc", "Adc", &manifest.adc),
+        ("gpio", "WasiOutputPin", "Gpio", &manifest.gpio),
+        ("gpio_input", "WasiInputPin", "Gpio", &manifest.gpio_input),
+    ];
+
+    let mut fields = String::new();
+    let mut opens = String::new();
+    let mut labels = String::new();
+    let mut names: BTreeMap<String, String> = BTreeMap::new();
+
+    for (section, ty, variant, entries) in sections {
+        if !entries.is_empty() {
+            let quoted: Vec<_> = entries.iter().map(|label| format!("{:?}", label)).collect();
+            writeln!(labels, "    {}: [{}],", section, quoted.join(", ")).unwrap();
+        }
+        for label in entries {
+            let field = field_name(label);
+            let key = format!("{}.{}", section, label);
+            if let Some(first) = names.insert(field.clone(), key.clone()) {
+                panic!(
+                    "`{}` and `{}` both become the Board field `{}`; rename one of them",
+                    first, key, field
+                );
+            }
+            writeln!(fields, "    /// `{}`", key).unwrap();
+            writeln!(fields, "    pub {}: ::wasi_embedded_hal::{},", field, ty).unwrap();
+            writeln!(
+                opens,
+                "            {}: ::wasi_embedded_hal::{}::open({:?})\n                .map_err(|err| ::wasi_embedded_hal::BoardError::{}({:?}, err))?,",
+                field, ty, label, variant, label
+            )
+            .unwrap();
+        }
+    }
+
+    format!(
+        "// Generated by wasi_embedded_hal::codegen from {source}; do not edit.
+
+/// The hardware granted to this guest, one field per label.
+pub struct Board {{
+{fields}}}
+
+impl Board {{
+    /// Opens every device, stopping at the first one the host refuses.
+    pub fn open() -> ::core::result::Result<Self, ::wasi_embedded_hal::BoardError> {{
+        Ok(Self {{
+{opens}        }})
+    }}
+}}
+
+::wasi_embedded_hal::manifest! {{
+{labels}}}
+"
+    )
+}
+
+/// `VBATC` becomes `vbatc` and `status-led` becomes `status_led`. Keywords,
+/// reserved words and a lone `_` get a trailing underscore.
+fn field_name(label: &str) -> String {
+    let mut name: String = label
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    if name == "_" || KEYWORDS.contains(&name.as_str()) {
+        name.push('_');
+    }
+    name
+}
+
+/// Strict and reserved keywords of the 2024 edition.
+const KEYWORDS: [&str; 51] = [
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_become_valid_field_names() {
+        let cases = [
+            ("VBATC", "vbatc"),
+            ("status-led", "status_led"),
+            ("1wire", "_1wire"),
+            ("", "__"),
+            ("-", "__"),
+            ("type", "type_"),
+            ("try", "try_"),
+            ("Box", "box_"),
+            ("yield", "yield_"),
+        ];
+        for (label, field) in cases {
+            assert_eq!(field_name(label), field, "label {:?}", label);
+        }
+    }
+
+    #[test]
+    fn labels_that_collide_are_rejected() {
+        let manifest = Manifest {
+            gpio: ["status-led".to_string()].into(),
+            gpio_input: ["STATUS_LED".to_string()].into(),
+            ..Manifest::default()
+        };
+        let render = std::panic::catch_unwind(|| render(&manifest, "manifest.toml"));
+        assert!(render.is_err());
+    }
+}
@@ -3,6 +3,10 @@ wit_bindgen::generate!({
         package guest:hal;
         world hal {
             import wasi:spi/spi;
+            import wasi:i2c/i2c;
+            import wasi:uart/uart;
+            import wasi:pwm/pwm;
+            import wasi:adc/adc;
             import wasi:gpio/gpio;
             import wasi:delay/delay;
         }
@@ -11,11 +15,25 @@ wit_bindgen::generate!({
     generate_all
 });
 
+#[cfg(feature = "codegen")]
+pub mod codegen;
+
 use embedded_hal::{
     delay::DelayNs,
-    digital::{ErrorType as DigitalErrorType, OutputPin},
+    digital::{
+        Error as DigitalErrorTrait, ErrorKind as DigitalErrorKind, ErrorType as DigitalErrorType,
+        InputPin, OutputPin,
+    },
+    i2c::{
+        Error as I2cErrorTrait, ErrorKind as I2cErrorKind, ErrorType as I2cErrorType, I2c,
+        NoAcknowledgeSource, Operation as I2cOperation, SevenBitAddress,
+    },
     spi::{Error as SpiErrorTrait, ErrorKind, ErrorType as SpiErrorType, Operation, SpiDevice},
 };
+use embedded_hal_async::digital::Wait;
+pub use wasi::gpio::gpio::Edge;
+use wasi::i2c::i2c::{Operation as WasiI2cOp, OperationResult as WasiI2cOpResult};
+pub use wasi::spi::spi::{BitOrder, BusConfig, Mode};
 use wasi::spi::spi::{Operation as WasiOp, OperationResult as WasiOpResult};
 
 // ==========================================
@@ -26,13 +44,11 @@ pub struct WasiDelay;
 
 impl DelayNs for WasiDelay {
     fn delay_ns(&mut self, ns: u32) {
-        let ms = (ns + 999_999) / 1_000_000;
-        wasi::delay::delay::delay_ms(ms);
+        wasi::delay::delay::delay_ns(ns);
     }
 
     fn delay_us(&mut self, us: u32) {
-        let ms = (us + 999) / 1_000;
-        wasi::delay::delay::delay_ms(ms);
+        wasi::delay::delay::delay_us(us);
     }
 
     fn delay_ms(&mut self, ms: u32) {
@@ -45,31 +61,130 @@ impl DelayNs for WasiDelay {
 // ==========================================
 
 pub struct WasiOutputPin {
-    label: String,
+    inner: wasi::gpio::gpio::Pin,
 }
 
 impl WasiOutputPin {
-    pub fn new(label: impl Into<String>) -> Self {
-        Self {
-            label: label.into(),
-        }
+    /// Create a wrapper from an already opened host pin
+    pub fn new(inner: wasi::gpio::gpio::Pin) -> Self {
+        Self { inner }
+    }
+
+    /// Open an output pin by label via the WASI host import
+    pub fn open(label: &str) -> Result<Self, WasiGpioError> {
+        let inner = wasi::gpio::gpio::open_pin(label).map_err(WasiGpioError)?;
+        Ok(Self { inner })
     }
 }
 
 impl DigitalErrorType for WasiOutputPin {
-    type Error = core::convert::Infallible;
+    type Error = WasiGpioError;
 }
 
 impl OutputPin for WasiOutputPin {
     fn set_low(&mut self) -> Result<(), Self::Error> {
-        wasi::gpio::gpio::set_pin_state(&self.label, wasi::gpio::gpio::Level::Low);
-        Ok(())
+        self.inner
+            .set_state(wasi::gpio::gpio::Level::Low)
+            .map_err(WasiGpioError)
     }
 
     fn set_high(&mut self) -> Result<(), Self::Error> {
-        wasi::gpio::gpio::set_pin_state(&self.label, wasi::gpio::gpio::Level::High);
+        self.inner
+            .set_state(wasi::gpio::gpio::Level::High)
+            .map_err(WasiGpioError)
+    }
+}
+
+pub struct WasiInputPin {
+    inner: wasi::gpio::gpio::Pin,
+}
+
+impl WasiInputPin {
+    /// Create a wrapper from an already opened host pin
+    pub fn new(inner: wasi::gpio::gpio::Pin) -> Self {
+        Self { inner }
+    }
+
+    /// Open an input pin by label via the WASI host import
+    pub fn open(label: &str) -> Result<Self, WasiGpioError> {
+        let inner = wasi::gpio::gpio::open_pin(label).map_err(WasiGpioError)?;
+        Ok(Self { inner })
+    }
+
+    /// Block until the edge is seen, returning false if `timeout_ms` elapsed first
+    pub fn wait_for_edge(&mut self, edge: Edge, timeout_ms: u32) -> Result<bool, WasiGpioError> {
+        self.inner
+            .wait_for_edge(edge, timeout_ms)
+            .map_err(WasiGpioError)
+    }
+
+    fn wait_forever(&mut self, edge: Edge) -> Result<(), WasiGpioError> {
+        while !self.wait_for_edge(edge, u32::MAX)? {}
         Ok(())
     }
+
+    fn level(&self) -> Result<wasi::gpio::gpio::Level, WasiGpioError> {
+        self.inner.get_state().map_err(WasiGpioError)
+    }
+}
+
+impl DigitalErrorType for WasiInputPin {
+    type Error = WasiGpioError;
+}
+
+impl InputPin for WasiInputPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.level()? == wasi::gpio::gpio::Level::High)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.level()? == wasi::gpio::gpio::Level::Low)
+    }
+}
+
+// The host blocks inside each call, so these futures complete on their first poll
+impl Wait for WasiInputPin {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        if !InputPin::is_high(self)? {
+            self.wait_forever(Edge::Rising)?;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        if !InputPin::is_low(self)? {
+            self.wait_forever(Edge::Falling)?;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_forever(Edge::Rising)
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_forever(Edge::Falling)
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_forever(Edge::Any)
+    }
+}
+
+#[derive(Debug)]
+pub struct WasiGpioError(pub wasi::gpio::gpio::Error);
+
+// Unknown labels and policy denials have no embedded-hal equivalent
+impl DigitalErrorTrait for WasiGpioError {
+    fn kind(&self) -> DigitalErrorKind {
+        DigitalErrorKind::Other
+    }
+}
+
+impl core::fmt::Display for WasiGpioError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
 }
 
 // ==========================================
@@ -91,6 +206,11 @@ impl WasiSpiDevice {
         let inner = wasi::spi::spi::open(name).map_err(WasiSpiError)?;
         Ok(Self { inner })
     }
+
+    /// Change the bus settings, returning what the host actually applied
+    pub fn configure(&mut self, config: BusConfig) -> Result<BusConfig, WasiSpiError> {
+        self.inner.configure(config).map_err(WasiSpiError)
+    }
 }
 
 #[derive(Debug)]
@@ -104,7 +224,9 @@ impl SpiErrorTrait for WasiSpiError {
             wasi::spi::spi::Error::ModeFault => ErrorKind::ModeFault,
             wasi::spi::spi::Error::FrameFormat => ErrorKind::FrameFormat,
             wasi::spi::spi::Error::ChipSelectFault => ErrorKind::ChipSelectFault,
-            wasi::spi::spi::Error::Other(_) => ErrorKind::Other,
+            wasi::spi::spi::Error::LimitExceeded(_)
+            | wasi::spi::spi::Error::UnsupportedConfig
+            | wasi::spi::spi::Error::Other(_) => ErrorKind::Other,
         }
     }
 }
@@ -197,3 +319,418 @@ impl SpiDevice for WasiSpiDevice {
         Ok(())
     }
 }
+
+// ==========================================
+// I2C IMPLEMENTATION
+// ==========================================
+
+pub struct WasiI2c {
+    inner: wasi::i2c::i2c::I2cBus,
+}
+
+impl WasiI2c {
+    /// Create a wrapper from an already opened host I2C bus
+    pub fn new(inner: wasi::i2c::i2c::I2cBus) -> Self {
+        Self { inner }
+    }
+
+    /// Open an I2C bus by name via the WASI host import
+    pub fn open(name: &str) -> Result<Self, WasiI2cError> {
+        let inner = wasi::i2c::i2c::open(name).map_err(WasiI2cError)?;
+        Ok(Self { inner })
+    }
+}
+
+#[derive(Debug)]
+pub struct WasiI2cError(pub wasi::i2c::i2c::Error);
+
+// Map the WASI host I2C errors to embedded-hal ErrorKind traits
+impl I2cErrorTrait for WasiI2cError {
+    fn kind(&self) -> I2cErrorKind {
+        match &self.0 {
+            wasi::i2c::i2c::Error::Bus => I2cErrorKind::Bus,
+            wasi::i2c::i2c::Error::ArbitrationLoss => I2cErrorKind::ArbitrationLoss,
+            wasi::i2c::i2c::Error::NoAcknowledge(source) => {
+                I2cErrorKind::NoAcknowledge(match source {
+                    wasi::i2c::i2c::NoAcknowledgeSource::Address => NoAcknowledgeSource::Address,
+                    wasi::i2c::i2c::NoAcknowledgeSource::Data => NoAcknowledgeSource::Data,
+                    wasi::i2c::i2c::NoAcknowledgeSource::Unknown => NoAcknowledgeSource::Unknown,
+                })
+            }
+            wasi::i2c::i2c::Error::Overrun => I2cErrorKind::Overrun,
+            wasi::i2c::i2c::Error::AddressNotPermitted
+            | wasi::i2c::i2c::Error::LimitExceeded(_)
+            | wasi::i2c::i2c::Error::Other(_) => I2cErrorKind::Other,
+        }
+    }
+}
+
+impl core::fmt::Display for WasiI2cError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl I2cErrorType for WasiI2c {
+    type Error = WasiI2cError;
+}
+
+impl I2c<SevenBitAddress> for WasiI2c {
+    fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<(), Self::Error> {
+        let wasi_ops: Vec<_> = operations
+            .iter()
+            .map(|op| match op {
+                I2cOperation::Read(buf) => WasiI2cOp::Read(buf.len() as u64),
+                I2cOperation::Write(buf) => WasiI2cOp::Write(buf.to_vec()),
+            })
+            .collect();
+
+        let results = self
+            .inner
+            .transaction(address, &wasi_ops)
+            .map_err(WasiI2cError)?;
+
+        for (op, result) in operations.iter_mut().zip(results) {
+            if let (I2cOperation::Read(buf), WasiI2cOpResult::Read(data)) = (op, result) {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, address: SevenBitAddress, read: &mut [u8]) -> Result<(), Self::Error> {
+        let data = self
+            .inner
+            .read(address, read.len() as u64)
+            .map_err(WasiI2cError)?;
+        let len = data.len().min(read.len());
+        read[..len].copy_from_slice(&data[..len]);
+        Ok(())
+    }
+
+    fn write(&mut self, address: SevenBitAddress, write: &[u8]) -> Result<(), Self::Error> {
+        self.inner.write(address, write).map_err(WasiI2cError)
+    }
+
+    fn write_read(
+        &mut self,
+        address: SevenBitAddress,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let data = self
+            .inner
+            .write_read(address, write, read.len() as u64)
+            .map_err(WasiI2cError)?;
+        let len = data.len().min(read.len());
+        read[..len].copy_from_slice(&data[..len]);
+        Ok(())
+    }
+}
+
+// ==========================================
+// UART IMPLEMENTATION
+// ==========================================
+
+pub struct WasiUart {
+    inner: wasi::uart::uart::UartPort,
+    // Largest write the host has accepted after a limit-exceeded, so later
+    // writes go out as short writes instead of failing
+    max_write: usize,
+}
+
+impl WasiUart {
+    /// Create a wrapper from an already opened host UART port
+    pub fn new(inner: wasi::uart::uart::UartPort) -> Self {
+        Self {
+            inner,
+            max_write: usize::MAX,
+        }
+    }
+
+    /// Open a UART port by name via the WASI host import
+    pub fn open(name: &str) -> Result<Self, WasiUartError> {
+        let inner = wasi::uart::uart::open(name).map_err(WasiUartError)?;
+        Ok(Self::new(inner))
+    }
+
+    /// Read whatever arrives within `timeout_ms`, returning 0 if nothing did
+    pub fn read_timeout(
+        &mut self,
+        buf: &mut [u8],
+        timeout_ms: u32,
+    ) -> Result<usize, WasiUartError> {
+        let data = self
+            .inner
+            .read(buf.len() as u64, timeout_ms)
+            .map_err(WasiUartError)?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+}
+
+#[derive(Debug)]
+pub struct WasiUartError(pub wasi::uart::uart::Error);
+
+impl embedded_io::Error for WasiUartError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match &self.0 {
+            wasi::uart::uart::Error::LimitExceeded => embedded_io::ErrorKind::OutOfMemory,
+            _ => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+impl core::fmt::Display for WasiUartError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl embedded_io::ErrorType for WasiUart {
+    type Error = WasiUartError;
+}
+
+impl embedded_io::Read for WasiUart {
+    // embedded-io reads block until at least one byte is available
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let len = self.read_timeout(buf, u32::MAX)?;
+            if len > 0 {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+impl embedded_io::Write for WasiUart {
+    // The host caps how much one call may send, so a buffer above that cap is
+    // halved until it fits and written short; write_all sends the rest
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let mut len = buf.len().min(self.max_write);
+        loop {
+            match self.inner.write(&buf[..len]) {
+                Ok(()) => return Ok(len),
+                Err(wasi::uart::uart::Error::LimitExceeded) if len > 1 => {
+                    len /= 2;
+                    self.max_write = len;
+                }
+                Err(e) => return Err(WasiUartError(e)),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().map_err(WasiUartError)
+    }
+}
+
+// ==========================================
+// PWM IMPLEMENTATION
+// ==========================================
+
+pub struct WasiPwm {
+    inner: wasi::pwm::pwm::PwmChannel,
+}
+
+impl WasiPwm {
+    /// Create a wrapper from an already opened host PWM channel
+    pub fn new(inner: wasi::pwm::pwm::PwmChannel) -> Self {
+        Self { inner }
+    }
+
+    /// Open a PWM channel by name via the WASI host import
+    pub fn open(name: &str) -> Result<Self, WasiPwmError> {
+        let inner = wasi::pwm::pwm::open(name).map_err(WasiPwmError)?;
+        Ok(Self { inner })
+    }
+
+    /// Change the frequency, returning what the host actually applied
+    pub fn set_frequency(&mut self, hz: u32) -> Result<u32, WasiPwmError> {
+        self.inner.set_frequency(hz).map_err(WasiPwmError)
+    }
+
+    pub fn enable(&mut self) -> Result<(), WasiPwmError> {
+        self.inner.enable().map_err(WasiPwmError)
+    }
+
+    pub fn disable(&mut self) -> Result<(), WasiPwmError> {
+        self.inner.disable().map_err(WasiPwmError)
+    }
+}
+
+#[derive(Debug)]
+pub struct WasiPwmError(pub wasi::pwm::pwm::Error);
+
+impl embedded_hal::pwm::Error for WasiPwmError {
+    fn kind(&self) -> embedded_hal::pwm::ErrorKind {
+        embedded_hal::pwm::ErrorKind::Other
+    }
+}
+
+impl core::fmt::Display for WasiPwmError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl embedded_hal::pwm::ErrorType for WasiPwm {
+    type Error = WasiPwmError;
+}
+
+// The host works in fractions, so the full u16 range is the guest-side resolution
+impl embedded_hal::pwm::SetDutyCycle for WasiPwm {
+    fn max_duty_cycle(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        self.set_duty_cycle_fraction(duty, u16::MAX)
+    }
+
+    fn set_duty_cycle_fraction(&mut self, num: u16, denom: u16) -> Result<(), Self::Error> {
+        self.inner.set_duty_cycle(num, denom).map_err(WasiPwmError)
+    }
+}
+
+// ==========================================
+// ADC IMPLEMENTATION
+// ==========================================
+
+// embedded-hal 1.0 has no ADC trait, so this only wraps the host resource
+pub struct WasiAdc {
+    inner: wasi::adc::adc::AdcChannel,
+}
+
+impl WasiAdc {
+    /// Create a wrapper from an already opened host ADC channel
+    pub fn new(inner: wasi::adc::adc::AdcChannel) -> Self {
+        Self { inner }
+    }
+
+    /// Open an ADC channel by name via the WASI host import
+    pub fn open(name: &str) -> Result<Self, WasiAdcError> {
+        let inner = wasi::adc::adc::open(name).map_err(WasiAdcError)?;
+        Ok(Self { inner })
+    }
+
+    /// Take a single raw conversion
+    pub fn read(&mut self) -> Result<u16, WasiAdcError> {
+        self.inner.read().map_err(WasiAdcError)
+    }
+
+    /// Take `count` raw conversions back to back
+    pub fn read_burst(&mut self, count: u32) -> Result<Vec<u16>, WasiAdcError> {
+        self.inner.read_burst(count).map_err(WasiAdcError)
+    }
+
+    /// Take a single conversion and scale it to millivolts
+    pub fn read_millivolts(&mut self) -> Result<u32, WasiAdcError> {
+        let raw = u64::from(self.read()?);
+        let full_scale = 1u64 << self.resolution();
+        Ok((raw * u64::from(self.reference_voltage()) / full_scale) as u32)
+    }
+
+    /// Full-scale voltage in millivolts
+    pub fn reference_voltage(&self) -> u32 {
+        self.inner.reference_voltage()
+    }
+
+    /// Bits per conversion result
+    pub fn resolution(&self) -> u8 {
+        self.inner.resolution()
+    }
+}
+
+#[derive(Debug)]
+pub struct WasiAdcError(pub wasi::adc::adc::Error);
+
+impl core::fmt::Display for WasiAdcError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+// ==========================================
+// MANIFEST
+// ==========================================
+
+/// Declares the labels this guest opens, per policy section, so hosts can check
+/// them against `policy.toml` before running it:
+///
+/// ```ignore
+/// wasi_embedded_hal::manifest! {
+///     spi: ["screen"],
+///     gpio: ["RES", "DC"],
+/// }
+/// ```
+///
+/// The list is stored as TOML in the `embedded-manifest` custom section. The
+/// linker concatenates that section across crates, so each invocation ends its
+/// TOML with a NUL byte and hosts merge the chunks; a helper crate and the guest
+/// may both declare labels.
+#[macro_export]
+macro_rules! manifest {
+    ($($section:ident: [$($label:literal),* $(,)?]),* $(,)?) => {
+        const _: () = {
+            const TEXT: &str = concat!(
+                $(stringify!($section), " = [", $("\"", $label, "\", ",)* "]\n",)*
+                "\0"
+            );
+
+            #[used]
+            #[unsafe(link_section = "embedded-manifest")]
+            static MANIFEST: [u8; TEXT.len()] = $crate::manifest_bytes(TEXT);
+        };
+    };
+}
+
+#[doc(hidden)]
+pub const fn manifest_bytes<const N: usize>(text: &str) -> [u8; N] {
+    let bytes = text.as_bytes();
+    let mut out = [0; N];
+    let mut i = 0;
+    while i < N {
+        out[i] = bytes[i];
+        i += 1;
+    }
+    out
+}
+
+// ==========================================
+// BOARD
+// ==========================================
+
+/// A device the host refused while a generated `Board` was being opened (see
+/// the `codegen` feature), with the label it was opened by.
+#[derive(Debug)]
+pub enum BoardError {
+    Spi(&'static str, WasiSpiError),
+    I2c(&'static str, WasiI2cError),
+    Uart(&'static str, WasiUartError),
+    Pwm(&'static str, WasiPwmError),
+    Adc(&'static str, WasiAdcError),
+    Gpio(&'static str, WasiGpioError),
+}
+
+impl core::fmt::Display for BoardError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BoardError::Spi(label, err) => write!(f, "failed to open SPI `{}`: {}", label, err),
+            BoardError::I2c(label, err) => write!(f, "failed to open I2C `{}`: {}", label, err),
+            BoardError::Uart(label, err) => write!(f, "failed to open UART `{}`: {}", label, err),
+            BoardError::Pwm(label, err) => write!(f, "failed to open PWM `{}`: {}", label, err),
+            BoardError::Adc(label, err) => write!(f, "failed to open ADC `{}`: {}", label, err),
+            BoardError::Gpio(label, err) => write!(f, "failed to open GPIO `{}`: {}", label, err),
+        }
+    }
+}
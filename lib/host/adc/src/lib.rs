@@ -0,0 +1,139 @@
+#![no_std]
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use wasmtime::component::{Linker, Resource, ResourceTable};
+
+wasmtime::component::bindgen!({
+    path: "../../../wit/adc.wit",
+    world: "wasi-adc-host",
+    with: { "wasi:adc/adc.adc-channel": ActiveAdcChannel }
+});
+
+use wasi::adc::adc;
+pub use wasi::adc::adc::Error;
+
+pub struct ActiveAdcChannel {
+    pub id: usize,
+}
+
+/// An analog input as seen by the host. embedded-hal 1.0 has no ADC trait,
+/// so each backend implements this directly.
+pub trait ErasedAdc {
+    fn read(&mut self) -> Result<u16, adc::Error>;
+
+    /// Backends with DMA or hardware buffering can override this.
+    fn read_burst(&mut self, samples: &mut [u16]) -> Result<(), adc::Error> {
+        for sample in samples {
+            *sample = self.read()?;
+        }
+        Ok(())
+    }
+}
+
+/// Upper bounds on what a guest may ask of a single channel, checked before
+/// any buffer is allocated on its behalf.
+#[derive(Clone, Copy, Debug)]
+pub struct AdcLimits {
+    pub max_samples_per_burst: u32,
+}
+
+impl Default for AdcLimits {
+    fn default() -> Self {
+        Self {
+            max_samples_per_burst: 1024,
+        }
+    }
+}
+
+pub struct AdcHardware {
+    pub name: String,
+    pub reference_mv: u32,
+    pub resolution_bits: u8,
+    pub limits: AdcLimits,
+    pub channel: Box<dyn ErasedAdc + Send + 'static>,
+}
+
+pub struct AdcCtx {
+    pub table: ResourceTable,
+    pub hardware: Vec<AdcHardware>,
+}
+
+pub trait AdcView {
+    fn adc_ctx(&mut self) -> &mut AdcCtx;
+}
+
+impl AdcCtx {
+    fn get_hw(
+        &mut self,
+        handle: &Resource<ActiveAdcChannel>,
+    ) -> Result<&mut AdcHardware, adc::Error> {
+        let id = self
+            .table
+            .get(handle)
+            .map_err(|_| adc::Error::Other("Bad Handle".into()))?
+            .id;
+
+        self.hardware
+            .get_mut(id)
+            .ok_or_else(|| adc::Error::Other("HW unavailable".into()))
+    }
+}
+
+impl adc::Host for AdcCtx {
+    fn open(&mut self, name: String) -> Result<Resource<ActiveAdcChannel>, adc::Error> {
+        let (id, _) = self
+            .hardware
+            .iter()
+            .enumerate()
+            .find(|(_, hw)| hw.name == name)
+            .ok_or_else(|| adc::Error::Other(format!("Channel '{}' not found", name)))?;
+
+        self.table
+            .push(ActiveAdcChannel { id })
+            .map_err(|e| adc::Error::Other(e.to_string()))
+    }
+}
+
+impl adc::HostAdcChannel for AdcCtx {
+    fn read(&mut self, handle: Resource<ActiveAdcChannel>) -> Result<u16, adc::Error> {
+        self.get_hw(&handle)?.channel.read()
+    }
+
+    fn read_burst(
+        &mut self,
+        handle: Resource<ActiveAdcChannel>,
+        count: u32,
+    ) -> Result<Vec<u16>, adc::Error> {
+        let hw = self.get_hw(&handle)?;
+        if count > hw.limits.max_samples_per_burst {
+            return Err(adc::Error::LimitExceeded);
+        }
+
+        let mut samples = vec![0u16; count as usize];
+        hw.channel.read_burst(&mut samples)?;
+        Ok(samples)
+    }
+
+    // Bad handles cannot be reported here, so they read as an unusable 0 mV / 0-bit channel
+    fn reference_voltage(&mut self, handle: Resource<ActiveAdcChannel>) -> u32 {
+        self.get_hw(&handle).map_or(0, |hw| hw.reference_mv)
+    }
+
+    fn resolution(&mut self, handle: Resource<ActiveAdcChannel>) -> u8 {
+        self.get_hw(&handle).map_or(0, |hw| hw.resolution_bits)
+    }
+
+    fn drop(&mut self, rep: Resource<ActiveAdcChannel>) -> wasmtime::Result<()> {
+        self.table.delete(rep)?;
+        Ok(())
+    }
+}
+
+pub fn add_to_linker<T: AdcView + 'static>(linker: &mut Linker<T>) -> wasmtime::Result<()> {
+    adc::add_to_linker::<T, wasmtime::component::HasSelf<AdcCtx>>(linker, |host| host.adc_ctx())
+}
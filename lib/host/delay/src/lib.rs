@@ -17,6 +17,14 @@ pub trait DelayView {
 }
 
 impl wasi::delay::delay::Host for DelayCtx {
+    fn delay_ns(&mut self, ns: u32) {
+        self.delay.delay_ns(ns);
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.delay.delay_us(us);
+    }
+
     fn delay_ms(&mut self, ms: u32) {
         self.delay.delay_ms(ms);
     }
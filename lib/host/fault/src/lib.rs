@@ -0,0 +1,630 @@
+//! Fault injection for host devices. [`FaultySpi`] and [`FaultyOutputPin`] wrap
+//! any SPI device or output pin the host hands to a guest and, on a schedule,
+//! make calls fail, corrupt what they read, slow them down or leave a pin stuck,
+//! so guests can be tested against the faults real buses have.
+//!
+//! Each rule pairs a fault with a [`Trigger`]. Random triggers draw from a
+//! seeded generator per wrapper, so a run repeats exactly for the same seed and
+//! sequence of calls.
+#![no_std]
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::Operation;
+
+use gpio::ErasedOutputPin;
+use spi::{BusConfig, ConfigurableSpiDevice, ErasedSpiDevice, Error};
+
+// ==========================================
+// SCHEDULING
+// ==========================================
+
+/// When a fault fires, counting calls from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// On each call with probability 1/n.
+    OneIn(u32),
+    /// On every nth call.
+    Every(u64),
+    /// On exactly these calls.
+    Calls(Vec<u64>),
+}
+
+impl Trigger {
+    fn fires(&self, call: u64, rng: &mut Rng) -> bool {
+        match self {
+            Trigger::OneIn(n) => rng.below(u64::from(*n).max(1)) == 0,
+            Trigger::Every(n) => *n > 0 && call.is_multiple_of(*n),
+            Trigger::Calls(calls) => calls.contains(&call),
+        }
+    }
+}
+
+/// SplitMix64: small, fast and the same on every platform.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`; `n` must not be 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+/// Derives a per-device seed, so devices sharing a run seed don't fault in step
+/// and adding a device doesn't change what the others see.
+pub fn label_seed(seed: u64, label: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in label.bytes() {
+        hash = (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    seed ^ hash
+}
+
+/// A fault that fired, and on which call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Injection<F> {
+    pub call: u64,
+    pub fault: F,
+}
+
+type Observer<F> = Box<dyn FnMut(&Injection<F>) + Send + 'static>;
+
+struct Schedule<F> {
+    rules: Vec<(F, Trigger)>,
+    rng: Rng,
+    calls: u64,
+    /// Only kept after `record`, since a long run fires faults without end.
+    injected: Option<Vec<Injection<F>>>,
+    observer: Option<Observer<F>>,
+}
+
+impl<F: Clone> Schedule<F> {
+    fn new(seed: u64) -> Self {
+        Self {
+            rules: Vec::new(),
+            rng: Rng::new(seed),
+            calls: 0,
+            injected: None,
+            observer: None,
+        }
+    }
+
+    /// Counts a call and returns the faults that fire on it.
+    fn next(&mut self) -> Vec<F> {
+        self.calls += 1;
+        let mut fired = Vec::new();
+        for (fault, trigger) in &self.rules {
+            if trigger.fires(self.calls, &mut self.rng) {
+                let injection = Injection {
+                    call: self.calls,
+                    fault: fault.clone(),
+                };
+                if let Some(observer) = &mut self.observer {
+                    observer(&injection);
+                }
+                if let Some(injected) = &mut self.injected {
+                    injected.push(injection);
+                }
+                fired.push(fault.clone());
+            }
+        }
+        fired
+    }
+}
+
+type Delay = Box<dyn DelayNs + Send + 'static>;
+
+// ==========================================
+// SPI
+// ==========================================
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpiFault {
+    /// The transfer happens, but the call reports that received data was lost.
+    Overrun,
+    /// The call fails before touching the bus.
+    ModeFault,
+    /// The call fails before touching the bus.
+    ChipSelectFault,
+    /// Flips this many distinct random bits of the data read, or all of them
+    /// if it has fewer.
+    BitFlip { bits: u8 },
+    /// The read stops at a random byte; the rest reads 0xFF, like an undriven MISO.
+    ShortRead,
+    /// Waits this long before the call.
+    Latency { micros: u32 },
+}
+
+/// Wraps an SPI device, injecting [`SpiFault`]s on every call but `configure`.
+pub struct FaultySpi {
+    device: Box<dyn ConfigurableSpiDevice + Send + 'static>,
+    delay: Delay,
+    schedule: Schedule<SpiFault>,
+}
+
+impl FaultySpi {
+    /// `delay` serves [`SpiFault::Latency`]; give it the clock the guest runs on.
+    pub fn new(
+        device: Box<dyn ConfigurableSpiDevice + Send + 'static>,
+        delay: impl DelayNs + Send + 'static,
+        seed: u64,
+    ) -> Self {
+        Self {
+            device,
+            delay: Box::new(delay),
+            schedule: Schedule::new(seed),
+        }
+    }
+
+    pub fn inject(mut self, fault: SpiFault, trigger: Trigger) -> Self {
+        self.schedule.rules.push((fault, trigger));
+        self
+    }
+
+    /// Called with every fault as it fires.
+    pub fn on_inject(
+        mut self,
+        observer: impl FnMut(&Injection<SpiFault>) + Send + 'static,
+    ) -> Self {
+        self.schedule.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Keeps every fault that fires from now on, for [`Self::injected`].
+    pub fn record(mut self) -> Self {
+        self.schedule.injected = Some(Vec::new());
+        self
+    }
+
+    /// Every fault fired since [`Self::record`], in order; empty without it.
+    pub fn injected(&self) -> &[Injection<SpiFault>] {
+        self.schedule.injected.as_deref().unwrap_or_default()
+    }
+
+    /// Runs one call with the faults scheduled for it. `received` gives the
+    /// buffers the call read into, for corrupting afterwards.
+    fn call<O>(
+        &mut self,
+        operation: O,
+        received: impl FnOnce(&mut O) -> Vec<&mut [u8]>,
+        run: impl FnOnce(&mut (dyn ConfigurableSpiDevice + Send), &mut O) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut operation = operation;
+        let faults = self.schedule.next();
+        for fault in &faults {
+            match fault {
+                SpiFault::Latency { micros } => self.delay.delay_us(*micros),
+                SpiFault::ModeFault => return Err(Error::ModeFault),
+                SpiFault::ChipSelectFault => return Err(Error::ChipSelectFault),
+                _ => {}
+            }
+        }
+
+        run(self.device.as_mut(), &mut operation)?;
+
+        let mut buffers = received(&mut operation);
+        let total: usize = buffers.iter().map(|buffer| buffer.len()).sum();
+        for fault in &faults {
+            match fault {
+                SpiFault::BitFlip { bits } if total > 0 => {
+                    let count = usize::from(*bits).min(total * 8);
+                    let mut flipped = Vec::with_capacity(count);
+                    while flipped.len() < count {
+                        let bit = self.schedule.rng.below(total as u64 * 8) as usize;
+                        if !flipped.contains(&bit) {
+                            flipped.push(bit);
+                        }
+                    }
+                    for bit in flipped {
+                        if let Some(byte) = nth_byte(&mut buffers, bit / 8) {
+                            *byte ^= 1 << (bit % 8);
+                        }
+                    }
+                }
+                SpiFault::ShortRead if total > 0 => {
+                    let cut = self.schedule.rng.below(total as u64) as usize;
+                    for index in cut..total {
+                        if let Some(byte) = nth_byte(&mut buffers, index) {
+                            *byte = 0xff;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if faults.contains(&SpiFault::Overrun) {
+            return Err(Error::Overrun);
+        }
+        Ok(())
+    }
+}
+
+fn nth_byte<'a>(buffers: &'a mut [&mut [u8]], mut index: usize) -> Option<&'a mut u8> {
+    for buffer in buffers.iter_mut() {
+        if index < buffer.len() {
+            return Some(&mut buffer[index]);
+        }
+        index -= buffer.len();
+    }
+    None
+}
+
+impl ErasedSpiDevice for FaultySpi {
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        self.call(
+            buf,
+            |buf| alloc::vec![&mut **buf],
+            |device, buf| device.read(buf),
+        )
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.call(data, |_| Vec::new(), |device, data| device.write(data))
+    }
+
+    fn transfer(&mut self, rx: &mut [u8], tx: &[u8]) -> Result<(), Error> {
+        self.call(
+            (rx, tx),
+            |(rx, _)| alloc::vec![&mut **rx],
+            |device, (rx, tx)| device.transfer(rx, tx),
+        )
+    }
+
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Error> {
+        self.call(
+            operations,
+            |operations| {
+                operations
+                    .iter_mut()
+                    .filter_map(|operation| match operation {
+                        Operation::Read(buf)
+                        | Operation::Transfer(buf, _)
+                        | Operation::TransferInPlace(buf) => Some(&mut **buf),
+                        _ => None,
+                    })
+                    .collect()
+            },
+            |device, operations| device.transaction(operations),
+        )
+    }
+}
+
+impl ConfigurableSpiDevice for FaultySpi {
+    fn configure(&mut self, config: &BusConfig) -> Result<(), Error> {
+        self.device.configure(config)
+    }
+}
+
+// ==========================================
+// GPIO
+// ==========================================
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PinFault {
+    /// From this call on, the pin stays at this level whatever the guest sets.
+    Stuck { high: bool },
+    /// Waits this long before the call.
+    Latency { micros: u32 },
+}
+
+/// Wraps an output pin, injecting [`PinFault`]s on every `set_high`/`set_low`.
+pub struct FaultyOutputPin {
+    pin: Box<dyn ErasedOutputPin + Send + 'static>,
+    delay: Delay,
+    schedule: Schedule<PinFault>,
+    stuck: Option<bool>,
+}
+
+impl FaultyOutputPin {
+    /// `delay` serves [`PinFault::Latency`]; give it the clock the guest runs on.
+    pub fn new(
+        pin: Box<dyn ErasedOutputPin + Send + 'static>,
+        delay: impl DelayNs + Send + 'static,
+        seed: u64,
+    ) -> Self {
+        Self {
+            pin,
+            delay: Box::new(delay),
+            schedule: Schedule::new(seed),
+            stuck: None,
+        }
+    }
+
+    pub fn inject(mut self, fault: PinFault, trigger: Trigger) -> Self {
+        self.schedule.rules.push((fault, trigger));
+        self
+    }
+
+    /// Called with every fault as it fires.
+    pub fn on_inject(
+        mut self,
+        observer: impl FnMut(&Injection<PinFault>) + Send + 'static,
+    ) -> Self {
+        self.schedule.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Keeps every fault that fires from now on, for [`Self::injected`].
+    pub fn record(mut self) -> Self {
+        self.schedule.injected = Some(Vec::new());
+        self
+    }
+
+    /// Every fault fired since [`Self::record`], in order; empty without it.
+    pub fn injected(&self) -> &[Injection<PinFault>] {
+        self.schedule.injected.as_deref().unwrap_or_default()
+    }
+
+    fn set(&mut self, high: bool) -> Result<(), gpio::Error> {
+        for fault in self.schedule.next() {
+            match fault {
+                PinFault::Latency { micros } => self.delay.delay_us(micros),
+                PinFault::Stuck { high } if self.stuck.is_none() => self.stuck = Some(high),
+                PinFault::Stuck { .. } => {}
+            }
+        }
+        // A stuck pin silently holds its level, as a shorted line would
+        let level = self.stuck.unwrap_or(high);
+        if level {
+            self.pin.set_high()
+        } else {
+            self.pin.set_low()
+        }
+    }
+}
+
+impl ErasedOutputPin for FaultyOutputPin {
+    fn set_high(&mut self) -> Result<(), gpio::Error> {
+        self.set(true)
+    }
+
+    fn set_low(&mut self) -> Result<(), gpio::Error> {
+        self.set(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::format;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use core::convert::Infallible;
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use embedded_hal::digital::{ErrorType as DigitalErrorType, OutputPin};
+    use embedded_hal::spi::{ErrorType as SpiErrorType, SpiDevice};
+    use spi::Reconfigurable;
+    use std::sync::Mutex;
+
+    /// Adds every wait to a shared count of nanoseconds.
+    #[derive(Clone, Default)]
+    struct Waited(Arc<AtomicU64>);
+
+    impl DelayNs for Waited {
+        fn delay_ns(&mut self, ns: u32) {
+            self.0.fetch_add(ns.into(), Ordering::Relaxed);
+        }
+    }
+
+    /// Reads 0x5a and keeps every byte written.
+    #[derive(Clone, Default)]
+    struct Bus(Arc<Mutex<Vec<u8>>>);
+
+    impl SpiErrorType for Bus {
+        type Error = Infallible;
+    }
+
+    impl SpiDevice<u8> for Bus {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+            let mut written = self.0.lock().unwrap();
+            for operation in operations {
+                match operation {
+                    Operation::Read(buf) => buf.fill(0x5a),
+                    Operation::Write(data) => written.extend_from_slice(data),
+                    Operation::Transfer(rx, tx) => {
+                        written.extend_from_slice(tx);
+                        rx.fill(0x5a);
+                    }
+                    Operation::TransferInPlace(buf) => {
+                        written.extend_from_slice(buf);
+                        buf.fill(0x5a);
+                    }
+                    Operation::DelayNs(_) => {}
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn faulty_spi(bus: &Bus, fault: SpiFault) -> FaultySpi {
+        let device = Reconfigurable::new(bus.clone(), |_: &mut Bus, _: &BusConfig| Ok(()));
+        FaultySpi::new(Box::new(device), Waited::default(), 1).inject(fault, Trigger::Every(1))
+    }
+
+    /// Keeps every level it is set to.
+    #[derive(Clone, Default)]
+    struct Line(Arc<Mutex<Vec<bool>>>);
+
+    impl DigitalErrorType for Line {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Line {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.lock().unwrap().push(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.lock().unwrap().push(true);
+            Ok(())
+        }
+    }
+
+    fn fired(trigger: Trigger, calls: u64) -> Vec<u64> {
+        let mut rng = Rng::new(7);
+        (1..=calls)
+            .filter(|&call| trigger.fires(call, &mut rng))
+            .collect()
+    }
+
+    #[test]
+    fn triggers_fire_on_schedule() {
+        assert_eq!(fired(Trigger::Every(3), 10), [3, 6, 9]);
+        assert_eq!(fired(Trigger::Every(0), 10), [] as [u64; 0]);
+        assert_eq!(fired(Trigger::Calls(vec![2, 5, 40]), 10), [2, 5]);
+        assert_eq!(fired(Trigger::OneIn(1), 4), [1, 2, 3, 4]);
+        assert_eq!(fired(Trigger::OneIn(0), 4), [1, 2, 3, 4]);
+
+        let one_in_four = fired(Trigger::OneIn(4), 4000).len();
+        assert!((800..1200).contains(&one_in_four), "{}", one_in_four);
+    }
+
+    /// The calls on which a pin with a 1-in-3 latency fault is slowed down.
+    fn latency_calls(seed: u64) -> Vec<u64> {
+        let mut pin = FaultyOutputPin::new(Box::new(Line::default()), Waited::default(), seed)
+            .inject(PinFault::Latency { micros: 1 }, Trigger::OneIn(3))
+            .record();
+        for _ in 0..100 {
+            pin.set_high().unwrap();
+        }
+        pin.injected()
+            .iter()
+            .map(|injection| injection.call)
+            .collect()
+    }
+
+    #[test]
+    fn a_seed_repeats_its_injections() {
+        let seed = label_seed(42, "LED");
+        assert_eq!(seed, label_seed(42, "LED"));
+        assert_eq!(latency_calls(seed), latency_calls(seed));
+        assert!(!latency_calls(seed).is_empty());
+
+        assert_ne!(seed, label_seed(42, "DC"));
+        assert_ne!(seed, label_seed(43, "LED"));
+        assert_ne!(latency_calls(seed), latency_calls(label_seed(42, "DC")));
+    }
+
+    #[test]
+    fn injections_are_only_kept_when_recording() {
+        let waited = Waited::default();
+        let mut pin = FaultyOutputPin::new(Box::new(Line::default()), waited.clone(), 0)
+            .inject(PinFault::Latency { micros: 5 }, Trigger::Calls(vec![2]));
+        pin.set_high().unwrap();
+        pin.set_high().unwrap();
+        assert!(pin.injected().is_empty());
+        assert_eq!(waited.0.load(Ordering::Relaxed), 5_000);
+
+        let mut spi = faulty_spi(&Bus::default(), SpiFault::Overrun).record();
+        let _ = spi.write(&[1]);
+        let _ = spi.write(&[2]);
+        assert_eq!(
+            spi.injected(),
+            [
+                Injection {
+                    call: 1,
+                    fault: SpiFault::Overrun
+                },
+                Injection {
+                    call: 2,
+                    fault: SpiFault::Overrun
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bit_flips_flip_distinct_bits() {
+        for (bits, flipped) in [(5, 5), (32, 32), (200, 32)] {
+            let mut spi = faulty_spi(&Bus::default(), SpiFault::BitFlip { bits });
+            let mut buf = [0; 4];
+            spi.read(&mut buf).unwrap();
+            let changed: u32 = buf.iter().map(|byte| (byte ^ 0x5a).count_ones()).sum();
+            assert_eq!(changed, flipped, "{} bits: {:02x?}", bits, buf);
+        }
+    }
+
+    #[test]
+    fn short_reads_end_in_undriven_bytes() {
+        let bus = Bus::default();
+        let mut spi = faulty_spi(&bus, SpiFault::ShortRead);
+        for _ in 0..20 {
+            let mut rx = [0; 8];
+            spi.transfer(&mut rx, &[0x01]).unwrap();
+            assert_eq!(rx[7], 0xff);
+            assert!(
+                rx.iter()
+                    .skip_while(|&&byte| byte == 0x5a)
+                    .all(|&byte| byte == 0xff),
+                "{:02x?}",
+                rx
+            );
+        }
+        assert_eq!(bus.0.lock().unwrap().len(), 20);
+    }
+
+    #[test]
+    fn overruns_report_a_completed_transfer() {
+        let bus = Bus::default();
+        let mut spi = faulty_spi(&bus, SpiFault::Overrun);
+        let (mut read, mut in_place) = ([0; 2], [0x03]);
+        let result = spi.transaction(&mut [
+            Operation::Write(&[0x01, 0x02]),
+            Operation::Read(&mut read),
+            Operation::TransferInPlace(&mut in_place),
+        ]);
+        assert!(matches!(result, Err(Error::Overrun)));
+        assert_eq!(*bus.0.lock().unwrap(), [0x01, 0x02, 0x03]);
+        assert_eq!((read, in_place), ([0x5a, 0x5a], [0x5a]));
+    }
+
+    #[test]
+    fn bus_faults_fail_before_the_transfer() {
+        for (fault, error) in [
+            (SpiFault::ModeFault, Error::ModeFault),
+            (SpiFault::ChipSelectFault, Error::ChipSelectFault),
+        ] {
+            let bus = Bus::default();
+            let mut spi = faulty_spi(&bus, fault);
+            let result = spi.write(&[0x01]);
+            assert_eq!(
+                format!("{:?}", result),
+                format!("{:?}", Err::<(), _>(error))
+            );
+            assert!(bus.0.lock().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn a_stuck_pin_holds_its_level() {
+        let line = Line::default();
+        let mut pin = FaultyOutputPin::new(Box::new(line.clone()), Waited::default(), 0)
+            .inject(PinFault::Stuck { high: true }, Trigger::Calls(vec![2]))
+            .inject(PinFault::Stuck { high: false }, Trigger::Calls(vec![4]));
+        pin.set_low().unwrap();
+        pin.set_low().unwrap();
+        pin.set_low().unwrap();
+        pin.set_high().unwrap();
+        pin.set_low().unwrap();
+        assert_eq!(*line.0.lock().unwrap(), [false, true, true, true, true]);
+    }
+}
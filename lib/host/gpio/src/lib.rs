@@ -3,45 +3,228 @@ extern crate alloc;
 
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::string::String;
-use embedded_hal::digital::OutputPin;
-use wasmtime::component::Linker;
+use alloc::vec::Vec;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{Error as HalError, InputPin, OutputPin};
+use wasmtime::component::{Linker, Resource, ResourceTable};
 
 wasmtime::component::bindgen!({
     path: "../../../wit/gpio.wit",
     world: "wasi-gpio-host",
+    with: { "wasi:gpio/gpio.pin": ActiveGpioPin }
 });
 
+pub use wasi::gpio::gpio::{Edge, Error, Level};
+
+/// Index into the output or input list of the `GpioCtx` that opened the pin.
+pub enum ActiveGpioPin {
+    Output(usize),
+    Input(usize),
+}
+
+fn map_hal_error<E: HalError>(err: E) -> Error {
+    Error::Hardware(format!("{:?}", err))
+}
+
 pub trait ErasedOutputPin {
-    fn set_high(&mut self);
-    fn set_low(&mut self);
+    fn set_high(&mut self) -> Result<(), Error>;
+    fn set_low(&mut self) -> Result<(), Error>;
 }
 
 impl<T: OutputPin> ErasedOutputPin for T {
-    fn set_high(&mut self) {
-        let _ = OutputPin::set_high(self);
+    fn set_high(&mut self) -> Result<(), Error> {
+        OutputPin::set_high(self).map_err(map_hal_error)
+    }
+    fn set_low(&mut self) -> Result<(), Error> {
+        OutputPin::set_low(self).map_err(map_hal_error)
+    }
+}
+
+pub trait ErasedInputPin {
+    fn is_high(&mut self) -> Result<bool, Error>;
+}
+
+impl<T: InputPin> ErasedInputPin for T {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        InputPin::is_high(self).map_err(map_hal_error)
+    }
+}
+
+/// An input pin the host can block on until its level changes.
+pub trait ErasedEdgePin: ErasedInputPin {
+    /// Returns false if `timeout_ms` elapsed before the edge was seen.
+    fn wait_for_edge(&mut self, edge: Edge, timeout_ms: u32) -> Result<bool, Error>;
+}
+
+/// Bounces shorter than this many re-samples are followed; a line still
+/// chattering after that reports its latest level instead of stalling the guest.
+const MAX_DEBOUNCE_SAMPLES: u32 = 8;
+
+/// Only reports a level once it reads the same across a full debounce period.
+pub struct Debounced<P, D> {
+    pin: P,
+    delay: D,
+    period_us: u32,
+}
+
+impl<P: ErasedInputPin, D: DelayNs> Debounced<P, D> {
+    pub fn new(pin: P, delay: D, period_us: u32) -> Self {
+        Self {
+            pin,
+            delay,
+            period_us,
+        }
     }
-    fn set_low(&mut self) {
-        let _ = OutputPin::set_low(self);
+}
+
+impl<P: ErasedInputPin, D: DelayNs> ErasedInputPin for Debounced<P, D> {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        let mut level = self.pin.is_high()?;
+        for _ in 0..MAX_DEBOUNCE_SAMPLES {
+            self.delay.delay_us(self.period_us);
+            let next = self.pin.is_high()?;
+            if next == level {
+                break;
+            }
+            level = next;
+        }
+        Ok(level)
+    }
+}
+
+impl<P: ErasedEdgePin, D: DelayNs> ErasedEdgePin for Debounced<P, D> {
+    /// Reports the first edge once the line has settled again.
+    fn wait_for_edge(&mut self, edge: Edge, timeout_ms: u32) -> Result<bool, Error> {
+        if !self.pin.wait_for_edge(edge, timeout_ms)? {
+            return Ok(false);
+        }
+        self.is_high()?;
+        Ok(true)
     }
 }
 
 pub struct GpioCtx {
-    pub pins: BTreeMap<String, Box<dyn ErasedOutputPin + Send + 'static>>,
+    table: ResourceTable,
+    pins: Vec<(String, Box<dyn ErasedOutputPin + Send + 'static>)>,
+    inputs: Vec<(String, Box<dyn ErasedEdgePin + Send + 'static>)>,
+    on_first_denial: Option<fn(&str, &Error)>,
+    denied: bool,
 }
 
 pub trait GpioView {
     fn gpio_ctx(&mut self) -> &mut GpioCtx;
 }
 
-impl wasi::gpio::gpio::Host for GpioCtx {
-    fn set_pin_state(&mut self, label: String, level: wasi::gpio::gpio::Level) {
-        if let Some(pin) = self.pins.get_mut(&label) {
-            match level {
-                wasi::gpio::gpio::Level::High => pin.set_high(),
-                wasi::gpio::gpio::Level::Low => pin.set_low(),
+impl GpioCtx {
+    pub fn new(
+        pins: BTreeMap<String, Box<dyn ErasedOutputPin + Send + 'static>>,
+        inputs: BTreeMap<String, Box<dyn ErasedEdgePin + Send + 'static>>,
+    ) -> Self {
+        Self {
+            table: ResourceTable::new(),
+            pins: pins.into_iter().collect(),
+            inputs: inputs.into_iter().collect(),
+            on_first_denial: None,
+            denied: false,
+        }
+    }
+
+    /// Reports the first request turned down by the policy, so a mistyped
+    /// label shows up in the host log instead of failing silently.
+    pub fn on_first_denial(mut self, log: fn(&str, &Error)) -> Self {
+        self.on_first_denial = Some(log);
+        self
+    }
+
+    fn deny(&mut self, label: &str, err: Error) -> Error {
+        if !self.denied {
+            self.denied = true;
+            if let Some(log) = self.on_first_denial {
+                log(label, &err);
             }
         }
+        err
+    }
+
+    fn active(&self, handle: &Resource<ActiveGpioPin>) -> Result<&ActiveGpioPin, Error> {
+        self.table
+            .get(handle)
+            .map_err(|_| Error::Hardware("Bad Handle".into()))
+    }
+
+    fn output(
+        &mut self,
+        handle: &Resource<ActiveGpioPin>,
+    ) -> Result<&mut Box<dyn ErasedOutputPin + Send>, Error> {
+        match *self.active(handle)? {
+            ActiveGpioPin::Output(id) => Ok(&mut self.pins[id].1),
+            ActiveGpioPin::Input(id) => {
+                let label = self.inputs[id].0.clone();
+                Err(self.deny(&label, Error::NotPermitted))
+            }
+        }
+    }
+
+    fn input(
+        &mut self,
+        handle: &Resource<ActiveGpioPin>,
+    ) -> Result<&mut Box<dyn ErasedEdgePin + Send>, Error> {
+        match *self.active(handle)? {
+            ActiveGpioPin::Input(id) => Ok(&mut self.inputs[id].1),
+            ActiveGpioPin::Output(id) => {
+                let label = self.pins[id].0.clone();
+                Err(self.deny(&label, Error::NotPermitted))
+            }
+        }
+    }
+}
+
+impl wasi::gpio::gpio::Host for GpioCtx {
+    fn open_pin(&mut self, label: String) -> Result<Resource<ActiveGpioPin>, Error> {
+        let active = if let Some(id) = self.pins.iter().position(|(name, _)| *name == label) {
+            ActiveGpioPin::Output(id)
+        } else if let Some(id) = self.inputs.iter().position(|(name, _)| *name == label) {
+            ActiveGpioPin::Input(id)
+        } else {
+            return Err(self.deny(&label, Error::UnknownPin));
+        };
+
+        self.table
+            .push(active)
+            .map_err(|e| Error::Hardware(format!("{}", e)))
+    }
+}
+
+impl wasi::gpio::gpio::HostPin for GpioCtx {
+    fn set_state(&mut self, handle: Resource<ActiveGpioPin>, level: Level) -> Result<(), Error> {
+        let pin = self.output(&handle)?;
+        match level {
+            Level::High => pin.set_high(),
+            Level::Low => pin.set_low(),
+        }
+    }
+
+    fn get_state(&mut self, handle: Resource<ActiveGpioPin>) -> Result<Level, Error> {
+        match self.input(&handle)?.is_high()? {
+            true => Ok(Level::High),
+            false => Ok(Level::Low),
+        }
+    }
+
+    fn wait_for_edge(
+        &mut self,
+        handle: Resource<ActiveGpioPin>,
+        edge: Edge,
+        timeout_ms: u32,
+    ) -> Result<bool, Error> {
+        self.input(&handle)?.wait_for_edge(edge, timeout_ms)
+    }
+
+    fn drop(&mut self, rep: Resource<ActiveGpioPin>) -> wasmtime::Result<()> {
+        self.table.delete(rep)?;
+        Ok(())
     }
 }
 
@@ -0,0 +1,453 @@
+#![no_std]
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use embedded_hal::i2c::{
+    Error as HalError, ErrorKind, I2c, NoAcknowledgeSource, Operation as HalOperation,
+};
+use wasmtime::component::{Linker, Resource, ResourceTable};
+
+wasmtime::component::bindgen!({
+    path: "../../../wit/i2c.wit",
+    world: "wasi-i2c-host",
+    with: { "wasi:i2c/i2c.i2c-bus": ActiveI2cBus }
+});
+
+use wasi::i2c::i2c;
+pub use wasi::i2c::i2c::Error;
+
+pub struct ActiveI2cBus {
+    pub id: usize,
+}
+
+pub trait ErasedI2c {
+    fn read(&mut self, address: u8, buf: &mut [u8]) -> Result<(), i2c::Error>;
+    fn write(&mut self, address: u8, data: &[u8]) -> Result<(), i2c::Error>;
+    fn write_read(&mut self, address: u8, tx: &[u8], rx: &mut [u8]) -> Result<(), i2c::Error>;
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [HalOperation<'_>],
+    ) -> Result<(), i2c::Error>;
+}
+
+fn map_hal_error<E: HalError>(err: E) -> i2c::Error {
+    match err.kind() {
+        ErrorKind::Bus => i2c::Error::Bus,
+        ErrorKind::ArbitrationLoss => i2c::Error::ArbitrationLoss,
+        ErrorKind::NoAcknowledge(source) => i2c::Error::NoAcknowledge(match source {
+            NoAcknowledgeSource::Address => i2c::NoAcknowledgeSource::Address,
+            NoAcknowledgeSource::Data => i2c::NoAcknowledgeSource::Data,
+            NoAcknowledgeSource::Unknown => i2c::NoAcknowledgeSource::Unknown,
+        }),
+        ErrorKind::Overrun => i2c::Error::Overrun,
+        _ => i2c::Error::Other("Hardware I2C error".to_string()),
+    }
+}
+
+impl<T: I2c> ErasedI2c for T {
+    fn read(&mut self, address: u8, buf: &mut [u8]) -> Result<(), i2c::Error> {
+        I2c::read(self, address, buf).map_err(map_hal_error)
+    }
+
+    fn write(&mut self, address: u8, data: &[u8]) -> Result<(), i2c::Error> {
+        I2c::write(self, address, data).map_err(map_hal_error)
+    }
+
+    fn write_read(&mut self, address: u8, tx: &[u8], rx: &mut [u8]) -> Result<(), i2c::Error> {
+        I2c::write_read(self, address, tx, rx).map_err(map_hal_error)
+    }
+
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [HalOperation<'_>],
+    ) -> Result<(), i2c::Error> {
+        I2c::transaction(self, address, operations).map_err(map_hal_error)
+    }
+}
+
+enum TransactionBuffer {
+    Read(Vec<u8>),
+    Write(Vec<u8>),
+}
+
+impl TransactionBuffer {
+    fn from_op(op: i2c::Operation) -> Self {
+        match op {
+            i2c::Operation::Read(len) => Self::Read(vec![0; len as usize]),
+            i2c::Operation::Write(data) => Self::Write(data),
+        }
+    }
+
+    fn as_hal_op(&mut self) -> HalOperation<'_> {
+        match self {
+            Self::Read(buf) => HalOperation::Read(buf),
+            Self::Write(buf) => HalOperation::Write(buf),
+        }
+    }
+
+    fn into_result(self) -> i2c::OperationResult {
+        match self {
+            Self::Read(buf) => i2c::OperationResult::Read(buf),
+            Self::Write(_) => i2c::OperationResult::Write,
+        }
+    }
+}
+
+/// Upper bounds on what a guest may ask of a single bus, checked before
+/// any buffer is allocated on its behalf.
+#[derive(Clone, Copy, Debug)]
+pub struct I2cLimits {
+    pub max_bytes_per_op: u64,
+    pub max_ops_per_transaction: u32,
+    pub max_bytes_per_transaction: u64,
+}
+
+impl Default for I2cLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes_per_op: 256,
+            max_ops_per_transaction: 16,
+            max_bytes_per_transaction: 1024,
+        }
+    }
+}
+
+impl I2cLimits {
+    fn check_op(&self, len: u64) -> Result<(), i2c::Error> {
+        if len > self.max_bytes_per_op {
+            return Err(i2c::Error::LimitExceeded(i2c::Limit::BytesPerOperation));
+        }
+        Ok(())
+    }
+
+    fn check_transaction(&self, operations: &[i2c::Operation]) -> Result<(), i2c::Error> {
+        if operations.len() > self.max_ops_per_transaction as usize {
+            return Err(i2c::Error::LimitExceeded(
+                i2c::Limit::OperationsPerTransaction,
+            ));
+        }
+
+        let mut total: u64 = 0;
+        for op in operations {
+            let len = match op {
+                i2c::Operation::Read(len) => *len,
+                i2c::Operation::Write(data) => data.len() as u64,
+            };
+            self.check_op(len)?;
+            total = total.saturating_add(len);
+        }
+        self.check_total(total)
+    }
+
+    fn check_total(&self, total: u64) -> Result<(), i2c::Error> {
+        if total > self.max_bytes_per_transaction {
+            return Err(i2c::Error::LimitExceeded(i2c::Limit::BytesPerTransaction));
+        }
+        Ok(())
+    }
+}
+
+/// A bus granted to the guest, restricted to the target addresses in the policy.
+pub struct I2cHardware {
+    pub name: String,
+    pub addresses: Vec<u8>,
+    pub limits: I2cLimits,
+    pub bus: Box<dyn ErasedI2c + Send + 'static>,
+}
+
+impl I2cHardware {
+    fn check_address(&self, address: u8) -> Result<(), i2c::Error> {
+        if !self.addresses.contains(&address) {
+            return Err(i2c::Error::AddressNotPermitted);
+        }
+        Ok(())
+    }
+}
+
+pub struct I2cCtx {
+    pub table: ResourceTable,
+    pub hardware: Vec<I2cHardware>,
+}
+
+pub trait I2cView {
+    fn i2c_ctx(&mut self) -> &mut I2cCtx;
+}
+
+impl I2cCtx {
+    fn get_hw(
+        &mut self,
+        handle: &Resource<ActiveI2cBus>,
+        address: u8,
+    ) -> Result<&mut I2cHardware, i2c::Error> {
+        let id = self
+            .table
+            .get(handle)
+            .map_err(|_| i2c::Error::Other("Bad Handle".into()))?
+            .id;
+
+        let hw = self
+            .hardware
+            .get_mut(id)
+            .ok_or_else(|| i2c::Error::Other("HW unavailable".into()))?;
+        hw.check_address(address)?;
+        Ok(hw)
+    }
+}
+
+impl i2c::Host for I2cCtx {
+    fn open(&mut self, name: String) -> Result<Resource<ActiveI2cBus>, i2c::Error> {
+        let (id, _) = self
+            .hardware
+            .iter()
+            .enumerate()
+            .find(|(_, hw)| hw.name == name)
+            .ok_or_else(|| i2c::Error::Other(alloc::format!("Bus '{}' not found", name)))?;
+
+        self.table
+            .push(ActiveI2cBus { id })
+            .map_err(|e| i2c::Error::Other(e.to_string()))
+    }
+}
+
+impl i2c::HostI2cBus for I2cCtx {
+    fn read(
+        &mut self,
+        handle: Resource<ActiveI2cBus>,
+        address: u8,
+        len: u64,
+    ) -> Result<Vec<u8>, i2c::Error> {
+        let hw = self.get_hw(&handle, address)?;
+        hw.limits.check_op(len)?;
+
+        let mut buf = vec![0u8; len as usize];
+        hw.bus.read(address, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn write(
+        &mut self,
+        handle: Resource<ActiveI2cBus>,
+        address: u8,
+        data: Vec<u8>,
+    ) -> Result<(), i2c::Error> {
+        let hw = self.get_hw(&handle, address)?;
+        hw.limits.check_op(data.len() as u64)?;
+        hw.bus.write(address, &data)
+    }
+
+    fn write_read(
+        &mut self,
+        handle: Resource<ActiveI2cBus>,
+        address: u8,
+        data: Vec<u8>,
+        len: u64,
+    ) -> Result<Vec<u8>, i2c::Error> {
+        let hw = self.get_hw(&handle, address)?;
+        hw.limits.check_op(data.len() as u64)?;
+        hw.limits.check_op(len)?;
+        hw.limits
+            .check_total((data.len() as u64).saturating_add(len))?;
+
+        let mut rx = vec![0u8; len as usize];
+        hw.bus.write_read(address, &data, &mut rx)?;
+        Ok(rx)
+    }
+
+    fn transaction(
+        &mut self,
+        handle: Resource<ActiveI2cBus>,
+        address: u8,
+        operations: Vec<i2c::Operation>,
+    ) -> Result<Vec<i2c::OperationResult>, i2c::Error> {
+        let hw = self.get_hw(&handle, address)?;
+        hw.limits.check_transaction(&operations)?;
+
+        let mut buffers: Vec<_> = operations
+            .into_iter()
+            .map(TransactionBuffer::from_op)
+            .collect();
+
+        let mut hal_ops: Vec<_> = buffers.iter_mut().map(|b| b.as_hal_op()).collect();
+
+        hw.bus.transaction(address, &mut hal_ops)?;
+
+        Ok(buffers
+            .into_iter()
+            .map(TransactionBuffer::into_result)
+            .collect())
+    }
+
+    fn drop(&mut self, rep: Resource<ActiveI2cBus>) -> wasmtime::Result<()> {
+        self.table.delete(rep)?;
+        Ok(())
+    }
+}
+
+pub fn add_to_linker<T: I2cView + 'static>(linker: &mut Linker<T>) -> wasmtime::Result<()> {
+    i2c::add_to_linker::<T, wasmtime::component::HasSelf<I2cCtx>>(linker, |host| host.i2c_ctx())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::sync::Arc;
+    use core::convert::Infallible;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use embedded_hal::i2c::{ErrorType, SevenBitAddress};
+    use i2c::{Host, HostI2cBus, Limit, Operation, OperationResult};
+
+    /// Counts the transactions that reach the bus.
+    #[derive(Clone, Default)]
+    struct Bus(Arc<AtomicUsize>);
+
+    impl ErrorType for Bus {
+        type Error = Infallible;
+    }
+
+    impl I2c for Bus {
+        fn transaction(
+            &mut self,
+            _address: SevenBitAddress,
+            _operations: &mut [HalOperation<'_>],
+        ) -> Result<(), Infallible> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    const SENSOR: u8 = 0x76;
+
+    fn ctx(bus: &Bus) -> I2cCtx {
+        I2cCtx {
+            table: ResourceTable::new(),
+            hardware: vec![I2cHardware {
+                name: "sensors".to_string(),
+                addresses: vec![SENSOR],
+                limits: I2cLimits {
+                    max_bytes_per_op: 4,
+                    max_ops_per_transaction: 3,
+                    max_bytes_per_transaction: 6,
+                },
+                bus: Box::new(bus.clone()),
+            }],
+        }
+    }
+
+    /// A handle to the bus, opened once; every call takes its own copy.
+    fn open(ctx: &mut I2cCtx) -> impl Fn() -> Resource<ActiveI2cBus> + use<> {
+        let rep = ctx.open("sensors".to_string()).unwrap().rep();
+        move || Resource::new_own(rep)
+    }
+
+    fn exceeded(result: Result<impl core::fmt::Debug, i2c::Error>) -> Limit {
+        match result {
+            Err(i2c::Error::LimitExceeded(limit)) => limit,
+            other => panic!("expected a limit to be exceeded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ungranted_addresses_are_refused() {
+        let bus = Bus::default();
+        let mut ctx = ctx(&bus);
+        let bus_handle = open(&mut ctx);
+        let results = [
+            ctx.read(bus_handle(), 0x77, 1).map(drop),
+            ctx.write(bus_handle(), 0x77, vec![1]),
+            ctx.write_read(bus_handle(), 0x00, vec![1], 1).map(drop),
+            ctx.transaction(bus_handle(), 0x7f, vec![Operation::Read(1)])
+                .map(drop),
+        ];
+        for result in results {
+            assert!(
+                matches!(result, Err(i2c::Error::AddressNotPermitted)),
+                "{:?}",
+                result
+            );
+        }
+        assert_eq!(bus.0.load(Ordering::Relaxed), 0);
+
+        assert_eq!(ctx.read(bus_handle(), SENSOR, 1).unwrap(), [0]);
+        assert_eq!(bus.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn oversized_operations_are_rejected_before_allocating() {
+        let bus = Bus::default();
+        let mut ctx = ctx(&bus);
+        let bus_handle = open(&mut ctx);
+
+        assert_eq!(
+            exceeded(ctx.read(bus_handle(), SENSOR, u64::MAX)),
+            Limit::BytesPerOperation
+        );
+        assert_eq!(
+            exceeded(ctx.write(bus_handle(), SENSOR, vec![0; 5])),
+            Limit::BytesPerOperation
+        );
+        assert_eq!(
+            exceeded(ctx.write_read(bus_handle(), SENSOR, vec![1], u64::MAX)),
+            Limit::BytesPerOperation
+        );
+        assert_eq!(
+            exceeded(ctx.transaction(
+                bus_handle(),
+                SENSOR,
+                vec![Operation::Write(vec![1]), Operation::Read(u64::MAX)]
+            )),
+            Limit::BytesPerOperation
+        );
+        assert_eq!(bus.0.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn transactions_are_bounded_in_operations_and_total_bytes() {
+        let bus = Bus::default();
+        let mut ctx = ctx(&bus);
+        let bus_handle = open(&mut ctx);
+
+        assert_eq!(
+            exceeded(ctx.transaction(bus_handle(), SENSOR, vec![Operation::Read(1); 4])),
+            Limit::OperationsPerTransaction
+        );
+        assert_eq!(
+            exceeded(ctx.transaction(
+                bus_handle(),
+                SENSOR,
+                vec![Operation::Read(4), Operation::Write(vec![1, 2, 3])]
+            )),
+            Limit::BytesPerTransaction
+        );
+        assert_eq!(
+            exceeded(ctx.write_read(bus_handle(), SENSOR, vec![1, 2, 3], 4)),
+            Limit::BytesPerTransaction
+        );
+        assert_eq!(bus.0.load(Ordering::Relaxed), 0);
+
+        let results = ctx
+            .transaction(
+                bus_handle(),
+                SENSOR,
+                vec![Operation::Write(vec![0xf7]), Operation::Read(4)],
+            )
+            .unwrap();
+        assert!(matches!(
+            &results[..],
+            [OperationResult::Write, OperationResult::Read(read)] if read.len() == 4
+        ));
+        assert_eq!(
+            ctx.write_read(bus_handle(), SENSOR, vec![1, 2], 4)
+                .unwrap()
+                .len(),
+            4
+        );
+        assert_eq!(bus.0.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,290 @@
+#![no_std]
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use embedded_hal::pwm::{Error as HalError, SetDutyCycle};
+use wasmtime::component::{Linker, Resource, ResourceTable};
+
+wasmtime::component::bindgen!({
+    path: "../../../wit/pwm.wit",
+    world: "wasi-pwm-host",
+    with: { "wasi:pwm/pwm.pwm-channel": ActivePwmChannel }
+});
+
+use wasi::pwm::pwm;
+pub use wasi::pwm::pwm::Error;
+
+pub struct ActivePwmChannel {
+    pub id: usize,
+}
+
+pub trait ErasedPwm {
+    fn set_duty_cycle_fraction(&mut self, num: u16, denom: u16) -> Result<(), pwm::Error>;
+}
+
+fn map_hal_error<E: HalError>(err: E) -> pwm::Error {
+    pwm::Error::Other(format!("{:?}", err))
+}
+
+impl<T: SetDutyCycle> ErasedPwm for T {
+    fn set_duty_cycle_fraction(&mut self, num: u16, denom: u16) -> Result<(), pwm::Error> {
+        SetDutyCycle::set_duty_cycle_fraction(self, num, denom).map_err(map_hal_error)
+    }
+}
+
+/// A channel whose period and output can be controlled besides its duty cycle.
+/// Implementations keep the duty cycle fraction when the frequency changes.
+pub trait PwmChannel: ErasedPwm {
+    /// Returns the frequency the hardware actually settled on.
+    fn set_frequency(&mut self, hz: u32) -> Result<u32, pwm::Error>;
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), pwm::Error>;
+}
+
+/// Frequencies and duty cycles a guest may choose for a channel.
+#[derive(Clone, Copy, Debug)]
+pub struct PwmBounds {
+    pub min_frequency: u32,
+    pub max_frequency: u32,
+    pub min_duty_percent: u8,
+    pub max_duty_percent: u8,
+}
+
+impl PwmBounds {
+    /// Only the given frequency, at any duty cycle.
+    pub fn fixed(frequency: u32) -> Self {
+        Self {
+            min_frequency: frequency,
+            max_frequency: frequency,
+            min_duty_percent: 0,
+            max_duty_percent: 100,
+        }
+    }
+
+    fn clamp_frequency(&self, hz: u32) -> u32 {
+        hz.clamp(self.min_frequency, self.max_frequency)
+    }
+
+    fn check_duty(&self, num: u16, denom: u16) -> Result<(), pwm::Error> {
+        let (num, denom) = (u32::from(num), u32::from(denom));
+        if denom == 0
+            || num > denom
+            || num * 100 < u32::from(self.min_duty_percent) * denom
+            || num * 100 > u32::from(self.max_duty_percent) * denom
+        {
+            return Err(pwm::Error::DutyOutOfRange);
+        }
+        Ok(())
+    }
+}
+
+pub struct PwmHardware {
+    pub name: String,
+    pub bounds: PwmBounds,
+    pub channel: Box<dyn PwmChannel + Send + 'static>,
+}
+
+pub struct PwmCtx {
+    pub table: ResourceTable,
+    pub hardware: Vec<PwmHardware>,
+}
+
+pub trait PwmView {
+    fn pwm_ctx(&mut self) -> &mut PwmCtx;
+}
+
+impl PwmCtx {
+    fn get_hw(
+        &mut self,
+        handle: &Resource<ActivePwmChannel>,
+    ) -> Result<&mut PwmHardware, pwm::Error> {
+        let id = self
+            .table
+            .get(handle)
+            .map_err(|_| pwm::Error::Other("Bad Handle".into()))?
+            .id;
+
+        self.hardware
+            .get_mut(id)
+            .ok_or_else(|| pwm::Error::Other("HW unavailable".into()))
+    }
+}
+
+impl pwm::Host for PwmCtx {
+    fn open(&mut self, name: String) -> Result<Resource<ActivePwmChannel>, pwm::Error> {
+        let (id, _) = self
+            .hardware
+            .iter()
+            .enumerate()
+            .find(|(_, hw)| hw.name == name)
+            .ok_or_else(|| pwm::Error::Other(format!("Channel '{}' not found", name)))?;
+
+        self.table
+            .push(ActivePwmChannel { id })
+            .map_err(|e| pwm::Error::Other(e.to_string()))
+    }
+}
+
+impl pwm::HostPwmChannel for PwmCtx {
+    fn set_duty_cycle(
+        &mut self,
+        handle: Resource<ActivePwmChannel>,
+        num: u16,
+        denom: u16,
+    ) -> Result<(), pwm::Error> {
+        let hw = self.get_hw(&handle)?;
+        hw.bounds.check_duty(num, denom)?;
+        hw.channel.set_duty_cycle_fraction(num, denom)
+    }
+
+    fn set_frequency(
+        &mut self,
+        handle: Resource<ActivePwmChannel>,
+        hz: u32,
+    ) -> Result<u32, pwm::Error> {
+        let hw = self.get_hw(&handle)?;
+        let hz = hw.bounds.clamp_frequency(hz);
+        hw.channel.set_frequency(hz)
+    }
+
+    fn enable(&mut self, handle: Resource<ActivePwmChannel>) -> Result<(), pwm::Error> {
+        self.get_hw(&handle)?.channel.set_enabled(true)
+    }
+
+    fn disable(&mut self, handle: Resource<ActivePwmChannel>) -> Result<(), pwm::Error> {
+        self.get_hw(&handle)?.channel.set_enabled(false)
+    }
+
+    fn drop(&mut self, rep: Resource<ActivePwmChannel>) -> wasmtime::Result<()> {
+        self.table.delete(rep)?;
+        Ok(())
+    }
+}
+
+pub fn add_to_linker<T: PwmView + 'static>(linker: &mut Linker<T>) -> wasmtime::Result<()> {
+    pwm::add_to_linker::<T, wasmtime::component::HasSelf<PwmCtx>>(linker, |host| host.pwm_ctx())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use core::convert::Infallible;
+    use embedded_hal::pwm::ErrorType;
+    use pwm::{Host, HostPwmChannel};
+    use std::sync::Mutex;
+
+    const BOUNDS: PwmBounds = PwmBounds {
+        min_frequency: 50,
+        max_frequency: 20_000,
+        min_duty_percent: 10,
+        max_duty_percent: 90,
+    };
+
+    fn duty_ok(bounds: &PwmBounds, num: u16, denom: u16) -> bool {
+        match bounds.check_duty(num, denom) {
+            Ok(()) => true,
+            Err(pwm::Error::DutyOutOfRange) => false,
+            Err(err) => panic!("unexpected {:?}", err),
+        }
+    }
+
+    #[test]
+    fn duty_cycles_stay_within_the_granted_percentages() {
+        for (num, denom, ok) in [
+            (0, 0, false),
+            (1, 0, false),
+            (0, 100, false),
+            (9, 100, false),
+            (10, 100, true),
+            (1, 2, true),
+            (90, 100, true),
+            (91, 100, false),
+            (100, 100, false),
+            (u16::MAX, u16::MAX, false),
+            (6554, u16::MAX, true),
+            (6553, u16::MAX, false),
+        ] {
+            assert_eq!(duty_ok(&BOUNDS, num, denom), ok, "{}/{}", num, denom);
+        }
+
+        let any = PwmBounds::fixed(1000);
+        assert!(duty_ok(&any, 0, 1) && duty_ok(&any, 1, 1) && duty_ok(&any, u16::MAX, u16::MAX));
+        assert!(!duty_ok(&any, 0, 0) && !duty_ok(&any, 2, 1));
+    }
+
+    #[test]
+    fn frequencies_are_clamped_at_both_ends() {
+        for (asked, clamped) in [
+            (0, 50),
+            (49, 50),
+            (50, 50),
+            (1000, 1000),
+            (20_000, 20_000),
+            (20_001, 20_000),
+            (u32::MAX, 20_000),
+        ] {
+            assert_eq!(BOUNDS.clamp_frequency(asked), clamped, "{} Hz", asked);
+        }
+        assert_eq!(PwmBounds::fixed(1000).clamp_frequency(5), 1000);
+    }
+
+    /// Keeps every duty cycle and frequency the host sets.
+    #[derive(Clone, Default)]
+    struct Channel(Arc<Mutex<Vec<(u16, u32)>>>);
+
+    impl ErrorType for Channel {
+        type Error = Infallible;
+    }
+
+    impl SetDutyCycle for Channel {
+        fn max_duty_cycle(&self) -> u16 {
+            1000
+        }
+
+        fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+            self.0.lock().unwrap().push((duty, 0));
+            Ok(())
+        }
+    }
+
+    impl PwmChannel for Channel {
+        fn set_frequency(&mut self, hz: u32) -> Result<u32, pwm::Error> {
+            self.0.lock().unwrap().push((0, hz));
+            Ok(hz)
+        }
+
+        fn set_enabled(&mut self, _enabled: bool) -> Result<(), pwm::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_channel_only_sees_what_the_bounds_allow() {
+        let channel = Channel::default();
+        let mut ctx = PwmCtx {
+            table: ResourceTable::new(),
+            hardware: vec![PwmHardware {
+                name: "fan".to_string(),
+                bounds: BOUNDS,
+                channel: Box::new(channel.clone()),
+            }],
+        };
+        let rep = ctx.open("fan".to_string()).unwrap().rep();
+        let handle = || Resource::<ActivePwmChannel>::new_own(rep);
+
+        assert!(matches!(
+            ctx.set_duty_cycle(handle(), 95, 100),
+            Err(pwm::Error::DutyOutOfRange)
+        ));
+        ctx.set_duty_cycle(handle(), 1, 4).unwrap();
+        assert_eq!(ctx.set_frequency(handle(), 1).unwrap(), 50);
+        assert_eq!(ctx.set_frequency(handle(), 100_000).unwrap(), 20_000);
+        assert_eq!(*channel.0.lock().unwrap(), [(250, 0), (0, 50), (0, 20_000)]);
+    }
+}
@@ -0,0 +1,408 @@
+#![no_std]
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use embedded_hal::delay::DelayNs;
+use wasmtime::component::types::ComponentItem;
+use wasmtime::component::{Component, HasSelf, Linker, ResourceTable, ResourceType};
+use wasmtime::{Engine, Store};
+
+use adc::{AdcCtx, AdcView};
+use delay::{DelayCtx, DelayView};
+use gpio::{GpioCtx, GpioView};
+use i2c::{I2cCtx, I2cView};
+use pwm::{PwmCtx, PwmView};
+use spi::{SpiCtx, SpiView};
+use uart::{UartCtx, UartView};
+
+// The board-agnostic interfaces every host serves from the logger and clock
+wasmtime::component::bindgen!({
+    inline: "
+        package embedded:runtime;
+        world runtime-host {
+            import my:debug/logging;
+            import my:clock/time;
+            import my:timer/timer;
+        }
+    ",
+    path: [
+        "../../../wit/debug.wit",
+        "../../../wit/clock.wit",
+        "../../../wit/timer.wit",
+    ],
+});
+
+type Logger = Box<dyn FnMut(&str) + Send + 'static>;
+type Clock = Box<dyn FnMut() -> u64 + Send + 'static>;
+
+/// The store data of a board host: one context per WASI interface, plus the
+/// logger and clock behind `my:debug` and `my:clock`/`my:timer`.
+pub struct EmbeddedHost {
+    spi_ctx: SpiCtx,
+    i2c_ctx: I2cCtx,
+    uart_ctx: UartCtx,
+    pwm_ctx: PwmCtx,
+    adc_ctx: AdcCtx,
+    gpio_ctx: GpioCtx,
+    delay_ctx: DelayCtx,
+    logger: Logger,
+    clock: Clock,
+}
+
+impl EmbeddedHost {
+    pub fn builder() -> EmbeddedHostBuilder {
+        EmbeddedHostBuilder::default()
+    }
+
+    /// A linker with every interface of the runtime registered. Hosts may add
+    /// their own world-specific imports before instantiating.
+    pub fn linker(engine: &Engine) -> wasmtime::Result<Linker<Self>> {
+        Self::gated_linker(engine, &Interface::ALL)
+    }
+
+    /// A linker with only the `granted` interfaces registered. Check the guest
+    /// with [`EmbeddedHost::check_imports`] first, or instantiation fails with a
+    /// generic missing-import error.
+    pub fn gated_linker(engine: &Engine, granted: &[Interface]) -> wasmtime::Result<Linker<Self>> {
+        let mut linker = Linker::new(engine);
+        for interface in Interface::ALL {
+            if granted.contains(&interface) {
+                interface.add_to_linker(&mut linker)?;
+            }
+        }
+        Ok(linker)
+    }
+
+    /// Lists the runtime interfaces `component` imports beyond `granted`. Imports
+    /// the runtime doesn't serve, such as a host's world-specific ones, are not
+    /// checked here.
+    pub fn check_imports(
+        engine: &Engine,
+        component: &Component,
+        granted: &[Interface],
+    ) -> Result<(), Denied> {
+        let denied: Vec<_> = component
+            .component_type()
+            .imports(engine)
+            .filter_map(|(name, _)| Interface::from_wit_name(name))
+            .filter(|interface| !granted.contains(interface))
+            .collect();
+        if denied.is_empty() {
+            Ok(())
+        } else {
+            Err(Denied(denied))
+        }
+    }
+
+    /// Links every function of the `denied` interfaces as a stub that traps when
+    /// called, so the guest can still be instantiated and run up to that call.
+    pub fn stub_imports(
+        linker: &mut Linker<Self>,
+        component: &Component,
+        denied: &Denied,
+    ) -> wasmtime::Result<()> {
+        let engine = linker.engine().clone();
+        for (name, item) in component.component_type().imports(&engine) {
+            let denied = Interface::from_wit_name(name).filter(|i| denied.0.contains(i));
+            let (Some(_), ComponentItem::ComponentInstance(instance)) = (denied, item) else {
+                continue;
+            };
+            let mut stubs = linker.instance(name)?;
+            for (export, item) in instance.exports(&engine) {
+                match item {
+                    ComponentItem::ComponentFunc(_) => {
+                        let message = format!("`{}#{}` is not granted by the policy", name, export);
+                        stubs.func_new(export, move |_, _, _, _| {
+                            Err(wasmtime::Error::msg(message.clone()))
+                        })?;
+                    }
+                    ComponentItem::Resource(_) => {
+                        stubs.resource(export, ResourceType::host::<()>(), |_, _| Ok(()))?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the host into a new store and instantiates `component` in it through
+    /// the world's generated `instantiate`, e.g. `|s, c, l| App::instantiate(s, c, l)`.
+    pub fn instantiate<W>(
+        self,
+        linker: &Linker<Self>,
+        component: &Component,
+        instantiate: impl FnOnce(&mut Store<Self>, &Component, &Linker<Self>) -> wasmtime::Result<W>,
+    ) -> wasmtime::Result<(Store<Self>, W)> {
+        let mut store = Store::new(linker.engine(), self);
+        let world = instantiate(&mut store, component, linker)?;
+        Ok((store, world))
+    }
+
+    /// Forwards a guest message to the host's logger.
+    pub fn log(&mut self, msg: &str) {
+        (self.logger)(msg);
+    }
+
+    /// Microseconds on the host clock; the epoch is up to the host.
+    pub fn now_micros(&mut self) -> u64 {
+        (self.clock)()
+    }
+}
+
+/// An interface the runtime can link, one per WIT import.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Interface {
+    Spi,
+    I2c,
+    Uart,
+    Pwm,
+    Adc,
+    Gpio,
+    Delay,
+    Logging,
+    Clock,
+    Timer,
+}
+
+impl Interface {
+    pub const ALL: [Interface; 10] = [
+        Interface::Spi,
+        Interface::I2c,
+        Interface::Uart,
+        Interface::Pwm,
+        Interface::Adc,
+        Interface::Gpio,
+        Interface::Delay,
+        Interface::Logging,
+        Interface::Clock,
+        Interface::Timer,
+    ];
+
+    /// The name a component imports it by, without a version.
+    pub fn wit_name(self) -> &'static str {
+        match self {
+            Interface::Spi => "wasi:spi/spi",
+            Interface::I2c => "wasi:i2c/i2c",
+            Interface::Uart => "wasi:uart/uart",
+            Interface::Pwm => "wasi:pwm/pwm",
+            Interface::Adc => "wasi:adc/adc",
+            Interface::Gpio => "wasi:gpio/gpio",
+            Interface::Delay => "wasi:delay/delay",
+            Interface::Logging => "my:debug/logging",
+            Interface::Clock => "my:clock/time",
+            Interface::Timer => "my:timer/timer",
+        }
+    }
+
+    fn from_wit_name(name: &str) -> Option<Self> {
+        let name = name.split('@').next().unwrap_or(name);
+        Self::ALL
+            .into_iter()
+            .find(|interface| interface.wit_name() == name)
+    }
+
+    fn add_to_linker(self, linker: &mut Linker<EmbeddedHost>) -> wasmtime::Result<()> {
+        type Host = EmbeddedHost;
+        match self {
+            Interface::Spi => spi::add_to_linker(linker),
+            Interface::I2c => i2c::add_to_linker(linker),
+            Interface::Uart => uart::add_to_linker(linker),
+            Interface::Pwm => pwm::add_to_linker(linker),
+            Interface::Adc => adc::add_to_linker(linker),
+            Interface::Gpio => gpio::add_to_linker(linker),
+            Interface::Delay => delay::add_to_linker(linker),
+            Interface::Logging => {
+                my::debug::logging::add_to_linker::<Host, HasSelf<Host>>(linker, |host| host)
+            }
+            Interface::Clock => {
+                my::clock::time::add_to_linker::<Host, HasSelf<Host>>(linker, |host| host)
+            }
+            Interface::Timer => {
+                my::timer::timer::add_to_linker::<Host, HasSelf<Host>>(linker, |host| host)
+            }
+        }
+    }
+}
+
+/// Runtime interfaces a guest imports that its host doesn't grant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denied(pub Vec<Interface>);
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the guest imports ")?;
+        for (i, interface) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "`{}`", interface.wit_name())?;
+        }
+        f.write_str(", which the policy does not grant")
+    }
+}
+
+impl core::error::Error for Denied {}
+
+/// Collects the hardware granted by a board's policy. Peripherals left out are
+/// simply empty, so guests that open them get a not-found error.
+#[derive(Default)]
+pub struct EmbeddedHostBuilder {
+    spi: Vec<spi::SpiHardware>,
+    i2c: Vec<i2c::I2cHardware>,
+    uart: Vec<uart::UartHardware>,
+    pwm: Vec<pwm::PwmHardware>,
+    adc: Vec<adc::AdcHardware>,
+    gpio: Option<GpioCtx>,
+    delay: Option<Box<dyn DelayNs + Send + 'static>>,
+    logger: Option<Logger>,
+    clock: Option<Clock>,
+}
+
+impl EmbeddedHostBuilder {
+    pub fn spi(mut self, hardware: Vec<spi::SpiHardware>) -> Self {
+        self.spi = hardware;
+        self
+    }
+
+    pub fn i2c(mut self, hardware: Vec<i2c::I2cHardware>) -> Self {
+        self.i2c = hardware;
+        self
+    }
+
+    pub fn uart(mut self, hardware: Vec<uart::UartHardware>) -> Self {
+        self.uart = hardware;
+        self
+    }
+
+    pub fn pwm(mut self, hardware: Vec<pwm::PwmHardware>) -> Self {
+        self.pwm = hardware;
+        self
+    }
+
+    pub fn adc(mut self, hardware: Vec<adc::AdcHardware>) -> Self {
+        self.adc = hardware;
+        self
+    }
+
+    pub fn gpio(mut self, gpio: GpioCtx) -> Self {
+        self.gpio = Some(gpio);
+        self
+    }
+
+    pub fn delay(mut self, delay: impl DelayNs + Send + 'static) -> Self {
+        self.delay = Some(Box::new(delay));
+        self
+    }
+
+    /// Receives every `my:debug/logging` message. Without one, guest logs are dropped.
+    pub fn logger(mut self, logger: impl FnMut(&str) + Send + 'static) -> Self {
+        self.logger = Some(Box::new(logger));
+        self
+    }
+
+    /// Returns the current time in microseconds. Without one, guests read a clock stuck at 0.
+    pub fn clock(mut self, clock: impl FnMut() -> u64 + Send + 'static) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Panics without a delay, since there is no portable way to wait.
+    pub fn build(self) -> EmbeddedHost {
+        EmbeddedHost {
+            spi_ctx: SpiCtx {
+                table: ResourceTable::new(),
+                hardware: self.spi,
+            },
+            i2c_ctx: I2cCtx {
+                table: ResourceTable::new(),
+                hardware: self.i2c,
+            },
+            uart_ctx: UartCtx {
+                table: ResourceTable::new(),
+                hardware: self.uart,
+            },
+            pwm_ctx: PwmCtx {
+                table: ResourceTable::new(),
+                hardware: self.pwm,
+            },
+            adc_ctx: AdcCtx {
+                table: ResourceTable::new(),
+                hardware: self.adc,
+            },
+            gpio_ctx: self
+                .gpio
+                .unwrap_or_else(|| GpioCtx::new(BTreeMap::new(), BTreeMap::new())),
+            delay_ctx: DelayCtx {
+                delay: self.delay.expect("EmbeddedHost needs a delay"),
+            },
+            logger: self.logger.unwrap_or_else(|| Box::new(|_| {})),
+            clock: self.clock.unwrap_or_else(|| Box::new(|| 0)),
+        }
+    }
+}
+
+impl my::debug::logging::Host for EmbeddedHost {
+    fn log(&mut self, msg: String) {
+        EmbeddedHost::log(self, &msg);
+    }
+}
+
+impl my::clock::time::Host for EmbeddedHost {
+    fn now_ms(&mut self) -> u64 {
+        self.now_micros() / 1000
+    }
+}
+
+impl my::timer::timer::Host for EmbeddedHost {
+    fn now_micros(&mut self) -> u64 {
+        EmbeddedHost::now_micros(self)
+    }
+}
+
+impl SpiView for EmbeddedHost {
+    fn spi_ctx(&mut self) -> &mut SpiCtx {
+        &mut self.spi_ctx
+    }
+}
+
+impl I2cView for EmbeddedHost {
+    fn i2c_ctx(&mut self) -> &mut I2cCtx {
+        &mut self.i2c_ctx
+    }
+}
+
+impl UartView for EmbeddedHost {
+    fn uart_ctx(&mut self) -> &mut UartCtx {
+        &mut self.uart_ctx
+    }
+}
+
+impl PwmView for EmbeddedHost {
+    fn pwm_ctx(&mut self) -> &mut PwmCtx {
+        &mut self.pwm_ctx
+    }
+}
+
+impl AdcView for EmbeddedHost {
+    fn adc_ctx(&mut self) -> &mut AdcCtx {
+        &mut self.adc_ctx
+    }
+}
+
+impl GpioView for EmbeddedHost {
+    fn gpio_ctx(&mut self) -> &mut GpioCtx {
+        &mut self.gpio_ctx
+    }
+}
+
+impl DelayView for EmbeddedHost {
+    fn delay_ctx(&mut self) -> &mut DelayCtx {
+        &mut self.delay_ctx
+    }
+}
@@ -0,0 +1,588 @@
+//! A Bosch BME280 on SPI: chip ID, calibration NVM, the control registers and
+//! forced or normal mode measurements. Readings come from [`Curve`]s and are
+//! encoded as the raw ADC values that the datasheet's compensation formulas turn
+//! back into them, using a fixed set of calibration words.
+
+use alloc::vec::Vec;
+
+use crate::{Clock, SimSpi};
+
+const CHIP_ID: u8 = 0x60;
+const RESET_COMMAND: u8 = 0xb6;
+
+const REG_CALIB_00: u8 = 0x88;
+const REG_CHIP_ID: u8 = 0xd0;
+const REG_RESET: u8 = 0xe0;
+const REG_CALIB_26: u8 = 0xe1;
+const REG_CTRL_HUM: u8 = 0xf2;
+const REG_STATUS: u8 = 0xf3;
+const REG_CTRL_MEAS: u8 = 0xf4;
+const REG_CONFIG: u8 = 0xf5;
+const REG_DATA: u8 = 0xf7;
+
+/// Raw values the data registers hold before the first measurement, and for
+/// channels whose oversampling is set to skip.
+const SKIPPED_20: u32 = 0x80000;
+const SKIPPED_16: u32 = 0x8000;
+
+/// A value over time, in seconds since the simulation started.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Curve {
+    Constant(f32),
+    /// Moves linearly from `from` to `to` over `seconds`, then stays at `to`.
+    Ramp {
+        from: f32,
+        to: f32,
+        seconds: f32,
+    },
+    /// `(seconds, value)` points in time order, interpolated linearly and held
+    /// flat before the first and after the last.
+    Trace(Vec<(f32, f32)>),
+}
+
+impl Curve {
+    pub fn at(&self, seconds: f32) -> f32 {
+        match self {
+            Curve::Constant(value) => *value,
+            Curve::Ramp {
+                from,
+                to,
+                seconds: duration,
+            } => {
+                if *duration <= 0.0 || seconds >= *duration {
+                    *to
+                } else {
+                    from + (to - from) * (seconds.max(0.0) / duration)
+                }
+            }
+            Curve::Trace(points) => {
+                let Some(&(first_time, first)) = points.first() else {
+                    return 0.0;
+                };
+                if seconds <= first_time {
+                    return first;
+                }
+                for pair in points.windows(2) {
+                    let ((t0, v0), (t1, v1)) = (pair[0], pair[1]);
+                    if seconds <= t1 {
+                        if t1 <= t0 {
+                            return v1;
+                        }
+                        return v0 + (v1 - v0) * ((seconds - t0) / (t1 - t0));
+                    }
+                }
+                points[points.len() - 1].1
+            }
+        }
+    }
+}
+
+/// What the sensor measures: degrees Celsius, hectopascals and percent relative
+/// humidity.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Environment {
+    pub temperature: Curve,
+    pub pressure: Curve,
+    pub humidity: Curve,
+}
+
+/// The example calibration from the BME280 datasheet, with humidity words
+/// typical of real parts.
+#[derive(Clone, Copy)]
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+    h1: u8,
+    h2: i16,
+    h3: u8,
+    h4: i16,
+    h5: i16,
+    h6: i8,
+}
+
+const CALIBRATION: Calibration = Calibration {
+    t1: 27504,
+    t2: 26435,
+    t3: -1000,
+    p1: 36477,
+    p2: -10685,
+    p3: 3024,
+    p4: 2855,
+    p5: 140,
+    p6: -7,
+    p7: 15500,
+    p8: -14600,
+    p9: 6000,
+    h1: 75,
+    h2: 370,
+    h3: 0,
+    h4: 313,
+    h5: 50,
+    h6: 30,
+};
+
+impl Calibration {
+    /// `calib00..calib25` from 0x88 and `calib26..calib32` from 0xE1.
+    fn nvm(&self) -> ([u8; 26], [u8; 7]) {
+        let mut low = [0; 26];
+        let words = [
+            self.t1,
+            self.t2 as u16,
+            self.t3 as u16,
+            self.p1,
+            self.p2 as u16,
+            self.p3 as u16,
+            self.p4 as u16,
+            self.p5 as u16,
+            self.p6 as u16,
+            self.p7 as u16,
+            self.p8 as u16,
+            self.p9 as u16,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            low[i * 2..i * 2 + 2].copy_from_slice(&word.to_le_bytes());
+        }
+        low[25] = self.h1;
+
+        let [h2_low, h2_high] = self.h2.to_le_bytes();
+        let (h4, h5) = (self.h4 as u16, self.h5 as u16);
+        let high = [
+            h2_low,
+            h2_high,
+            self.h3,
+            (h4 >> 4) as u8,
+            ((h5 & 0x0f) << 4) as u8 | (h4 & 0x0f) as u8,
+            (h5 >> 4) as u8,
+            self.h6 as u8,
+        ];
+        (low, high)
+    }
+
+    /// Returns `t_fine` and the temperature in 0.01 degC.
+    fn temperature(&self, adc: i32) -> (i32, i32) {
+        let (t1, t2, t3) = (i32::from(self.t1), i32::from(self.t2), i32::from(self.t3));
+        let var1 = (((adc >> 3) - (t1 << 1)) * t2) >> 11;
+        let var2 = (((((adc >> 4) - t1) * ((adc >> 4) - t1)) >> 12) * t3) >> 14;
+        let t_fine = var1 + var2;
+        (t_fine, (t_fine * 5 + 128) >> 8)
+    }
+
+    /// Pressure in Pa as Q24.8.
+    fn pressure(&self, adc: i32, t_fine: i32) -> i64 {
+        let mut var1 = i64::from(t_fine) - 128000;
+        let mut var2 = var1 * var1 * i64::from(self.p6);
+        var2 += (var1 * i64::from(self.p5)) << 17;
+        var2 += i64::from(self.p4) << 35;
+        var1 = ((var1 * var1 * i64::from(self.p3)) >> 8) + ((var1 * i64::from(self.p2)) << 12);
+        var1 = (((1i64 << 47) + var1) * i64::from(self.p1)) >> 33;
+        if var1 == 0 {
+            return 0;
+        }
+        let mut p = 1048576 - i64::from(adc);
+        p = (((p << 31) - var2) * 3125) / var1;
+        let var1 = (i64::from(self.p9) * (p >> 13) * (p >> 13)) >> 25;
+        let var2 = (i64::from(self.p8) * p) >> 19;
+        ((p + var1 + var2) >> 8) + (i64::from(self.p7) << 4)
+    }
+
+    /// Relative humidity in percent as Q22.10. The datasheet's 32-bit arithmetic,
+    /// widened so the search can try out-of-range inputs without overflowing.
+    fn humidity(&self, adc: i32, t_fine: i32) -> i64 {
+        let (h1, h2, h3) = (i64::from(self.h1), i64::from(self.h2), i64::from(self.h3));
+        let (h4, h5, h6) = (i64::from(self.h4), i64::from(self.h5), i64::from(self.h6));
+        let x = i64::from(t_fine) - 76800;
+        let mut v = (((i64::from(adc) << 14) - (h4 << 20) - (h5 * x)) + 16384) >> 15;
+        v *=
+            ((((((x * h6) >> 10) * (((x * h3) >> 11) + 32768)) >> 10) + 2097152) * h2 + 8192) >> 14;
+        v -= ((((v >> 15) * (v >> 15)) >> 7) * h1) >> 4;
+        v.clamp(0, 419430400) >> 12
+    }
+
+    /// Raw readings the compensation formulas turn into these values.
+    fn encode(&self, celsius: f32, hpa: f32, percent: f32) -> (u32, u32, u32) {
+        let adc_t = search(0, 0xfffff, (celsius * 100.0) as i64, |adc| {
+            i64::from(self.temperature(adc).1)
+        });
+        let (t_fine, _) = self.temperature(adc_t);
+        let adc_p = search(0, 0xfffff, (hpa * 100.0 * 256.0) as i64, |adc| {
+            self.pressure(adc, t_fine)
+        });
+        let adc_h = search(0, 0xffff, (percent * 1024.0) as i64, |adc| {
+            self.humidity(adc, t_fine)
+        });
+        (adc_t as u32, adc_p as u32, adc_h as u32)
+    }
+}
+
+/// The input in `low..=high` whose output is closest to `target`, for a
+/// monotonic `f` in either direction.
+fn search(mut low: i32, mut high: i32, target: i64, f: impl Fn(i32) -> i64) -> i32 {
+    let rising = f(high) >= f(low);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if (f(mid) < target) == rising {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low > 0 && (f(low - 1) - target).abs() < (f(low) - target).abs() {
+        low - 1
+    } else {
+        low
+    }
+}
+
+/// SPI framing as on the real part: the first byte carries the register address
+/// with bit 7 set for a read, reads then auto-increment, and writes continue as
+/// address and data pairs. Only the low 7 address bits go over the wire, and the
+/// register map lives at 0x80..=0xFF, so bit 7 is put back before decoding.
+///
+/// A forced measurement takes the datasheet's typical time on `clock`; until
+/// then `status.measuring` is set and the data registers hold the old values.
+pub struct Bme280 {
+    environment: Environment,
+    clock: Clock,
+    nvm: ([u8; 26], [u8; 7]),
+    ctrl_hum: u8,
+    /// `ctrl_hum` only takes effect with the next write to `ctrl_meas`.
+    humidity_oversampling: u8,
+    ctrl_meas: u8,
+    config: u8,
+    data: [u8; 8],
+    /// When the forced conversion in progress completes, in clock microseconds.
+    converting_until: Option<u64>,
+    address: Option<u8>,
+    read: bool,
+}
+
+impl Bme280 {
+    pub fn new(environment: Environment, clock: Clock) -> Self {
+        let mut sensor = Self {
+            environment,
+            clock,
+            nvm: CALIBRATION.nvm(),
+            ctrl_hum: 0,
+            humidity_oversampling: 0,
+            ctrl_meas: 0,
+            config: 0,
+            data: [0; 8],
+            converting_until: None,
+            address: None,
+            read: false,
+        };
+        sensor.reset();
+        sensor
+    }
+
+    fn reset(&mut self) {
+        self.ctrl_hum = 0;
+        self.humidity_oversampling = 0;
+        self.ctrl_meas = 0;
+        self.config = 0;
+        self.converting_until = None;
+        self.store(SKIPPED_20, SKIPPED_20, SKIPPED_16);
+    }
+
+    fn mode(&self) -> u8 {
+        self.ctrl_meas & 0b11
+    }
+
+    /// Typical measurement time from the datasheet, in microseconds, for the
+    /// oversampling settings in effect.
+    fn conversion_micros(&self) -> u64 {
+        let samples = |setting: u8| match setting & 0b111 {
+            0 => 0,
+            setting @ 1..=4 => 1 << (setting - 1),
+            _ => 16,
+        };
+        let temperature = samples(self.ctrl_meas >> 5);
+        let pressure = samples(self.ctrl_meas >> 2);
+        let humidity = samples(self.humidity_oversampling);
+        let optional = |samples: u64| {
+            if samples == 0 {
+                0
+            } else {
+                2000 * samples + 500
+            }
+        };
+        1000 + 2000 * temperature + optional(pressure) + optional(humidity)
+    }
+
+    /// Completes a forced conversion once its time has passed: the data registers
+    /// update and the sensor goes back to sleep.
+    fn update(&mut self) {
+        if let Some(until) = self.converting_until
+            && (self.clock)() >= until
+        {
+            self.converting_until = None;
+            self.measure();
+            self.ctrl_meas &= !0b11;
+        }
+    }
+
+    /// Converts the environment at the current time into the data registers,
+    /// skipping channels whose oversampling is 0.
+    fn measure(&mut self) {
+        let seconds = (self.clock)() as f32 / 1_000_000.0;
+        let (adc_t, adc_p, adc_h) = CALIBRATION.encode(
+            self.environment.temperature.at(seconds),
+            self.environment.pressure.at(seconds),
+            self.environment.humidity.at(seconds),
+        );
+        let temperature = if self.ctrl_meas >> 5 == 0 {
+            SKIPPED_20
+        } else {
+            adc_t
+        };
+        let pressure = if (self.ctrl_meas >> 2) & 0b111 == 0 {
+            SKIPPED_20
+        } else {
+            adc_p
+        };
+        let humidity = if self.humidity_oversampling == 0 {
+            SKIPPED_16
+        } else {
+            adc_h
+        };
+        self.store(pressure, temperature, humidity);
+    }
+
+    fn store(&mut self, pressure: u32, temperature: u32, humidity: u32) {
+        let twenty = |raw: u32| {
+            [
+                (raw >> 12) as u8,
+                (raw >> 4) as u8,
+                ((raw & 0x0f) << 4) as u8,
+            ]
+        };
+        self.data[0..3].copy_from_slice(&twenty(pressure));
+        self.data[3..6].copy_from_slice(&twenty(temperature));
+        self.data[6..8].copy_from_slice(&(humidity as u16).to_be_bytes());
+    }
+
+    fn register(&self, address: u8) -> u8 {
+        let (low, high) = &self.nvm;
+        match address {
+            0x88..=0xa1 => low[usize::from(address - REG_CALIB_00)],
+            REG_CHIP_ID => CHIP_ID,
+            0xe1..=0xe7 => high[usize::from(address - REG_CALIB_26)],
+            REG_CTRL_HUM => self.ctrl_hum,
+            // `measuring` during forced conversions; the NVM copy (`im_update`) is instant
+            REG_STATUS => u8::from(self.converting_until.is_some()) << 3,
+            REG_CTRL_MEAS => self.ctrl_meas,
+            REG_CONFIG => self.config,
+            0xf7..=0xfe => self.data[usize::from(address - REG_DATA)],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u8, value: u8) {
+        match address {
+            REG_RESET if value == RESET_COMMAND => self.reset(),
+            REG_CTRL_HUM => self.ctrl_hum = value & 0b111,
+            REG_CTRL_MEAS => {
+                self.ctrl_meas = value;
+                self.humidity_oversampling = self.ctrl_hum;
+                if matches!(self.mode(), 0b01 | 0b10) {
+                    // Forced mode: one measurement, then back to sleep
+                    self.converting_until = Some((self.clock)() + self.conversion_micros());
+                    self.update();
+                }
+            }
+            REG_CONFIG => self.config = value & 0b1111_1101,
+            // Everything else is read-only
+            _ => {}
+        }
+    }
+}
+
+impl SimSpi for Bme280 {
+    fn select(&mut self) {
+        self.address = None;
+        self.update();
+    }
+
+    fn exchange(&mut self, mosi: u8) -> u8 {
+        let Some(address) = self.address else {
+            self.address = Some(mosi | 0x80);
+            self.read = mosi & 0x80 != 0;
+            // Normal mode keeps converting; a burst read from the data
+            // registers sees the latest values
+            if self.read && self.mode() == 0b11 && (mosi | 0x80) >= REG_DATA {
+                self.measure();
+            }
+            return 0;
+        };
+        if self.read {
+            self.address = Some(address.wrapping_add(1) | 0x80);
+            self.register(address)
+        } else {
+            self.write(address, mosi);
+            self.address = None;
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VirtualClock;
+    use alloc::vec;
+
+    /// The calibration words as a driver reads them back out of the NVM.
+    fn from_nvm((low, high): ([u8; 26], [u8; 7])) -> Calibration {
+        let word = |i: usize| u16::from_le_bytes([low[i * 2], low[i * 2 + 1]]);
+        Calibration {
+            t1: word(0),
+            t2: word(1) as i16,
+            t3: word(2) as i16,
+            p1: word(3),
+            p2: word(4) as i16,
+            p3: word(5) as i16,
+            p4: word(6) as i16,
+            p5: word(7) as i16,
+            p6: word(8) as i16,
+            p7: word(9) as i16,
+            p8: word(10) as i16,
+            p9: word(11) as i16,
+            h1: low[25],
+            h2: i16::from_le_bytes([high[0], high[1]]),
+            h3: high[2],
+            h4: (i16::from(high[3] as i8) << 4) | i16::from(high[4] & 0x0f),
+            h5: (i16::from(high[5] as i8) << 4) | i16::from(high[4] >> 4),
+            h6: high[6] as i8,
+        }
+    }
+
+    /// Degrees Celsius, hectopascals and percent from raw readings.
+    fn compensate(calibration: &Calibration, (t, p, h): (u32, u32, u32)) -> (f32, f32, f32) {
+        let (t_fine, centi_celsius) = calibration.temperature(t as i32);
+        (
+            centi_celsius as f32 / 100.0,
+            calibration.pressure(p as i32, t_fine) as f32 / 256.0 / 100.0,
+            calibration.humidity(h as i32, t_fine) as f32 / 1024.0,
+        )
+    }
+
+    #[test]
+    fn encoded_readings_compensate_back() {
+        let calibration = from_nvm(CALIBRATION.nvm());
+        for (celsius, hpa, percent) in [
+            (25.0, 1013.25, 45.0),
+            (-10.5, 850.0, 5.0),
+            (60.0, 1100.0, 95.5),
+            (0.0, 300.0, 0.0),
+        ] {
+            let raw = CALIBRATION.encode(celsius, hpa, percent);
+            let (t, p, h) = compensate(&calibration, raw);
+            assert!(
+                (t - celsius).abs() <= 0.01,
+                "{} degC read as {}",
+                celsius,
+                t
+            );
+            assert!((p - hpa).abs() <= 0.01, "{} hPa read as {}", hpa, p);
+            assert!((h - percent).abs() <= 0.01, "{} % read as {}", percent, h);
+        }
+    }
+
+    #[test]
+    fn curves_interpolate() {
+        let ramp = Curve::Ramp {
+            from: 10.0,
+            to: 20.0,
+            seconds: 4.0,
+        };
+        assert_eq!(
+            [-1.0, 0.0, 1.0, 4.0, 9.0].map(|t| ramp.at(t)),
+            [10.0, 10.0, 12.5, 20.0, 20.0]
+        );
+        let instant = Curve::Ramp {
+            from: 10.0,
+            to: 20.0,
+            seconds: 0.0,
+        };
+        assert_eq!(instant.at(0.0), 20.0);
+
+        let trace = Curve::Trace(vec![(1.0, 0.0), (3.0, 10.0), (3.0, 50.0), (4.0, 40.0)]);
+        assert_eq!(
+            [0.0, 1.0, 2.0, 3.5, 4.0, 8.0].map(|t| trace.at(t)),
+            [0.0, 0.0, 5.0, 45.0, 40.0, 40.0]
+        );
+        assert_eq!(Curve::Trace(Vec::new()).at(1.0), 0.0);
+    }
+
+    /// Runs one SPI transaction and returns the bytes after the address.
+    fn transact(sensor: &mut Bme280, mosi: &[u8]) -> Vec<u8> {
+        sensor.select();
+        let miso: Vec<u8> = mosi.iter().map(|&byte| sensor.exchange(byte)).collect();
+        sensor.deselect();
+        miso[1..].to_vec()
+    }
+
+    fn read(sensor: &mut Bme280, address: u8, len: usize) -> Vec<u8> {
+        let mut mosi = vec![address | 0x80];
+        mosi.resize(len + 1, 0);
+        transact(sensor, &mosi)
+    }
+
+    fn raw((p, t, h): (&[u8], &[u8], &[u8])) -> (u32, u32, u32) {
+        let twenty = |b: &[u8]| u32::from(b[0]) << 12 | u32::from(b[1]) << 4 | u32::from(b[2]) >> 4;
+        (
+            twenty(t),
+            twenty(p),
+            u32::from(u16::from_be_bytes([h[0], h[1]])),
+        )
+    }
+
+    #[test]
+    fn forced_measurements_take_the_conversion_time() {
+        let clock = VirtualClock::new();
+        let environment = Environment {
+            temperature: Curve::Ramp {
+                from: 20.0,
+                to: 30.0,
+                seconds: 1.0,
+            },
+            pressure: Curve::Constant(1000.0),
+            humidity: Curve::Constant(50.0),
+        };
+        let mut sensor = Bme280::new(environment, clock.clock());
+        assert_eq!(read(&mut sensor, REG_CHIP_ID, 1), [CHIP_ID]);
+
+        // x1 humidity, then x1 temperature and pressure in forced mode
+        transact(&mut sensor, &[REG_CTRL_HUM & 0x7f, 0b001]);
+        let oversampling = (0b001 << 5) | (0b001 << 2);
+        transact(&mut sensor, &[REG_CTRL_MEAS & 0x7f, oversampling | 0b01]);
+        let conversion = 1000 + 2000 + 2500 + 2500;
+
+        clock.advance_ns((conversion - 1) * 1000);
+        assert_eq!(read(&mut sensor, REG_STATUS, 1), [0b1000]);
+        let data = read(&mut sensor, REG_DATA, 8);
+        assert_eq!(
+            raw((&data[0..3], &data[3..6], &data[6..8])),
+            (SKIPPED_20, SKIPPED_20, SKIPPED_16)
+        );
+
+        clock.advance_ns(1000);
+        assert_eq!(read(&mut sensor, REG_STATUS, 1), [0]);
+        assert_eq!(read(&mut sensor, REG_CTRL_MEAS, 1), [oversampling]);
+        let data = read(&mut sensor, REG_DATA, 8);
+        let (t, p, h) = compensate(&CALIBRATION, raw((&data[0..3], &data[3..6], &data[6..8])));
+        assert!((t - 20.08).abs() <= 0.01, "{}", t);
+        assert!((p - 1000.0).abs() <= 0.01, "{}", p);
+        assert!((h - 50.0).abs() <= 0.01, "{}", h);
+    }
+}
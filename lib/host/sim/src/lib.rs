@@ -0,0 +1,262 @@
+//! Simulated devices for running guests without hardware. SPI peripherals are
+//! modelled one byte at a time through [`SimSpi`] and plugged into the host's
+//! `spi` crate via [`SimSpiDevice`]; pins share their level through a [`Line`],
+//! so a simulated peripheral can watch a pin the guest drives. A
+//! [`VirtualClock`] replaces real time: delays advance it instantly, and the
+//! guest's clock and the devices all read it, so runs are fast and repeatable.
+#![no_std]
+extern crate alloc;
+
+pub mod bme280;
+mod png;
+pub mod registers;
+pub mod ssd1306;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::convert::Infallible;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{ErrorType as DigitalErrorType, InputPin, OutputPin};
+use embedded_hal::spi::{ErrorType as SpiErrorType, Operation, SpiDevice};
+
+use gpio::{Edge, Error};
+
+// ==========================================
+// TIME
+// ==========================================
+
+/// Microseconds since the simulation started, as simulated devices see them.
+pub type Clock = Box<dyn Fn() -> u64 + Send + 'static>;
+
+/// Simulated time, shared by everything that holds a clone. It only moves when
+/// something advances it, such as a [`VirtualDelay`].
+#[derive(Clone, Default, Debug)]
+pub struct VirtualClock(Arc<AtomicU64>);
+
+impl VirtualClock {
+    /// Starts at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now_micros(&self) -> u64 {
+        self.0.load(Ordering::Relaxed) / 1000
+    }
+
+    pub fn advance_ns(&self, ns: u64) {
+        self.0.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    /// A [`Clock`] for simulated devices that reads this one.
+    pub fn clock(&self) -> Clock {
+        let clock = self.clone();
+        Box::new(move || clock.now_micros())
+    }
+}
+
+/// A delay that advances a [`VirtualClock`] instead of sleeping.
+pub struct VirtualDelay {
+    clock: VirtualClock,
+}
+
+impl VirtualDelay {
+    pub fn new(clock: VirtualClock) -> Self {
+        Self { clock }
+    }
+}
+
+impl DelayNs for VirtualDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.clock.advance_ns(u64::from(ns));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.clock.advance_ns(u64::from(us) * 1_000);
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.clock.advance_ns(u64::from(ms) * 1_000_000);
+    }
+}
+
+// ==========================================
+// LINES
+// ==========================================
+
+/// The level of a simulated pin, shared by everything wired to it.
+#[derive(Clone, Default, Debug)]
+pub struct Line(Arc<AtomicBool>);
+
+impl Line {
+    pub fn new(high: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(high)))
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, high: bool) {
+        self.0.store(high, Ordering::Relaxed);
+    }
+}
+
+/// An output pin that drives a [`Line`].
+pub struct SimOutputPin {
+    line: Line,
+}
+
+impl SimOutputPin {
+    pub fn new(line: Line) -> Self {
+        Self { line }
+    }
+}
+
+impl DigitalErrorType for SimOutputPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for SimOutputPin {
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.line.set(true);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.line.set(false);
+        Ok(())
+    }
+}
+
+/// An input pin that reads a [`Line`] set by the host or a simulated device.
+pub struct SimInputPin {
+    line: Line,
+    clock: Option<VirtualClock>,
+}
+
+impl SimInputPin {
+    pub fn new(line: Line) -> Self {
+        Self { line, clock: None }
+    }
+
+    /// Lets edge timeouts pass on `clock`.
+    pub fn on_clock(mut self, clock: VirtualClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+}
+
+impl DigitalErrorType for SimInputPin {
+    type Error = Infallible;
+}
+
+impl InputPin for SimInputPin {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(self.line.is_high())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(!self.line.is_high())
+    }
+}
+
+impl gpio::ErasedEdgePin for SimInputPin {
+    /// Nothing moves a simulated line while the guest is blocked, so this
+    /// reports a timeout straight away instead of sleeping through it. On a
+    /// virtual clock, the timeout still elapses.
+    fn wait_for_edge(&mut self, _edge: Edge, timeout_ms: u32) -> Result<bool, Error> {
+        if let Some(clock) = &self.clock {
+            clock.advance_ns(u64::from(timeout_ms) * 1_000_000);
+        }
+        Ok(false)
+    }
+}
+
+// ==========================================
+// SPI
+// ==========================================
+
+/// A simulated SPI peripheral. The host selects it, clocks bytes through it and
+/// deselects it again once per `SpiDevice` transaction.
+pub trait SimSpi {
+    fn select(&mut self) {}
+
+    /// Takes the byte the host sends and returns the one it receives.
+    fn exchange(&mut self, mosi: u8) -> u8;
+
+    fn deselect(&mut self) {}
+}
+
+/// Runs a [`SimSpi`] as an embedded-hal `SpiDevice`. Reads clock out zeros.
+pub struct SimSpiDevice<D> {
+    device: D,
+}
+
+impl<D: SimSpi> SimSpiDevice<D> {
+    pub fn new(device: D) -> Self {
+        Self { device }
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+}
+
+impl<D> SpiErrorType for SimSpiDevice<D> {
+    type Error = Infallible;
+}
+
+impl<D: SimSpi> SpiDevice<u8> for SimSpiDevice<D> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        self.device.select();
+        for operation in operations {
+            match operation {
+                Operation::Read(buf) => {
+                    for byte in buf.iter_mut() {
+                        *byte = self.device.exchange(0);
+                    }
+                }
+                Operation::Write(data) => {
+                    for &byte in data.iter() {
+                        self.device.exchange(byte);
+                    }
+                }
+                Operation::Transfer(rx, tx) => {
+                    for i in 0..rx.len().max(tx.len()) {
+                        let miso = self.device.exchange(tx.get(i).copied().unwrap_or(0));
+                        if let Some(byte) = rx.get_mut(i) {
+                            *byte = miso;
+                        }
+                    }
+                }
+                Operation::TransferInPlace(buf) => {
+                    for byte in buf.iter_mut() {
+                        *byte = self.device.exchange(*byte);
+                    }
+                }
+                Operation::DelayNs(_) => {}
+            }
+        }
+        self.device.deselect();
+        Ok(())
+    }
+}
+
+/// Accepts every write and reads back zeros.
+pub struct NullSpi;
+
+impl SimSpi for NullSpi {
+    fn exchange(&mut self, _mosi: u8) -> u8 {
+        0
+    }
+}
+
+/// Echoes every byte back, like MOSI wired to MISO.
+pub struct LoopbackSpi;
+
+impl SimSpi for LoopbackSpi {
+    fn exchange(&mut self, mosi: u8) -> u8 {
+        mosi
+    }
+}
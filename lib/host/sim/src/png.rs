@@ -0,0 +1,125 @@
+//! Just enough PNG to save simulated frames: 8-bit grayscale, no filtering, and
+//! stored (uncompressed) deflate blocks, so there's no compressor to pull in.
+
+use alloc::vec::Vec;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Encodes `rows` of `width` gray levels each.
+pub fn grayscale(width: u32, height: u32, rows: &[Vec<u8>]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(rows.len() * (width as usize + 1));
+    for row in rows {
+        raw.push(0); // filter type: none
+        raw.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 0, 0, 0, 0]); // bit depth, grayscale, deflate, no filter, no interlace
+
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 0xffff * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(u8::from(last));
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be32(bytes: &[u8]) -> u32 {
+        u32::from_be_bytes(bytes.try_into().unwrap())
+    }
+
+    #[test]
+    fn crc_matches_the_reference_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    }
+
+    #[test]
+    fn header_and_chunk_crcs_are_valid() {
+        let png = grayscale(3, 2, &[alloc::vec![0, 128, 255], alloc::vec![255, 128, 0]]);
+        assert_eq!(png[..8], SIGNATURE);
+
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = be32(&rest[..4]) as usize;
+            let (body, crc) = rest[4..].split_at(4 + len);
+            assert_eq!(crc32(body), be32(&crc[..4]));
+            chunks.push(body[..4].to_vec());
+            if &body[..4] == b"IHDR" {
+                assert_eq!(be32(&body[4..8]), 3);
+                assert_eq!(be32(&body[8..12]), 2);
+                assert_eq!(body[12..], [8, 0, 0, 0, 0]);
+            }
+            rest = &crc[4..];
+        }
+        assert_eq!(chunks, [b"IHDR", b"IDAT", b"IEND"]);
+    }
+
+    #[test]
+    fn image_data_is_stored_with_filter_bytes() {
+        let data = zlib_stored(&[0, 1, 2, 0, 3, 4]);
+        assert_eq!(data[..2], [0x78, 0x01]);
+        // One final stored block of 6 bytes
+        assert_eq!(data[2..7], [1, 6, 0, !6, 0xff]);
+        assert_eq!(data[7..13], [0, 1, 2, 0, 3, 4]);
+        assert_eq!(be32(&data[13..]), adler32(&[0, 1, 2, 0, 3, 4]));
+    }
+}
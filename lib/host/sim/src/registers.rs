@@ -0,0 +1,392 @@
+//! A generic register-file peripheral. The first byte of a transaction carries
+//! the register address and the transfer direction as described by a
+//! [`Framing`]; the data bytes that follow read or write registers, optionally
+//! advancing to the next address after each one.
+
+use alloc::vec::Vec;
+
+use crate::SimSpi;
+
+/// When the address advances after a whole register has been transferred.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Increment {
+    Never,
+    Always,
+    /// Only in transfers whose first byte has this bit set.
+    WithBit(u8),
+}
+
+/// How the first byte of a transaction and the registers themselves go over the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Framing {
+    /// The register address is the low `address_bits` bits of the first byte.
+    pub address_bits: u8,
+    /// The bit of the first byte that gives the direction.
+    pub direction_bit: u8,
+    /// Whether `direction_bit` being set means a read rather than a write.
+    pub read_when_set: bool,
+    pub increment: Increment,
+    /// Register width, 1 to 4 bytes.
+    pub bytes: u8,
+    pub big_endian: bool,
+}
+
+impl Default for Framing {
+    /// The usual sensor framing: a 7-bit address with bit 7 set for reads,
+    /// auto-incrementing byte-wide registers.
+    fn default() -> Self {
+        Self {
+            address_bits: 7,
+            direction_bit: 7,
+            read_when_set: true,
+            increment: Increment::Always,
+            bytes: 1,
+            big_endian: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Access {
+    #[default]
+    ReadWrite,
+    /// Writes are ignored.
+    ReadOnly,
+    /// Reads return zero.
+    WriteOnly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Register {
+    pub address: u8,
+    pub reset: u32,
+    pub access: Access,
+    /// Reading the register sets it back to zero.
+    pub clear_on_read: bool,
+}
+
+impl Register {
+    pub fn new(address: u8, reset: u32) -> Self {
+        Self {
+            address,
+            reset,
+            access: Access::ReadWrite,
+            clear_on_read: false,
+        }
+    }
+}
+
+struct Slot {
+    register: Register,
+    value: u32,
+}
+
+/// The register a transaction is on and how far through it the data bytes are.
+struct Transfer {
+    address: u8,
+    read: bool,
+    increment: bool,
+    byte: u8,
+    /// The value being read out, or the bytes written so far.
+    value: u32,
+}
+
+pub struct RegisterSpi {
+    framing: Framing,
+    slots: Vec<Option<Slot>>,
+    transfer: Option<Transfer>,
+}
+
+impl RegisterSpi {
+    /// 128 read-write byte registers with the [default framing](Framing::default),
+    /// starting from `(address, value)` pairs; every other register reads 0.
+    /// Only the low 7 bits of each address are used.
+    pub fn new(registers: impl IntoIterator<Item = (u8, u8)>) -> Self {
+        let mut spi = Self::with_map(
+            Framing::default(),
+            (0..128).map(|address| Register::new(address, 0)),
+        );
+        for (address, value) in registers {
+            if let Some(slot) = &mut spi.slots[usize::from(address & 0x7f)] {
+                slot.register.reset = u32::from(value);
+                slot.value = u32::from(value);
+            }
+        }
+        spi
+    }
+
+    /// Only the listed registers exist; other addresses read 0 and ignore writes.
+    /// Addresses beyond `framing.address_bits` are dropped.
+    pub fn with_map(framing: Framing, registers: impl IntoIterator<Item = Register>) -> Self {
+        let mut slots: Vec<Option<Slot>> =
+            (0..1usize << framing.address_bits).map(|_| None).collect();
+        for register in registers {
+            if let Some(slot) = slots.get_mut(usize::from(register.address)) {
+                *slot = Some(Slot {
+                    register,
+                    value: register.reset,
+                });
+            }
+        }
+        Self {
+            framing,
+            slots,
+            transfer: None,
+        }
+    }
+
+    /// The current value of a register, regardless of its access.
+    pub fn register(&self, address: u8) -> Option<u32> {
+        let slot = self.slots.get(usize::from(address))?.as_ref()?;
+        Some(slot.value)
+    }
+
+    /// Puts every register back to its reset value.
+    pub fn reset(&mut self) {
+        for slot in self.slots.iter_mut().flatten() {
+            slot.value = slot.register.reset;
+        }
+    }
+
+    fn read(&mut self, address: u8) -> u32 {
+        let Some(Some(slot)) = self.slots.get_mut(usize::from(address)) else {
+            return 0;
+        };
+        if slot.register.access == Access::WriteOnly {
+            return 0;
+        }
+        let value = slot.value;
+        if slot.register.clear_on_read {
+            slot.value = 0;
+        }
+        value
+    }
+
+    fn write(&mut self, address: u8, value: u32) {
+        if let Some(Some(slot)) = self.slots.get_mut(usize::from(address))
+            && slot.register.access != Access::ReadOnly
+        {
+            slot.value = value;
+        }
+    }
+
+    /// Bit offset of data byte `byte` within a register.
+    fn shift(&self, byte: u8) -> u32 {
+        let index = if self.framing.big_endian {
+            self.framing.bytes - 1 - byte
+        } else {
+            byte
+        };
+        u32::from(index) * 8
+    }
+}
+
+impl SimSpi for RegisterSpi {
+    fn select(&mut self) {
+        self.transfer = None;
+    }
+
+    fn exchange(&mut self, mosi: u8) -> u8 {
+        let framing = self.framing;
+        let mask = ((1u16 << framing.address_bits) - 1) as u8;
+        let Some(mut transfer) = self.transfer.take() else {
+            let direction = mosi >> framing.direction_bit & 1 == 1;
+            self.transfer = Some(Transfer {
+                address: mosi & mask,
+                read: direction == framing.read_when_set,
+                increment: match framing.increment {
+                    Increment::Never => false,
+                    Increment::Always => true,
+                    Increment::WithBit(bit) => mosi >> bit & 1 == 1,
+                },
+                byte: 0,
+                value: 0,
+            });
+            return 0;
+        };
+
+        let shift = self.shift(transfer.byte);
+        let miso = if transfer.read {
+            if transfer.byte == 0 {
+                transfer.value = self.read(transfer.address);
+            }
+            (transfer.value >> shift) as u8
+        } else {
+            transfer.value |= u32::from(mosi) << shift;
+            0
+        };
+
+        transfer.byte += 1;
+        if transfer.byte == framing.bytes {
+            if !transfer.read {
+                self.write(transfer.address, transfer.value);
+            }
+            transfer.byte = 0;
+            transfer.value = 0;
+            if transfer.increment {
+                transfer.address = transfer.address.wrapping_add(1) & mask;
+            }
+        }
+        self.transfer = Some(transfer);
+        miso
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Runs one transaction and returns the bytes clocked back.
+    fn transact(spi: &mut RegisterSpi, mosi: &[u8]) -> Vec<u8> {
+        spi.select();
+        let miso = mosi.iter().map(|&byte| spi.exchange(byte)).collect();
+        spi.deselect();
+        miso
+    }
+
+    fn framing(address_bits: u8, increment: Increment) -> Framing {
+        Framing {
+            address_bits,
+            increment,
+            ..Framing::default()
+        }
+    }
+
+    fn counting(framing: Framing) -> RegisterSpi {
+        let count = 1u8 << framing.address_bits;
+        RegisterSpi::with_map(
+            framing,
+            (0..count).map(|address| Register::new(address, u32::from(address) + 0x40)),
+        )
+    }
+
+    #[test]
+    fn the_first_byte_frames_address_and_direction() {
+        let mut spi = RegisterSpi::new([(0x10, 0xaa), (0x11, 0xbb)]);
+        assert_eq!(transact(&mut spi, &[0x90, 0, 0]), [0, 0xaa, 0xbb]);
+        assert_eq!(transact(&mut spi, &[0x10, 0x01, 0x02]), [0, 0, 0]);
+        assert_eq!(
+            (spi.register(0x10), spi.register(0x11)),
+            (Some(0x01), Some(0x02))
+        );
+
+        // A chip that sets bit 7 to write
+        let mut spi = RegisterSpi::with_map(
+            Framing {
+                read_when_set: false,
+                ..Framing::default()
+            },
+            [Register::new(0x05, 0x12)],
+        );
+        assert_eq!(transact(&mut spi, &[0x05, 0]), [0, 0x12]);
+        transact(&mut spi, &[0x85, 0x34]);
+        assert_eq!(spi.register(0x05), Some(0x34));
+
+        // Bits above a 6-bit address aren't part of it
+        let mut spi = counting(framing(6, Increment::Never));
+        assert_eq!(transact(&mut spi, &[0xc5, 0]), [0, 0x45]);
+    }
+
+    #[test]
+    fn the_address_advances_as_framed() {
+        let mut spi = counting(framing(6, Increment::Always));
+        assert_eq!(transact(&mut spi, &[0xbe, 0, 0, 0]), [0, 0x7e, 0x7f, 0x40]);
+
+        let mut spi = counting(framing(6, Increment::Never));
+        assert_eq!(transact(&mut spi, &[0x81, 0, 0]), [0, 0x41, 0x41]);
+
+        let mut spi = counting(framing(6, Increment::WithBit(6)));
+        assert_eq!(transact(&mut spi, &[0x81, 0, 0]), [0, 0x41, 0x41]);
+        assert_eq!(transact(&mut spi, &[0xc1, 0, 0]), [0, 0x41, 0x42]);
+        // Without the bit, every write lands on the same register
+        transact(&mut spi, &[0x02, 0x01, 0x02]);
+        assert_eq!((spi.register(2), spi.register(3)), (Some(0x02), Some(0x43)));
+    }
+
+    #[test]
+    fn wide_registers_go_over_the_wire_in_byte_order() {
+        for (big_endian, wire) in [
+            (true, [0x12, 0x34, 0xab, 0xcd]),
+            (false, [0x34, 0x12, 0xcd, 0xab]),
+        ] {
+            let framing = Framing {
+                bytes: 2,
+                big_endian,
+                ..Framing::default()
+            };
+            let mut spi = RegisterSpi::with_map(
+                framing,
+                [Register::new(0, 0x1234), Register::new(1, 0xabcd)],
+            );
+            let mut read = vec![0x80];
+            read.extend([0; 4]);
+            assert_eq!(transact(&mut spi, &read)[1..], wire);
+
+            let mut write = vec![0x00];
+            write.extend(wire);
+            transact(&mut spi, &[0x00, 0xff, 0xff]);
+            transact(&mut spi, &write);
+            assert_eq!(
+                (spi.register(0), spi.register(1)),
+                (Some(0x1234), Some(0xabcd))
+            );
+
+            // Half a register is never written
+            transact(&mut spi, &[0x01, 0x99]);
+            assert_eq!(spi.register(1), Some(0xabcd));
+        }
+    }
+
+    #[test]
+    fn access_and_clear_on_read() {
+        let registers = [
+            Register {
+                access: Access::ReadOnly,
+                ..Register::new(0, 0xe5)
+            },
+            Register {
+                access: Access::WriteOnly,
+                ..Register::new(1, 0x0f)
+            },
+            Register {
+                access: Access::ReadOnly,
+                clear_on_read: true,
+                ..Register::new(2, 0x83)
+            },
+        ];
+        let mut spi = RegisterSpi::with_map(Framing::default(), registers);
+
+        transact(&mut spi, &[0x00, 0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(spi.register(0), Some(0xe5));
+        assert_eq!(spi.register(1), Some(0x22));
+        assert_eq!(spi.register(2), Some(0x83));
+        assert_eq!(spi.register(3), None);
+
+        assert_eq!(
+            transact(&mut spi, &[0x80, 0, 0, 0, 0]),
+            [0, 0xe5, 0, 0x83, 0]
+        );
+        assert_eq!(transact(&mut spi, &[0x82, 0]), [0, 0]);
+    }
+
+    #[test]
+    fn reset_restores_every_register() {
+        let mut spi = RegisterSpi::with_map(
+            Framing::default(),
+            [
+                Register::new(0, 0x10),
+                Register {
+                    clear_on_read: true,
+                    ..Register::new(1, 0x20)
+                },
+            ],
+        );
+        transact(&mut spi, &[0x00, 0x99]);
+        transact(&mut spi, &[0x81, 0]);
+        assert_eq!((spi.register(0), spi.register(1)), (Some(0x99), Some(0)));
+
+        spi.reset();
+        assert_eq!((spi.register(0), spi.register(1)), (Some(0x10), Some(0x20)));
+    }
+}
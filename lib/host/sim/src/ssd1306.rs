@@ -0,0 +1,387 @@
+//! An SSD1306 OLED controller on SPI. The DC line tells commands (low) from
+//! display data (high), as on the real 4-wire interface.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{Line, SimSpi, png};
+
+const COLUMNS: usize = 128;
+const PAGES: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressingMode {
+    Horizontal,
+    Vertical,
+    Page,
+}
+
+/// What the panel shows, one entry per pixel, row by row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub contrast: u8,
+    pixels: Vec<bool>,
+}
+
+impl Frame {
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x]
+    }
+
+    /// One character per pixel: `#` lit, `.` dark.
+    pub fn to_ascii(&self) -> String {
+        let mut out = String::with_capacity((self.width + 1) * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                out.push(if self.pixel(x, y) { '#' } else { '.' });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Two pixel rows per line using half blocks, to fit a terminal.
+    pub fn to_unicode(&self) -> String {
+        let mut out = String::new();
+        for y in (0..self.height).step_by(2) {
+            for x in 0..self.width {
+                let top = self.pixel(x, y);
+                let bottom = y + 1 < self.height && self.pixel(x, y + 1);
+                out.push(match (top, bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// An 8-bit grayscale PNG; lit pixels get brighter with the contrast setting.
+    pub fn to_png(&self) -> Vec<u8> {
+        let lit = 55 + (u16::from(self.contrast) * 200 / 255) as u8;
+        let rows: Vec<Vec<u8>> = (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| if self.pixel(x, y) { lit } else { 0 })
+                    .collect()
+            })
+            .collect();
+        png::grayscale(self.width as u32, self.height as u32, &rows)
+    }
+}
+
+/// Called with every completed frame.
+pub type FrameSink = Box<dyn FnMut(&Frame) + Send + 'static>;
+
+/// A frame is complete whenever the data pointer wraps back to the start of the
+/// address window, i.e. after every full buffer flush, and whenever the display
+/// is switched on or off. Page addressing has no window to wrap, so there a
+/// frame is complete when the host deselects the chip after writing data.
+pub struct Ssd1306 {
+    dc: Line,
+    width: usize,
+    height: usize,
+    ram: [[u8; COLUMNS]; PAGES],
+    mode: AddressingMode,
+    columns: (u8, u8),
+    pages: (u8, u8),
+    column: u8,
+    page: u8,
+    start_line: u8,
+    contrast: u8,
+    display_on: bool,
+    inverted: bool,
+    entire_on: bool,
+    segment_remap: bool,
+    com_remap: bool,
+    command: Vec<u8>,
+    /// Data written in page addressing mode since the last frame.
+    page_written: bool,
+    sink: FrameSink,
+}
+
+impl Ssd1306 {
+    /// A panel of `width` x `height` pixels (at most 128 x 64) with its DC pin on
+    /// `dc`, handing every completed frame to `sink`.
+    pub fn new(dc: Line, width: usize, height: usize, sink: FrameSink) -> Self {
+        assert!(width <= COLUMNS && height <= PAGES * 8);
+        Self {
+            dc,
+            width,
+            height,
+            ram: [[0; COLUMNS]; PAGES],
+            mode: AddressingMode::Page,
+            columns: (0, COLUMNS as u8 - 1),
+            pages: (0, PAGES as u8 - 1),
+            column: 0,
+            page: 0,
+            start_line: 0,
+            contrast: 0x7f,
+            display_on: false,
+            inverted: false,
+            entire_on: false,
+            segment_remap: false,
+            com_remap: false,
+            command: Vec::new(),
+            page_written: false,
+            sink,
+        }
+    }
+
+    pub fn mode(&self) -> AddressingMode {
+        self.mode
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.display_on
+    }
+
+    /// The panel as it looks now. Modules are wired so that segment remap and
+    /// reversed COM scan (`0xA1`, `0xC8`) show the RAM upright.
+    pub fn frame(&self) -> Frame {
+        let mut pixels = vec![false; self.width * self.height];
+        if self.display_on {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let column = if self.segment_remap {
+                        x
+                    } else {
+                        self.width - 1 - x
+                    };
+                    let row = if self.com_remap {
+                        y
+                    } else {
+                        self.height - 1 - y
+                    };
+                    let row = (row + usize::from(self.start_line)) % (PAGES * 8);
+                    let bit = self.ram[row / 8][column] >> (row % 8) & 1 == 1;
+                    pixels[y * self.width + x] = (bit || self.entire_on) != self.inverted;
+                }
+            }
+        }
+        Frame {
+            width: self.width,
+            height: self.height,
+            contrast: self.contrast,
+            pixels,
+        }
+    }
+
+    fn emit(&mut self) {
+        self.page_written = false;
+        let frame = self.frame();
+        (self.sink)(&frame);
+    }
+
+    /// Bytes a command takes, including its opcode.
+    fn command_len(opcode: u8) -> usize {
+        match opcode {
+            0x20 | 0x81 | 0x8d | 0xa8 | 0xd3 | 0xd5 | 0xd9 | 0xda | 0xdb => 2,
+            0x21 | 0x22 | 0xa3 => 3,
+            0x29 | 0x2a => 6,
+            0x26 | 0x27 => 7,
+            _ => 1,
+        }
+    }
+
+    fn run_command(&mut self) {
+        let command = core::mem::take(&mut self.command);
+        match command[..] {
+            [low @ 0x00..=0x0f] => self.column = (self.column & 0x70) | low,
+            // Only 3 bits of the high nibble exist, so the column stays below 128
+            [high @ 0x10..=0x1f] => self.column = (self.column & 0x0f) | ((high & 0x07) << 4),
+            [0x20, mode] => {
+                self.mode = match mode & 0b11 {
+                    0 => AddressingMode::Horizontal,
+                    1 => AddressingMode::Vertical,
+                    _ => AddressingMode::Page,
+                }
+            }
+            [0x21, start, end] => {
+                self.columns = (start & 0x7f, end & 0x7f);
+                self.column = self.columns.0;
+            }
+            [0x22, start, end] => {
+                self.pages = (start & 0x07, end & 0x07);
+                self.page = self.pages.0;
+            }
+            [line @ 0x40..=0x7f] => self.start_line = line & 0x3f,
+            [0x81, contrast] => self.contrast = contrast,
+            [0xa0] => self.segment_remap = false,
+            [0xa1] => self.segment_remap = true,
+            [0xa4] => self.entire_on = false,
+            [0xa5] => self.entire_on = true,
+            [0xa6] => self.inverted = false,
+            [0xa7] => self.inverted = true,
+            [0xae] => {
+                self.display_on = false;
+                self.emit();
+            }
+            [0xaf] => {
+                self.display_on = true;
+                self.emit();
+            }
+            [page @ 0xb0..=0xb7] => self.page = page & 0x07,
+            [0xc0] => self.com_remap = false,
+            [0xc8] => self.com_remap = true,
+            // Charge pump, timing, scrolling and panel wiring don't change the picture here
+            _ => {}
+        }
+    }
+
+    fn write_data(&mut self, byte: u8) {
+        self.ram[usize::from(self.page)][usize::from(self.column)] = byte;
+        let wrapped = match self.mode {
+            AddressingMode::Page => {
+                self.column = (self.column + 1) % COLUMNS as u8;
+                self.page_written = true;
+                false
+            }
+            AddressingMode::Horizontal => {
+                advance(&mut self.column, self.columns) && advance(&mut self.page, self.pages)
+            }
+            AddressingMode::Vertical => {
+                advance(&mut self.page, self.pages) && advance(&mut self.column, self.columns)
+            }
+        };
+        if wrapped {
+            self.emit();
+        }
+    }
+}
+
+/// Steps `pointer` through `start..=end`, returning true when it wraps.
+fn advance(pointer: &mut u8, (start, end): (u8, u8)) -> bool {
+    if *pointer >= end {
+        *pointer = start;
+        true
+    } else {
+        *pointer += 1;
+        false
+    }
+}
+
+impl SimSpi for Ssd1306 {
+    fn exchange(&mut self, mosi: u8) -> u8 {
+        if self.dc.is_high() {
+            self.write_data(mosi);
+        } else {
+            self.command.push(mosi);
+            if self.command.len() >= Self::command_len(self.command[0]) {
+                self.run_command();
+            }
+        }
+        0
+    }
+
+    fn deselect(&mut self) {
+        if self.page_written {
+            self.emit();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{SimOutputPin, SimSpiDevice};
+    use alloc::sync::Arc;
+    use embedded_hal::digital::OutputPin;
+    use embedded_hal::spi::SpiDevice;
+    use std::sync::Mutex;
+
+    /// A panel on a simulated bus, its DC pin, and every frame it has emitted.
+    fn panel(
+        width: usize,
+        height: usize,
+    ) -> (SimSpiDevice<Ssd1306>, SimOutputPin, Arc<Mutex<Vec<Frame>>>) {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let sink = frames.clone();
+        let dc = Line::new(false);
+        let display = Ssd1306::new(
+            dc.clone(),
+            width,
+            height,
+            Box::new(move |frame| sink.lock().unwrap().push(frame.clone())),
+        );
+        (SimSpiDevice::new(display), SimOutputPin::new(dc), frames)
+    }
+
+    fn send(spi: &mut SimSpiDevice<Ssd1306>, dc: &mut SimOutputPin, commands: &[u8], data: &[u8]) {
+        dc.set_low().unwrap();
+        spi.write(commands).unwrap();
+        dc.set_high().unwrap();
+        spi.write(data).unwrap();
+    }
+
+    #[test]
+    fn renders_a_flushed_buffer() {
+        let (mut spi, mut dc, frames) = panel(8, 8);
+        let init = [
+            0xae, // display off
+            0x20, 0x00, // horizontal addressing
+            0x21, 0, 7, // columns 0..=7
+            0x22, 0, 0, // page 0 only
+            0xa1, 0xc8, // upright
+            0xaf, // display on
+        ];
+        send(
+            &mut spi,
+            &mut dc,
+            &init,
+            &[0xff, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xff],
+        );
+
+        let frames = frames.lock().unwrap();
+        // Off, on with empty RAM, then the flush
+        assert_eq!(frames.len(), 3);
+        assert_eq!(
+            frames[2].to_ascii(),
+            "########\n\
+             #......#\n\
+             #......#\n\
+             #......#\n\
+             #......#\n\
+             #......#\n\
+             #......#\n\
+             ########\n"
+        );
+    }
+
+    #[test]
+    fn page_mode_emits_a_frame_per_transaction() {
+        let (mut spi, mut dc, frames) = panel(COLUMNS, 8);
+        send(
+            &mut spi,
+            &mut dc,
+            &[0xa1, 0xc8, 0xaf, 0xb0, 0x00, 0x10],
+            &[0x01; 4],
+        );
+        assert_eq!(frames.lock().unwrap().len(), 2);
+        assert!(frames.lock().unwrap()[1].pixel(3, 0));
+        assert!(!frames.lock().unwrap()[1].pixel(4, 0));
+    }
+
+    #[test]
+    fn column_high_nibble_stays_on_the_panel() {
+        let (mut spi, mut dc, frames) = panel(COLUMNS, 8);
+        // 0x1f sets the high nibble to 7 on the chip, not 15
+        send(
+            &mut spi,
+            &mut dc,
+            &[0xa1, 0xc8, 0xaf, 0xb0, 0x0f, 0x1f],
+            &[0x01],
+        );
+        let frames = frames.lock().unwrap();
+        assert!(frames.last().unwrap().pixel(0x7f, 0));
+    }
+}
@@ -15,6 +15,7 @@ wasmtime::component::bindgen!({
 });
 
 use wasi::spi::spi;
+pub use wasi::spi::spi::{BitOrder, BusConfig, Error, Limit, Mode};
 
 pub struct ActiveSpiDriver {
     pub id: usize,
@@ -55,6 +56,54 @@ impl<T: SpiDevice<u8>> ErasedSpiDevice for T {
     }
 }
 
+/// A device whose bus settings can be changed while the guest is running.
+pub trait ConfigurableSpiDevice: ErasedSpiDevice {
+    fn configure(&mut self, config: &BusConfig) -> Result<(), spi::Error>;
+}
+
+/// Pairs a device with the backend-specific routine that reconfigures its bus.
+pub struct Reconfigurable<D, F> {
+    device: D,
+    apply: F,
+}
+
+impl<D, F> Reconfigurable<D, F>
+where
+    F: FnMut(&mut D, &BusConfig) -> Result<(), spi::Error>,
+{
+    pub fn new(device: D, apply: F) -> Self {
+        Self { device, apply }
+    }
+}
+
+impl<D: ErasedSpiDevice, F> ErasedSpiDevice for Reconfigurable<D, F> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), spi::Error> {
+        self.device.read(buf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), spi::Error> {
+        self.device.write(data)
+    }
+
+    fn transfer(&mut self, rx: &mut [u8], tx: &[u8]) -> Result<(), spi::Error> {
+        self.device.transfer(rx, tx)
+    }
+
+    fn transaction(&mut self, operations: &mut [HalOperation<'_, u8>]) -> Result<(), spi::Error> {
+        self.device.transaction(operations)
+    }
+}
+
+impl<D, F> ConfigurableSpiDevice for Reconfigurable<D, F>
+where
+    D: ErasedSpiDevice,
+    F: FnMut(&mut D, &BusConfig) -> Result<(), spi::Error>,
+{
+    fn configure(&mut self, config: &BusConfig) -> Result<(), spi::Error> {
+        (self.apply)(&mut self.device, config)
+    }
+}
+
 enum TransactionBuffer {
     Read(Vec<u8>),
     Write(Vec<u8>),
@@ -94,9 +143,104 @@ impl TransactionBuffer {
     }
 }
 
+/// Upper bounds on what a guest may ask of a single device, checked before
+/// any buffer is allocated on its behalf.
+#[derive(Clone, Copy, Debug)]
+pub struct SpiLimits {
+    pub max_bytes_per_op: u64,
+    pub max_ops_per_transaction: u32,
+    pub max_bytes_per_transaction: u64,
+}
+
+impl Default for SpiLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes_per_op: 4096,
+            max_ops_per_transaction: 32,
+            max_bytes_per_transaction: 16 * 1024,
+        }
+    }
+}
+
+impl SpiLimits {
+    fn check_op(&self, len: u64) -> Result<(), spi::Error> {
+        if len > self.max_bytes_per_op {
+            return Err(spi::Error::LimitExceeded(spi::Limit::BytesPerOperation));
+        }
+        Ok(())
+    }
+
+    fn check_transaction(&self, operations: &[spi::Operation]) -> Result<(), spi::Error> {
+        if operations.len() > self.max_ops_per_transaction as usize {
+            return Err(spi::Error::LimitExceeded(
+                spi::Limit::OperationsPerTransaction,
+            ));
+        }
+
+        let mut total: u64 = 0;
+        for op in operations {
+            let len = match op {
+                spi::Operation::Read(len) => *len,
+                spi::Operation::Write(data) | spi::Operation::Transfer(data) => data.len() as u64,
+                spi::Operation::DelayNs(_) => 0,
+            };
+            self.check_op(len)?;
+            total = total.saturating_add(len);
+        }
+
+        if total > self.max_bytes_per_transaction {
+            return Err(spi::Error::LimitExceeded(spi::Limit::BytesPerTransaction));
+        }
+        Ok(())
+    }
+}
+
+/// Bus settings a guest may switch between at runtime.
+#[derive(Clone, Debug)]
+pub struct SpiConfigBounds {
+    pub min_frequency: u32,
+    pub max_frequency: u32,
+    pub modes: Vec<Mode>,
+    pub bit_orders: Vec<BitOrder>,
+}
+
+impl SpiConfigBounds {
+    /// Only allows the configuration the device was initialised with.
+    pub fn fixed(config: BusConfig) -> Self {
+        Self {
+            min_frequency: config.frequency,
+            max_frequency: config.frequency,
+            modes: vec![config.mode],
+            bit_orders: vec![config.bit_order],
+        }
+    }
+
+    fn apply(&self, config: BusConfig) -> Result<BusConfig, spi::Error> {
+        if !self.modes.contains(&config.mode) || !self.bit_orders.contains(&config.bit_order) {
+            return Err(spi::Error::UnsupportedConfig);
+        }
+
+        Ok(BusConfig {
+            frequency: config
+                .frequency
+                .max(self.min_frequency)
+                .min(self.max_frequency),
+            ..config
+        })
+    }
+}
+
+/// A device the guest may open, as granted by the host policy.
+pub struct SpiHardware {
+    pub name: String,
+    pub limits: SpiLimits,
+    pub bounds: SpiConfigBounds,
+    pub device: Box<dyn ConfigurableSpiDevice + Send + 'static>,
+}
+
 pub struct SpiCtx {
     pub table: ResourceTable,
-    pub hardware: Vec<(String, Box<dyn ErasedSpiDevice + Send + 'static>)>,
+    pub hardware: Vec<SpiHardware>,
 }
 
 pub trait SpiView {
@@ -107,7 +251,7 @@ impl SpiCtx {
     fn get_hw(
         &mut self,
         handle: &Resource<ActiveSpiDriver>,
-    ) -> Result<&mut Box<dyn ErasedSpiDevice + Send + 'static>, spi::Error> {
+    ) -> Result<&mut SpiHardware, spi::Error> {
         let id = self
             .table
             .get(handle)
@@ -116,7 +260,6 @@ impl SpiCtx {
 
         self.hardware
             .get_mut(id)
-            .map(|(_, hw)| hw)
             .ok_or_else(|| spi::Error::Other("HW unavailable".into()))
     }
 }
@@ -127,7 +270,7 @@ impl spi::Host for SpiCtx {
             .hardware
             .iter()
             .enumerate()
-            .find(|(_, (hw_name, _))| hw_name == &name)
+            .find(|(_, hw)| hw.name == name)
             .ok_or_else(|| spi::Error::Other(alloc::format!("Device '{}' not found", name)))?;
 
         self.table
@@ -138,8 +281,11 @@ impl spi::Host for SpiCtx {
 
 impl spi::HostSpiDevice for SpiCtx {
     fn read(&mut self, handle: Resource<ActiveSpiDriver>, len: u64) -> Result<Vec<u8>, spi::Error> {
+        let hw = self.get_hw(&handle)?;
+        hw.limits.check_op(len)?;
+
         let mut buf = vec![0u8; len as usize];
-        self.get_hw(&handle)?.read(&mut buf)?;
+        hw.device.read(&mut buf)?;
         Ok(buf)
     }
 
@@ -148,7 +294,9 @@ impl spi::HostSpiDevice for SpiCtx {
         handle: Resource<ActiveSpiDriver>,
         data: Vec<u8>,
     ) -> Result<(), spi::Error> {
-        self.get_hw(&handle)?.write(&data)
+        let hw = self.get_hw(&handle)?;
+        hw.limits.check_op(data.len() as u64)?;
+        hw.device.write(&data)
     }
 
     fn transfer(
@@ -156,8 +304,11 @@ impl spi::HostSpiDevice for SpiCtx {
         handle: Resource<ActiveSpiDriver>,
         data: Vec<u8>,
     ) -> Result<Vec<u8>, spi::Error> {
+        let hw = self.get_hw(&handle)?;
+        hw.limits.check_op(data.len() as u64)?;
+
         let mut rx = vec![0u8; data.len()];
-        self.get_hw(&handle)?.transfer(&mut rx, &data)?;
+        hw.device.transfer(&mut rx, &data)?;
         Ok(rx)
     }
 
@@ -167,6 +318,7 @@ impl spi::HostSpiDevice for SpiCtx {
         operations: Vec<spi::Operation>,
     ) -> Result<Vec<spi::OperationResult>, spi::Error> {
         let hw = self.get_hw(&handle)?;
+        hw.limits.check_transaction(&operations)?;
 
         let mut buffers: Vec<_> = operations
             .into_iter()
@@ -175,7 +327,7 @@ impl spi::HostSpiDevice for SpiCtx {
 
         let mut hal_ops: Vec<_> = buffers.iter_mut().map(|b| b.as_hal_op()).collect();
 
-        hw.transaction(&mut hal_ops)?;
+        hw.device.transaction(&mut hal_ops)?;
 
         Ok(buffers
             .into_iter()
@@ -183,6 +335,17 @@ impl spi::HostSpiDevice for SpiCtx {
             .collect())
     }
 
+    fn configure(
+        &mut self,
+        handle: Resource<ActiveSpiDriver>,
+        config: BusConfig,
+    ) -> Result<BusConfig, spi::Error> {
+        let hw = self.get_hw(&handle)?;
+        let applied = hw.bounds.apply(config)?;
+        hw.device.configure(&applied)?;
+        Ok(applied)
+    }
+
     fn drop(&mut self, rep: Resource<ActiveSpiDriver>) -> wasmtime::Result<()> {
         self.table.delete(rep)?;
         Ok(())
@@ -192,3 +355,195 @@ impl spi::HostSpiDevice for SpiCtx {
 pub fn add_to_linker<T: SpiView + 'static>(linker: &mut Linker<T>) -> wasmtime::Result<()> {
     spi::add_to_linker::<T, wasmtime::component::HasSelf<SpiCtx>>(linker, |host| host.spi_ctx())
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::sync::Arc;
+    use core::convert::Infallible;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use embedded_hal::spi::ErrorType;
+    use spi::{Host, HostSpiDevice, Operation, OperationResult};
+
+    /// Counts the transactions that reach the bus.
+    #[derive(Clone, Default)]
+    struct Bus(Arc<AtomicUsize>);
+
+    impl ErrorType for Bus {
+        type Error = Infallible;
+    }
+
+    impl SpiDevice<u8> for Bus {
+        fn transaction(
+            &mut self,
+            _operations: &mut [HalOperation<'_, u8>],
+        ) -> Result<(), Infallible> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    const LIMITS: SpiLimits = SpiLimits {
+        max_bytes_per_op: 4,
+        max_ops_per_transaction: 3,
+        max_bytes_per_transaction: 6,
+    };
+
+    fn bus_config(frequency: u32, mode: Mode, bit_order: BitOrder) -> BusConfig {
+        BusConfig {
+            frequency,
+            mode,
+            bit_order,
+        }
+    }
+
+    fn ctx(bus: &Bus) -> SpiCtx {
+        let config = bus_config(1_000_000, Mode::Mode0, BitOrder::MsbFirst);
+        SpiCtx {
+            table: ResourceTable::new(),
+            hardware: vec![SpiHardware {
+                name: "sensor".to_string(),
+                limits: LIMITS,
+                bounds: SpiConfigBounds::fixed(config),
+                device: Box::new(Reconfigurable::new(
+                    bus.clone(),
+                    |_: &mut Bus, _: &BusConfig| Ok(()),
+                )),
+            }],
+        }
+    }
+
+    fn exceeded(result: Result<impl core::fmt::Debug, spi::Error>) -> Limit {
+        match result {
+            Err(spi::Error::LimitExceeded(limit)) => limit,
+            other => panic!("expected a limit to be exceeded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oversized_operations_are_rejected_before_allocating() {
+        let bus = Bus::default();
+        let mut ctx = ctx(&bus);
+        let mut open = || ctx.open("sensor".to_string()).unwrap();
+        let (a, b, c, d) = (open(), open(), open(), open());
+
+        assert_eq!(exceeded(ctx.read(a, u64::MAX)), Limit::BytesPerOperation);
+        assert_eq!(exceeded(ctx.write(b, vec![0; 5])), Limit::BytesPerOperation);
+        assert_eq!(
+            exceeded(ctx.transfer(c, vec![0; 5])),
+            Limit::BytesPerOperation
+        );
+        assert_eq!(
+            exceeded(ctx.transaction(
+                d,
+                vec![Operation::Write(vec![1]), Operation::Read(u64::MAX)]
+            )),
+            Limit::BytesPerOperation
+        );
+        assert_eq!(bus.0.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn transactions_are_bounded_in_operations_and_total_bytes() {
+        let bus = Bus::default();
+        let mut ctx = ctx(&bus);
+        let (a, b) = (
+            ctx.open("sensor".to_string()).unwrap(),
+            ctx.open("sensor".to_string()).unwrap(),
+        );
+
+        let delays = vec![Operation::DelayNs(1); 4];
+        assert_eq!(
+            exceeded(ctx.transaction(a, delays)),
+            Limit::OperationsPerTransaction
+        );
+        let each_under = vec![
+            Operation::Read(4),
+            Operation::Write(vec![1, 2]),
+            Operation::Transfer(vec![3]),
+        ];
+        assert_eq!(
+            exceeded(ctx.transaction(b, each_under)),
+            Limit::BytesPerTransaction
+        );
+        assert_eq!(bus.0.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn delays_count_as_no_bytes() {
+        let bus = Bus::default();
+        let mut ctx = ctx(&bus);
+        let handle = ctx.open("sensor".to_string()).unwrap();
+
+        let results = ctx
+            .transaction(
+                handle,
+                vec![
+                    Operation::Read(4),
+                    Operation::DelayNs(u32::MAX),
+                    Operation::Write(vec![1, 2]),
+                ],
+            )
+            .unwrap();
+        assert!(matches!(
+            &results[..],
+            [
+                OperationResult::Read(read),
+                OperationResult::Delay,
+                OperationResult::Write
+            ] if read.len() == 4
+        ));
+        assert_eq!(bus.0.load(Ordering::Relaxed), 1);
+    }
+
+    fn bounds() -> SpiConfigBounds {
+        SpiConfigBounds {
+            min_frequency: 100_000,
+            max_frequency: 8_000_000,
+            modes: vec![Mode::Mode0, Mode::Mode3],
+            bit_orders: vec![BitOrder::MsbFirst],
+        }
+    }
+
+    #[test]
+    fn frequencies_are_clamped_to_the_granted_range() {
+        for (asked, applied) in [
+            (0, 100_000),
+            (100_000, 100_000),
+            (1_000_000, 1_000_000),
+            (8_000_000, 8_000_000),
+            (u32::MAX, 8_000_000),
+        ] {
+            let config = bounds()
+                .apply(bus_config(asked, Mode::Mode3, BitOrder::MsbFirst))
+                .unwrap();
+            assert_eq!(config.frequency, applied, "asked for {} Hz", asked);
+            assert_eq!(
+                (config.mode, config.bit_order),
+                (Mode::Mode3, BitOrder::MsbFirst)
+            );
+        }
+
+        let fixed = SpiConfigBounds::fixed(bus_config(500_000, Mode::Mode2, BitOrder::LsbFirst));
+        let config = fixed
+            .apply(bus_config(20_000_000, Mode::Mode2, BitOrder::LsbFirst))
+            .unwrap();
+        assert_eq!(config.frequency, 500_000);
+    }
+
+    #[test]
+    fn ungranted_modes_and_bit_orders_are_unsupported() {
+        for config in [
+            bus_config(1_000_000, Mode::Mode1, BitOrder::MsbFirst),
+            bus_config(1_000_000, Mode::Mode2, BitOrder::MsbFirst),
+            bus_config(1_000_000, Mode::Mode0, BitOrder::LsbFirst),
+        ] {
+            assert!(matches!(
+                bounds().apply(config),
+                Err(spi::Error::UnsupportedConfig)
+            ));
+        }
+    }
+}
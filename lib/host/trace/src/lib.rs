@@ -0,0 +1,1382 @@
+//! Records what a guest does with its devices and replays it. The recording
+//! wrappers log every SPI call, output pin change and input pin read, with its
+//! data, result and a timestamp, as one line of text per call:
+//!
+//! ```text
+//! 1042 spi sensor transfer x:fa000000/00805000
+//! 1310 spi sensor transaction w:f7 r:5a ! overrun
+//! 2007 gpio DC set-high
+//! 2950 gpio_input BUTTON wait falling 250 1
+//! ```
+//!
+//! Labels are written as single words, so only [`is_recordable`] labels can be
+//! traced.
+//!
+//! The replay devices play a [`Trace`] back: reads return what was recorded,
+//! and calls that don't match the recording are reported as [`Divergence`]s.
+//! Each device replays its own calls in order; how calls to different devices
+//! interleaved, and when they happened, is not checked.
+#![no_std]
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use embedded_hal::spi::Operation;
+
+use gpio::{Edge, ErasedEdgePin, ErasedInputPin, ErasedOutputPin};
+use spi::{BusConfig, ConfigurableSpiDevice, ErasedSpiDevice, Limit};
+
+// ==========================================
+// EVENTS
+// ==========================================
+
+/// Microseconds on the host's clock.
+pub type Clock = Box<dyn Fn() -> u64 + Send + 'static>;
+
+/// Receives every call a recording device makes, as it returns.
+pub type Sink = Box<dyn FnMut(&Event) + Send + 'static>;
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// When the call started.
+    pub micros: u64,
+    pub label: String,
+    pub call: Call,
+}
+
+#[derive(Clone, Debug)]
+pub enum Call {
+    Spi {
+        method: Method,
+        ops: Vec<Op>,
+        error: Option<spi::Error>,
+    },
+    /// `set_high` or `set_low` on an output.
+    Output {
+        high: bool,
+        error: Option<gpio::Error>,
+    },
+    /// `is_high` on an input.
+    Level { result: Result<bool, gpio::Error> },
+    /// `wait_for_edge` on an input; true if the edge came in time.
+    Edge {
+        edge: Edge,
+        timeout_ms: u32,
+        result: Result<bool, gpio::Error>,
+    },
+}
+
+/// The [`ErasedSpiDevice`] method a guest called.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Read,
+    Write,
+    Transfer,
+    Transaction,
+}
+
+/// One operation of an SPI call, with the data that went each way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    Read(Vec<u8>),
+    Write(Vec<u8>),
+    Transfer { tx: Vec<u8>, rx: Vec<u8> },
+    TransferInPlace { tx: Vec<u8>, rx: Vec<u8> },
+    DelayNs(u32),
+}
+
+impl Op {
+    /// Whether the guest asked for the same thing: the same kind of operation,
+    /// data written and number of bytes read.
+    fn same_request(&self, other: &Op) -> bool {
+        match (self, other) {
+            (Op::Read(a), Op::Read(b)) => a.len() == b.len(),
+            (Op::Write(a), Op::Write(b)) => a == b,
+            (Op::Transfer { tx: a, rx: ra }, Op::Transfer { tx: b, rx: rb }) => {
+                a == b && ra.len() == rb.len()
+            }
+            (Op::TransferInPlace { tx: a, .. }, Op::TransferInPlace { tx: b, .. }) => a == b,
+            (Op::DelayNs(a), Op::DelayNs(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// The bytes this operation read, if any.
+    fn received(&self) -> Option<&[u8]> {
+        match self {
+            Op::Read(rx) | Op::Transfer { rx, .. } | Op::TransferInPlace { rx, .. } => Some(rx),
+            Op::Write(_) | Op::DelayNs(_) => None,
+        }
+    }
+}
+
+/// The operations as they stand; `TransferInPlace` shows the buffer as both
+/// `tx` and `rx`, so taking this before and after a call gives both sides.
+fn snapshot(operations: &[Operation<'_, u8>]) -> Vec<Op> {
+    operations
+        .iter()
+        .map(|operation| match operation {
+            Operation::Read(buf) => Op::Read(buf.to_vec()),
+            Operation::Write(data) => Op::Write(data.to_vec()),
+            Operation::Transfer(rx, tx) => Op::Transfer {
+                tx: tx.to_vec(),
+                rx: rx.to_vec(),
+            },
+            Operation::TransferInPlace(buf) => Op::TransferInPlace {
+                tx: buf.to_vec(),
+                rx: buf.to_vec(),
+            },
+            Operation::DelayNs(ns) => Op::DelayNs(*ns),
+        })
+        .collect()
+}
+
+/// Both sides of a call from [`snapshot`]s taken before and after it.
+fn merge(before: Vec<Op>, after: Vec<Op>) -> Vec<Op> {
+    after
+        .into_iter()
+        .zip(before)
+        .map(|(after, before)| match (after, before) {
+            (Op::TransferInPlace { rx, .. }, Op::TransferInPlace { tx, .. }) => {
+                Op::TransferInPlace { tx, rx }
+            }
+            (after, _) => after,
+        })
+        .collect()
+}
+
+impl Call {
+    /// The policy section the device's label is granted in.
+    pub fn section(&self) -> &'static str {
+        match self {
+            Call::Spi { .. } => "spi",
+            Call::Output { .. } => "gpio",
+            Call::Level { .. } | Call::Edge { .. } => "gpio_input",
+        }
+    }
+}
+
+// ==========================================
+// TEXT FORMAT
+// ==========================================
+
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn spi_error_name(error: &spi::Error) -> String {
+    match error {
+        spi::Error::Overrun => "overrun".to_string(),
+        spi::Error::ModeFault => "mode-fault".to_string(),
+        spi::Error::FrameFormat => "frame-format".to_string(),
+        spi::Error::ChipSelectFault => "chip-select-fault".to_string(),
+        spi::Error::LimitExceeded(limit) => format!(
+            "limit-exceeded:{}",
+            match limit {
+                Limit::BytesPerOperation => "bytes-per-operation",
+                Limit::OperationsPerTransaction => "operations-per-transaction",
+                Limit::BytesPerTransaction => "bytes-per-transaction",
+            }
+        ),
+        spi::Error::UnsupportedConfig => "unsupported-config".to_string(),
+        spi::Error::Other(message) => format!("other:{}", message.replace('\n', " ")),
+    }
+}
+
+fn parse_spi_error(text: &str) -> Option<spi::Error> {
+    Some(match text.split_once(':') {
+        Some(("limit-exceeded", limit)) => spi::Error::LimitExceeded(match limit {
+            "bytes-per-operation" => Limit::BytesPerOperation,
+            "operations-per-transaction" => Limit::OperationsPerTransaction,
+            "bytes-per-transaction" => Limit::BytesPerTransaction,
+            _ => return None,
+        }),
+        Some(("other", message)) => spi::Error::Other(message.to_string()),
+        _ => match text {
+            "overrun" => spi::Error::Overrun,
+            "mode-fault" => spi::Error::ModeFault,
+            "frame-format" => spi::Error::FrameFormat,
+            "chip-select-fault" => spi::Error::ChipSelectFault,
+            "unsupported-config" => spi::Error::UnsupportedConfig,
+            _ => return None,
+        },
+    })
+}
+
+fn gpio_error_name(error: &gpio::Error) -> String {
+    match error {
+        gpio::Error::UnknownPin => "unknown-pin".to_string(),
+        gpio::Error::NotPermitted => "not-permitted".to_string(),
+        gpio::Error::Hardware(message) => format!("hardware:{}", message.replace('\n', " ")),
+    }
+}
+
+fn parse_gpio_error(text: &str) -> Option<gpio::Error> {
+    Some(match text {
+        "unknown-pin" => gpio::Error::UnknownPin,
+        "not-permitted" => gpio::Error::NotPermitted,
+        _ => gpio::Error::Hardware(text.strip_prefix("hardware:")?.to_string()),
+    })
+}
+
+fn edge_name(edge: Edge) -> &'static str {
+    match edge {
+        Edge::Rising => "rising",
+        Edge::Falling => "falling",
+        Edge::Any => "any",
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Read(rx) => {
+                f.write_str("r:")?;
+                write_hex(f, rx)
+            }
+            Op::Write(tx) => {
+                f.write_str("w:")?;
+                write_hex(f, tx)
+            }
+            Op::Transfer { tx, rx } | Op::TransferInPlace { tx, rx } => {
+                let kind = if matches!(self, Op::Transfer { .. }) {
+                    "x"
+                } else {
+                    "i"
+                };
+                write!(f, "{}:", kind)?;
+                write_hex(f, tx)?;
+                f.write_str("/")?;
+                write_hex(f, rx)
+            }
+            Op::DelayNs(ns) => write!(f, "d:{}", ns),
+        }
+    }
+}
+
+/// `is-high` and `wait` end in 0 or 1, or an error instead.
+fn write_level(f: &mut fmt::Formatter<'_>, result: &Result<bool, gpio::Error>) -> fmt::Result {
+    match result {
+        Ok(high) => write!(f, " {}", u8::from(*high)),
+        Err(error) => write!(f, " ! {}", gpio_error_name(error)),
+    }
+}
+
+/// The call without its timestamp and label, as it appears in the trace.
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Call::Spi { method, ops, error } => {
+                f.write_str(match method {
+                    Method::Read => "read",
+                    Method::Write => "write",
+                    Method::Transfer => "transfer",
+                    Method::Transaction => "transaction",
+                })?;
+                for op in ops {
+                    write!(f, " {}", op)?;
+                }
+                if let Some(error) = error {
+                    write!(f, " ! {}", spi_error_name(error))?;
+                }
+                Ok(())
+            }
+            Call::Output { high, error } => {
+                f.write_str(if *high { "set-high" } else { "set-low" })?;
+                if let Some(error) = error {
+                    write!(f, " ! {}", gpio_error_name(error))?;
+                }
+                Ok(())
+            }
+            Call::Level { result } => {
+                f.write_str("is-high")?;
+                write_level(f, result)
+            }
+            Call::Edge {
+                edge,
+                timeout_ms,
+                result,
+            } => {
+                write!(f, "wait {} {}", edge_name(*edge), timeout_ms)?;
+                write_level(f, result)
+            }
+        }
+    }
+}
+
+/// Whether `label` fits in a trace line. A quoted TOML key can hold spaces,
+/// but the label is one word between the section and the call.
+pub fn is_recordable(label: &str) -> bool {
+    !label.is_empty() && !label.contains(char::is_whitespace)
+}
+
+/// One line of a trace file.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.micros,
+            self.call.section(),
+            self.label,
+            self.call
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+impl Event {
+    /// Parses one line of a trace file.
+    pub fn parse(line: &str) -> Result<Event, String> {
+        let (call, error) = match line.split_once(" ! ") {
+            Some((call, error)) => (call, Some(error)),
+            None => (line, None),
+        };
+        let mut words = call.split_whitespace();
+        let mut next = |what: &str| words.next().ok_or_else(|| format!("missing {}", what));
+        let micros = next("timestamp")?;
+        let micros = micros
+            .parse()
+            .map_err(|_| format!("`{}` is not a timestamp", micros))?;
+        let section = next("section")?;
+        let label = next("label")?.to_string();
+        let name = next("call")?;
+        let rest: Vec<&str> = words.collect();
+
+        let spi_error = || {
+            error
+                .map(|text| {
+                    parse_spi_error(text).ok_or_else(|| format!("unknown error `{}`", text))
+                })
+                .transpose()
+        };
+        let gpio_error = || {
+            error
+                .map(|text| {
+                    parse_gpio_error(text).ok_or_else(|| format!("unknown error `{}`", text))
+                })
+                .transpose()
+        };
+        let gpio_result = |value: Option<&&str>| -> Result<Result<bool, gpio::Error>, String> {
+            match (gpio_error()?, value) {
+                (Some(error), None) => Ok(Err(error)),
+                (None, Some(&"0")) => Ok(Ok(false)),
+                (None, Some(&"1")) => Ok(Ok(true)),
+                _ => Err("expected a level of 0 or 1, or an error".to_string()),
+            }
+        };
+
+        let call = match (section, name) {
+            ("spi", method) => Call::Spi {
+                method: match method {
+                    "read" => Method::Read,
+                    "write" => Method::Write,
+                    "transfer" => Method::Transfer,
+                    "transaction" => Method::Transaction,
+                    _ => return Err(format!("unknown SPI call `{}`", method)),
+                },
+                ops: rest
+                    .iter()
+                    .map(|word| parse_op(word).ok_or_else(|| format!("bad operation `{}`", word)))
+                    .collect::<Result<_, _>>()?,
+                error: spi_error()?,
+            },
+            ("gpio", "set-high" | "set-low") if rest.is_empty() => Call::Output {
+                high: name == "set-high",
+                error: gpio_error()?,
+            },
+            ("gpio_input", "is-high") if rest.len() <= 1 => Call::Level {
+                result: gpio_result(rest.first())?,
+            },
+            ("gpio_input", "wait") if (2..=3).contains(&rest.len()) => Call::Edge {
+                edge: match rest[0] {
+                    "rising" => Edge::Rising,
+                    "falling" => Edge::Falling,
+                    "any" => Edge::Any,
+                    edge => return Err(format!("unknown edge `{}`", edge)),
+                },
+                timeout_ms: rest[1]
+                    .parse()
+                    .map_err(|_| format!("`{}` is not a timeout", rest[1]))?,
+                result: gpio_result(rest.get(2))?,
+            },
+            _ => return Err(format!("unknown call `{} {}`", section, name)),
+        };
+        Ok(Event {
+            micros,
+            label,
+            call,
+        })
+    }
+}
+
+fn parse_op(word: &str) -> Option<Op> {
+    let (kind, data) = word.split_once(':')?;
+    Some(match kind {
+        "r" => Op::Read(parse_hex(data)?),
+        "w" => Op::Write(parse_hex(data)?),
+        "x" | "i" => {
+            let (tx, rx) = data.split_once('/')?;
+            let (tx, rx) = (parse_hex(tx)?, parse_hex(rx)?);
+            if kind == "x" {
+                Op::Transfer { tx, rx }
+            } else {
+                Op::TransferInPlace { tx, rx }
+            }
+        }
+        "d" => Op::DelayNs(data.parse().ok()?),
+        _ => return None,
+    })
+}
+
+// ==========================================
+// RECORDING
+// ==========================================
+
+/// Wraps an SPI device, recording every call but `configure`.
+pub struct RecordingSpi {
+    label: String,
+    device: Box<dyn ConfigurableSpiDevice + Send + 'static>,
+    clock: Clock,
+    sink: Sink,
+}
+
+impl RecordingSpi {
+    pub fn new(
+        label: &str,
+        device: Box<dyn ConfigurableSpiDevice + Send + 'static>,
+        clock: Clock,
+        sink: Sink,
+    ) -> Self {
+        Self {
+            label: label.to_string(),
+            device,
+            clock,
+            sink,
+        }
+    }
+
+    /// Runs `operations` through `run` and records them as a call to `method`.
+    fn record(
+        &mut self,
+        method: Method,
+        operations: &mut [Operation<'_, u8>],
+        run: impl FnOnce(
+            &mut (dyn ConfigurableSpiDevice + Send),
+            &mut [Operation<'_, u8>],
+        ) -> Result<(), spi::Error>,
+    ) -> Result<(), spi::Error> {
+        let micros = (self.clock)();
+        let before = snapshot(operations);
+        let result = run(self.device.as_mut(), operations);
+        let ops = merge(before, snapshot(operations));
+        (self.sink)(&Event {
+            micros,
+            label: self.label.clone(),
+            call: Call::Spi {
+                method,
+                ops,
+                error: result.clone().err(),
+            },
+        });
+        result
+    }
+}
+
+impl ErasedSpiDevice for RecordingSpi {
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), spi::Error> {
+        self.record(Method::Read, &mut [Operation::Read(buf)], |device, ops| {
+            let [Operation::Read(buf)] = ops else {
+                unreachable!()
+            };
+            device.read(buf)
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), spi::Error> {
+        self.record(Method::Write, &mut [Operation::Write(data)], |device, _| {
+            device.write(data)
+        })
+    }
+
+    fn transfer(&mut self, rx: &mut [u8], tx: &[u8]) -> Result<(), spi::Error> {
+        self.record(
+            Method::Transfer,
+            &mut [Operation::Transfer(rx, tx)],
+            |device, ops| {
+                let [Operation::Transfer(rx, tx)] = ops else {
+                    unreachable!()
+                };
+                device.transfer(rx, tx)
+            },
+        )
+    }
+
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), spi::Error> {
+        self.record(Method::Transaction, operations, |device, ops| {
+            device.transaction(ops)
+        })
+    }
+}
+
+impl ConfigurableSpiDevice for RecordingSpi {
+    fn configure(&mut self, config: &BusConfig) -> Result<(), spi::Error> {
+        self.device.configure(config)
+    }
+}
+
+/// Wraps an output pin, recording every `set_high`/`set_low`.
+pub struct RecordingOutputPin {
+    label: String,
+    pin: Box<dyn ErasedOutputPin + Send + 'static>,
+    clock: Clock,
+    sink: Sink,
+}
+
+impl RecordingOutputPin {
+    pub fn new(
+        label: &str,
+        pin: Box<dyn ErasedOutputPin + Send + 'static>,
+        clock: Clock,
+        sink: Sink,
+    ) -> Self {
+        Self {
+            label: label.to_string(),
+            pin,
+            clock,
+            sink,
+        }
+    }
+
+    fn set(&mut self, high: bool) -> Result<(), gpio::Error> {
+        let micros = (self.clock)();
+        let result = if high {
+            self.pin.set_high()
+        } else {
+            self.pin.set_low()
+        };
+        (self.sink)(&Event {
+            micros,
+            label: self.label.clone(),
+            call: Call::Output {
+                high,
+                error: result.clone().err(),
+            },
+        });
+        result
+    }
+}
+
+impl ErasedOutputPin for RecordingOutputPin {
+    fn set_high(&mut self) -> Result<(), gpio::Error> {
+        self.set(true)
+    }
+
+    fn set_low(&mut self) -> Result<(), gpio::Error> {
+        self.set(false)
+    }
+}
+
+/// Wraps an input pin, recording every read and edge wait.
+pub struct RecordingInputPin {
+    label: String,
+    pin: Box<dyn ErasedEdgePin + Send + 'static>,
+    clock: Clock,
+    sink: Sink,
+}
+
+impl RecordingInputPin {
+    pub fn new(
+        label: &str,
+        pin: Box<dyn ErasedEdgePin + Send + 'static>,
+        clock: Clock,
+        sink: Sink,
+    ) -> Self {
+        Self {
+            label: label.to_string(),
+            pin,
+            clock,
+            sink,
+        }
+    }
+
+    fn emit(&mut self, micros: u64, call: Call) {
+        (self.sink)(&Event {
+            micros,
+            label: self.label.clone(),
+            call,
+        });
+    }
+}
+
+impl ErasedInputPin for RecordingInputPin {
+    fn is_high(&mut self) -> Result<bool, gpio::Error> {
+        let micros = (self.clock)();
+        let result = self.pin.is_high();
+        self.emit(
+            micros,
+            Call::Level {
+                result: result.clone(),
+            },
+        );
+        result
+    }
+}
+
+impl ErasedEdgePin for RecordingInputPin {
+    fn wait_for_edge(&mut self, edge: Edge, timeout_ms: u32) -> Result<bool, gpio::Error> {
+        let micros = (self.clock)();
+        let result = self.pin.wait_for_edge(edge, timeout_ms);
+        self.emit(
+            micros,
+            Call::Edge {
+                edge,
+                timeout_ms,
+                result: result.clone(),
+            },
+        );
+        result
+    }
+}
+
+// ==========================================
+// REPLAY
+// ==========================================
+
+/// A replayed call that doesn't match the trace. `call` counts the device's
+/// calls from 1.
+#[derive(Clone, Debug)]
+pub enum Divergence {
+    /// The guest asked for something other than what was recorded.
+    Mismatch {
+        call: usize,
+        expected: Event,
+        actual: Call,
+    },
+    /// The guest made more calls than were recorded.
+    PastEnd {
+        call: usize,
+        label: String,
+        actual: Call,
+    },
+    /// The guest stopped before making every recorded call.
+    Unplayed {
+        call: usize,
+        remaining: usize,
+        next: Event,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Mismatch {
+                call,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}.{} call {}: the guest did `{}`, the trace has `{}`",
+                expected.call.section(),
+                expected.label,
+                call,
+                actual,
+                expected.call
+            ),
+            Divergence::PastEnd {
+                call,
+                label,
+                actual,
+            } => write!(
+                f,
+                "{}.{} call {}: the guest did `{}` after the trace ended",
+                actual.section(),
+                label,
+                call,
+                actual
+            ),
+            Divergence::Unplayed {
+                call,
+                remaining,
+                next,
+            } => write!(
+                f,
+                "{}.{} call {}: the guest stopped with {} recorded call(s) left, starting with `{}`",
+                next.call.section(),
+                next.label,
+                call,
+                remaining,
+                next.call
+            ),
+        }
+    }
+}
+
+type Observer = Box<dyn FnMut(&Divergence) + Send + 'static>;
+
+/// A parsed trace file, handing out each device's recorded calls.
+#[derive(Default)]
+pub struct Trace {
+    devices: BTreeMap<(&'static str, String), VecDeque<Event>>,
+}
+
+impl Trace {
+    /// Parses a trace, skipping blank lines and `#` comments.
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let mut trace = Self::default();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let event = Event::parse(line).map_err(|message| ParseError {
+                line: index + 1,
+                message,
+            })?;
+            trace
+                .devices
+                .entry((event.call.section(), event.label.clone()))
+                .or_default()
+                .push_back(event);
+        }
+        Ok(trace)
+    }
+
+    fn take(&mut self, section: &'static str, label: &str) -> Replay {
+        Replay {
+            label: label.to_string(),
+            events: self
+                .devices
+                .remove(&(section, label.to_string()))
+                .unwrap_or_default(),
+            calls: 0,
+            observer: None,
+        }
+    }
+
+    /// Replays the calls recorded for the SPI device `label`.
+    pub fn spi(&mut self, label: &str) -> ReplaySpi {
+        ReplaySpi {
+            replay: self.take("spi", label),
+        }
+    }
+
+    /// Replays the changes recorded for the output `label`.
+    pub fn output(&mut self, label: &str) -> ReplayOutputPin {
+        ReplayOutputPin {
+            replay: self.take("gpio", label),
+        }
+    }
+
+    /// Replays the reads recorded for the input `label`.
+    pub fn input(&mut self, label: &str) -> ReplayInputPin {
+        ReplayInputPin {
+            replay: self.take("gpio_input", label),
+        }
+    }
+
+    /// Labels with recorded calls that no device has taken, as `section.label`.
+    pub fn unclaimed(&self) -> Vec<String> {
+        self.devices
+            .keys()
+            .map(|(section, label)| format!("{}.{}", section, label))
+            .collect()
+    }
+}
+
+/// One device's share of a [`Trace`].
+struct Replay {
+    label: String,
+    events: VecDeque<Event>,
+    calls: usize,
+    observer: Option<Observer>,
+}
+
+impl Replay {
+    /// Counts a call and takes the recorded one it should match.
+    fn next(&mut self) -> (usize, Option<Event>) {
+        self.calls += 1;
+        (self.calls, self.events.pop_front())
+    }
+
+    fn diverge(&mut self, call: usize, expected: Option<Event>, actual: Call) {
+        let divergence = match expected {
+            Some(expected) => Divergence::Mismatch {
+                call,
+                expected,
+                actual,
+            },
+            None => Divergence::PastEnd {
+                call,
+                label: self.label.clone(),
+                actual,
+            },
+        };
+        if let Some(observer) = &mut self.observer {
+            observer(&divergence);
+        }
+    }
+}
+
+impl Drop for Replay {
+    fn drop(&mut self) {
+        if let (Some(next), Some(observer)) = (self.events.front(), &mut self.observer) {
+            observer(&Divergence::Unplayed {
+                call: self.calls + 1,
+                remaining: self.events.len(),
+                next: next.clone(),
+            });
+        }
+    }
+}
+
+/// Serves an SPI device's recorded reads and results. A call that doesn't
+/// match the recording is reported, and still gets whatever recorded data fits.
+pub struct ReplaySpi {
+    replay: Replay,
+}
+
+impl ReplaySpi {
+    /// Called with every divergence as it happens, and on drop with the calls left over.
+    pub fn on_divergence(mut self, observer: impl FnMut(&Divergence) + Send + 'static) -> Self {
+        self.replay.observer = Some(Box::new(observer));
+        self
+    }
+
+    fn play(
+        &mut self,
+        method: Method,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), spi::Error> {
+        let requested = snapshot(operations);
+        let (call, expected) = self.replay.next();
+        let (matched, error) = match &expected {
+            Some(Event {
+                call:
+                    Call::Spi {
+                        method: m,
+                        ops,
+                        error,
+                    },
+                ..
+            }) => {
+                for (operation, recorded) in operations.iter_mut().zip(ops) {
+                    if let (
+                        Operation::Read(buf)
+                        | Operation::Transfer(buf, _)
+                        | Operation::TransferInPlace(buf),
+                        Some(data),
+                    ) = (operation, recorded.received())
+                    {
+                        let n = buf.len().min(data.len());
+                        buf[..n].copy_from_slice(&data[..n]);
+                    }
+                }
+                let matched = *m == method
+                    && ops.len() == requested.len()
+                    && ops.iter().zip(&requested).all(|(a, b)| a.same_request(b));
+                (matched, error.clone())
+            }
+            _ => (false, None),
+        };
+        if !matched {
+            let actual = Call::Spi {
+                method,
+                ops: merge(requested, snapshot(operations)),
+                error: error.clone(),
+            };
+            self.replay.diverge(call, expected, actual);
+        }
+        error.map_or(Ok(()), Err)
+    }
+}
+
+impl ErasedSpiDevice for ReplaySpi {
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), spi::Error> {
+        self.play(Method::Read, &mut [Operation::Read(buf)])
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), spi::Error> {
+        self.play(Method::Write, &mut [Operation::Write(data)])
+    }
+
+    fn transfer(&mut self, rx: &mut [u8], tx: &[u8]) -> Result<(), spi::Error> {
+        self.play(Method::Transfer, &mut [Operation::Transfer(rx, tx)])
+    }
+
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), spi::Error> {
+        self.play(Method::Transaction, operations)
+    }
+}
+
+/// Whatever configuration the policy lets through; the trace holds the results.
+impl ConfigurableSpiDevice for ReplaySpi {
+    fn configure(&mut self, _config: &BusConfig) -> Result<(), spi::Error> {
+        Ok(())
+    }
+}
+
+/// Checks an output's changes against the recording and returns the recorded results.
+pub struct ReplayOutputPin {
+    replay: Replay,
+}
+
+impl ReplayOutputPin {
+    /// Called with every divergence as it happens, and on drop with the calls left over.
+    pub fn on_divergence(mut self, observer: impl FnMut(&Divergence) + Send + 'static) -> Self {
+        self.replay.observer = Some(Box::new(observer));
+        self
+    }
+
+    fn set(&mut self, high: bool) -> Result<(), gpio::Error> {
+        let (call, expected) = self.replay.next();
+        let (matched, error) = match &expected {
+            Some(Event {
+                call: Call::Output { high: h, error },
+                ..
+            }) => (*h == high, error.clone()),
+            _ => (false, None),
+        };
+        if !matched {
+            let actual = Call::Output {
+                high,
+                error: error.clone(),
+            };
+            self.replay.diverge(call, expected, actual);
+        }
+        error.map_or(Ok(()), Err)
+    }
+}
+
+impl ErasedOutputPin for ReplayOutputPin {
+    fn set_high(&mut self) -> Result<(), gpio::Error> {
+        self.set(true)
+    }
+
+    fn set_low(&mut self) -> Result<(), gpio::Error> {
+        self.set(false)
+    }
+}
+
+/// Serves an input's recorded levels and edges. Calls the recording doesn't
+/// have read low, or time out.
+pub struct ReplayInputPin {
+    replay: Replay,
+}
+
+impl ReplayInputPin {
+    /// Called with every divergence as it happens, and on drop with the calls left over.
+    pub fn on_divergence(mut self, observer: impl FnMut(&Divergence) + Send + 'static) -> Self {
+        self.replay.observer = Some(Box::new(observer));
+        self
+    }
+}
+
+impl ErasedInputPin for ReplayInputPin {
+    fn is_high(&mut self) -> Result<bool, gpio::Error> {
+        let (call, expected) = self.replay.next();
+        if let Some(Event {
+            call: Call::Level { result },
+            ..
+        }) = &expected
+        {
+            return result.clone();
+        }
+        let actual = Call::Level { result: Ok(false) };
+        self.replay.diverge(call, expected, actual);
+        Ok(false)
+    }
+}
+
+impl ErasedEdgePin for ReplayInputPin {
+    fn wait_for_edge(&mut self, edge: Edge, timeout_ms: u32) -> Result<bool, gpio::Error> {
+        let (call, expected) = self.replay.next();
+        let (matched, result) = match &expected {
+            Some(Event {
+                call:
+                    Call::Edge {
+                        edge: e,
+                        timeout_ms: t,
+                        result,
+                    },
+                ..
+            }) => (*e == edge && *t == timeout_ms, result.clone()),
+            _ => (false, Ok(false)),
+        };
+        if !matched {
+            let actual = Call::Edge {
+                edge,
+                timeout_ms,
+                result: result.clone(),
+            };
+            self.replay.diverge(call, expected, actual);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use core::convert::Infallible;
+    use embedded_hal::digital::{ErrorType as DigitalErrorType, InputPin, OutputPin};
+    use embedded_hal::spi::{ErrorType as SpiErrorType, SpiDevice};
+    use spi::Reconfigurable;
+    use std::sync::Mutex;
+
+    fn event(call: Call) -> Event {
+        Event {
+            micros: 1042,
+            label: "sensor".to_string(),
+            call,
+        }
+    }
+
+    fn spi_call(error: Option<spi::Error>) -> Call {
+        Call::Spi {
+            method: Method::Transaction,
+            ops: vec![Op::Write(vec![0xf7])],
+            error,
+        }
+    }
+
+    #[test]
+    fn every_event_round_trips_through_text() {
+        let mut calls = vec![
+            Call::Spi {
+                method: Method::Read,
+                ops: vec![Op::Read(vec![0x00, 0x80, 0xff])],
+                error: None,
+            },
+            Call::Spi {
+                method: Method::Write,
+                ops: vec![Op::Write(vec![])],
+                error: None,
+            },
+            Call::Spi {
+                method: Method::Transfer,
+                ops: vec![Op::Transfer {
+                    tx: vec![0xfa, 0x00],
+                    rx: vec![0x12, 0x34, 0x56],
+                }],
+                error: None,
+            },
+            Call::Spi {
+                method: Method::Transaction,
+                ops: vec![
+                    Op::Write(vec![0xf7]),
+                    Op::DelayNs(250),
+                    Op::TransferInPlace {
+                        tx: vec![0x01],
+                        rx: vec![0x02],
+                    },
+                    Op::Read(vec![0x5a]),
+                ],
+                error: None,
+            },
+            Call::Output {
+                high: true,
+                error: None,
+            },
+            Call::Output {
+                high: false,
+                error: Some(gpio::Error::NotPermitted),
+            },
+            Call::Level { result: Ok(true) },
+            Call::Level { result: Ok(false) },
+            Call::Level {
+                result: Err(gpio::Error::UnknownPin),
+            },
+            Call::Level {
+                result: Err(gpio::Error::Hardware("line busy ! try again".to_string())),
+            },
+        ];
+        for edge in [Edge::Rising, Edge::Falling, Edge::Any] {
+            calls.push(Call::Edge {
+                edge,
+                timeout_ms: 250,
+                result: Ok(true),
+            });
+        }
+        calls.push(Call::Edge {
+            edge: Edge::Any,
+            timeout_ms: 0,
+            result: Err(gpio::Error::Hardware(String::new())),
+        });
+        let spi_errors = [
+            spi::Error::Overrun,
+            spi::Error::ModeFault,
+            spi::Error::FrameFormat,
+            spi::Error::ChipSelectFault,
+            spi::Error::LimitExceeded(Limit::BytesPerOperation),
+            spi::Error::LimitExceeded(Limit::OperationsPerTransaction),
+            spi::Error::LimitExceeded(Limit::BytesPerTransaction),
+            spi::Error::UnsupportedConfig,
+            spi::Error::Other("bus stuck: MISO reads 0xff".to_string()),
+        ];
+        calls.extend(spi_errors.into_iter().map(|error| spi_call(Some(error))));
+
+        for call in calls {
+            let event = event(call);
+            let line = event.to_string();
+            let parsed = Event::parse(&line).unwrap_or_else(|err| panic!("`{}`: {}", line, err));
+            assert_eq!(
+                format!("{:?}", parsed),
+                format!("{:?}", event),
+                "`{}`",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        for line in [
+            "",
+            "x spi sensor read",
+            "1 spi sensor scan",
+            "1 spi sensor read r:abc",
+            "1 spi sensor read ! melted",
+            "1 gpio LED toggle",
+            "1 gpio_input BUTTON is-high 2",
+            "1 gpio_input BUTTON wait sideways 10 1",
+        ] {
+            assert!(Event::parse(line).is_err(), "`{}` parsed", line);
+        }
+    }
+
+    #[test]
+    fn labels_must_be_one_word() {
+        assert!(is_recordable("status-led"));
+        assert!(!is_recordable("status led"));
+        assert!(!is_recordable("tab\tlabel"));
+        assert!(!is_recordable(""));
+    }
+
+    /// Reads count up from 1, so every recorded read is distinct.
+    struct Counter(u8);
+
+    impl SpiErrorType for Counter {
+        type Error = Infallible;
+    }
+
+    impl SpiDevice<u8> for Counter {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+            for operation in operations {
+                if let Operation::Read(buf)
+                | Operation::Transfer(buf, _)
+                | Operation::TransferInPlace(buf) = operation
+                {
+                    for byte in buf.iter_mut() {
+                        self.0 = self.0.wrapping_add(1);
+                        *byte = self.0;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Accepts every change.
+    struct Latch;
+
+    impl DigitalErrorType for Latch {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Latch {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    /// High on every other read; edges always arrive.
+    struct Toggle(bool);
+
+    impl DigitalErrorType for Toggle {
+        type Error = Infallible;
+    }
+
+    impl InputPin for Toggle {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            self.0 = !self.0;
+            Ok(self.0)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(!InputPin::is_high(self)?)
+        }
+    }
+
+    impl ErasedEdgePin for Toggle {
+        fn wait_for_edge(&mut self, _edge: Edge, timeout_ms: u32) -> Result<bool, gpio::Error> {
+            Ok(timeout_ms > 0)
+        }
+    }
+
+    /// What the guest reads back during [`session`], in order.
+    type Reads = Vec<Vec<u8>>;
+
+    fn session(
+        spi: &mut dyn ErasedSpiDevice,
+        output: &mut dyn ErasedOutputPin,
+        input: &mut dyn ErasedEdgePin,
+    ) -> (Reads, Vec<bool>) {
+        let mut reads = Vec::new();
+        let mut levels = Vec::new();
+
+        spi.write(&[0x01, 0x02]).unwrap();
+        let mut buf = [0; 3];
+        spi.read(&mut buf).unwrap();
+        reads.push(buf.to_vec());
+        let mut rx = [0; 2];
+        spi.transfer(&mut rx, &[0xaa, 0xbb]).unwrap();
+        reads.push(rx.to_vec());
+        let (mut read, mut in_place) = ([0; 2], [0x10, 0x20]);
+        spi.transaction(&mut [
+            Operation::Write(&[0xf7]),
+            Operation::Read(&mut read),
+            Operation::DelayNs(10),
+            Operation::TransferInPlace(&mut in_place),
+        ])
+        .unwrap();
+        reads.push(read.to_vec());
+        reads.push(in_place.to_vec());
+
+        output.set_high().unwrap();
+        output.set_low().unwrap();
+        levels.push(input.is_high().unwrap());
+        levels.push(input.is_high().unwrap());
+        levels.push(input.wait_for_edge(Edge::Falling, 250).unwrap());
+        levels.push(input.wait_for_edge(Edge::Rising, 0).unwrap());
+        (reads, levels)
+    }
+
+    /// Runs [`session`] on real-ish devices and returns its results and trace.
+    fn record() -> (Reads, Vec<bool>, String) {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = || -> Sink {
+            let lines = lines.clone();
+            Box::new(move |event: &Event| lines.lock().unwrap().push(event.to_string()))
+        };
+        let clock = || -> Clock { Box::new(|| 7) };
+
+        let device = Reconfigurable::new(Counter(0), |_: &mut Counter, _: &BusConfig| Ok(()));
+        let mut spi = RecordingSpi::new("sensor", Box::new(device), clock(), sink());
+        let mut output = RecordingOutputPin::new("LED", Box::new(Latch), clock(), sink());
+        let mut input = RecordingInputPin::new("BUTTON", Box::new(Toggle(false)), clock(), sink());
+        let (reads, levels) = session(&mut spi, &mut output, &mut input);
+
+        let trace = lines.lock().unwrap().join("\n");
+        (reads, levels, trace)
+    }
+
+    type Divergences = Arc<Mutex<Vec<Divergence>>>;
+
+    fn replay(trace: &str) -> (ReplaySpi, ReplayOutputPin, ReplayInputPin, Divergences) {
+        let mut trace = Trace::parse(trace).unwrap();
+        let divergences: Divergences = Arc::default();
+        let observer = || {
+            let divergences = divergences.clone();
+            move |divergence: &Divergence| divergences.lock().unwrap().push(divergence.clone())
+        };
+        let spi = trace.spi("sensor").on_divergence(observer());
+        let output = trace.output("LED").on_divergence(observer());
+        let input = trace.input("BUTTON").on_divergence(observer());
+        assert!(trace.unclaimed().is_empty());
+        (spi, output, input, divergences)
+    }
+
+    #[test]
+    fn a_recorded_session_replays_without_divergences() {
+        let (reads, levels, trace) = record();
+        assert_eq!(trace.lines().count(), 10);
+
+        let (mut spi, mut output, mut input, divergences) = replay(&trace);
+        let replayed = session(&mut spi, &mut output, &mut input);
+        drop((spi, output, input));
+
+        assert_eq!(replayed, (reads, levels));
+        assert!(divergences.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_different_write_is_a_mismatch() {
+        let (_, _, trace) = record();
+        let (mut spi, _output, _input, divergences) = replay(&trace);
+
+        spi.write(&[0x01, 0x03]).unwrap();
+        let divergences = divergences.lock().unwrap();
+        assert!(matches!(
+            &divergences[..],
+            [Divergence::Mismatch {
+                call: 1,
+                actual: Call::Spi {
+                    method: Method::Write,
+                    ..
+                },
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn calls_past_the_end_and_unplayed_calls_are_reported() {
+        let trace = "1 gpio LED set-high\n2 gpio LED set-low\n";
+        let (_, mut output, _, divergences) = replay(trace);
+        output.set_high().unwrap();
+        drop(output);
+        assert!(matches!(
+            &divergences.lock().unwrap()[..],
+            [Divergence::Unplayed {
+                call: 2,
+                remaining: 1,
+                ..
+            }]
+        ));
+
+        let (_, mut output, _, divergences) = replay("1 gpio LED set-high\n");
+        output.set_high().unwrap();
+        output.set_low().unwrap();
+        assert!(matches!(
+            &divergences.lock().unwrap()[..],
+            [Divergence::PastEnd { call: 2, .. }]
+        ));
+    }
+}
@@ -0,0 +1,135 @@
+#![no_std]
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use wasmtime::component::{Linker, Resource, ResourceTable};
+
+wasmtime::component::bindgen!({
+    path: "../../../wit/uart.wit",
+    world: "wasi-uart-host",
+    with: { "wasi:uart/uart.uart-port": ActiveUartPort }
+});
+
+use wasi::uart::uart;
+pub use wasi::uart::uart::Error;
+
+pub struct ActiveUartPort {
+    pub id: usize,
+}
+
+/// A serial port as seen by the host. Unlike SPI and I2C there is no
+/// embedded-hal trait with read timeouts, so each backend implements this directly.
+pub trait ErasedUart {
+    /// Returns the number of bytes read, or 0 if nothing arrived within `timeout_ms`.
+    fn read(&mut self, buf: &mut [u8], timeout_ms: u32) -> Result<usize, uart::Error>;
+    fn write(&mut self, data: &[u8]) -> Result<(), uart::Error>;
+    fn flush(&mut self) -> Result<(), uart::Error>;
+}
+
+/// Upper bounds on what a guest may ask of a single port, checked before
+/// any buffer is allocated on its behalf.
+#[derive(Clone, Copy, Debug)]
+pub struct UartLimits {
+    pub max_bytes_per_op: u64,
+}
+
+impl Default for UartLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes_per_op: 1024,
+        }
+    }
+}
+
+pub struct UartHardware {
+    pub name: String,
+    pub limits: UartLimits,
+    pub port: Box<dyn ErasedUart + Send + 'static>,
+}
+
+pub struct UartCtx {
+    pub table: ResourceTable,
+    pub hardware: Vec<UartHardware>,
+}
+
+pub trait UartView {
+    fn uart_ctx(&mut self) -> &mut UartCtx;
+}
+
+impl UartCtx {
+    fn get_hw(
+        &mut self,
+        handle: &Resource<ActiveUartPort>,
+    ) -> Result<&mut UartHardware, uart::Error> {
+        let id = self
+            .table
+            .get(handle)
+            .map_err(|_| uart::Error::Other("Bad Handle".into()))?
+            .id;
+
+        self.hardware
+            .get_mut(id)
+            .ok_or_else(|| uart::Error::Other("HW unavailable".into()))
+    }
+}
+
+impl uart::Host for UartCtx {
+    fn open(&mut self, name: String) -> Result<Resource<ActiveUartPort>, uart::Error> {
+        let (id, _) = self
+            .hardware
+            .iter()
+            .enumerate()
+            .find(|(_, hw)| hw.name == name)
+            .ok_or_else(|| uart::Error::Other(alloc::format!("Port '{}' not found", name)))?;
+
+        self.table
+            .push(ActiveUartPort { id })
+            .map_err(|e| uart::Error::Other(e.to_string()))
+    }
+}
+
+impl uart::HostUartPort for UartCtx {
+    /// Reads are short by nature, so a request over the limit is clamped rather than rejected.
+    fn read(
+        &mut self,
+        handle: Resource<ActiveUartPort>,
+        len: u64,
+        timeout_ms: u32,
+    ) -> Result<Vec<u8>, uart::Error> {
+        let hw = self.get_hw(&handle)?;
+        let len = len.min(hw.limits.max_bytes_per_op);
+
+        let mut buf = vec![0u8; len as usize];
+        let read = hw.port.read(&mut buf, timeout_ms)?;
+        buf.truncate(read);
+        Ok(buf)
+    }
+
+    fn write(
+        &mut self,
+        handle: Resource<ActiveUartPort>,
+        data: Vec<u8>,
+    ) -> Result<(), uart::Error> {
+        let hw = self.get_hw(&handle)?;
+        if data.len() as u64 > hw.limits.max_bytes_per_op {
+            return Err(uart::Error::LimitExceeded);
+        }
+        hw.port.write(&data)
+    }
+
+    fn flush(&mut self, handle: Resource<ActiveUartPort>) -> Result<(), uart::Error> {
+        self.get_hw(&handle)?.port.flush()
+    }
+
+    fn drop(&mut self, rep: Resource<ActiveUartPort>) -> wasmtime::Result<()> {
+        self.table.delete(rep)?;
+        Ok(())
+    }
+}
+
+pub fn add_to_linker<T: UartView + 'static>(linker: &mut Linker<T>) -> wasmtime::Result<()> {
+    uart::add_to_linker::<T, wasmtime::component::HasSelf<UartCtx>>(linker, |host| host.uart_ctx())
+}
@@ -0,0 +1,603 @@
+//! The `policy.toml` schema shared by the Pico build script and the Linux host.
+//!
+//! Every policy starts with a `version` and the `board` it was written for. The
+//! GPIO sections are common to all boards, while buses and other peripherals are
+//! described the way each board addresses them (see [`pico`] and [`linux`]).
+//! Parsing rejects unknown keys, and a parsed policy is validated as a whole, so
+//! a host never sees duplicate pins or a label granted twice.
+
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub mod linux;
+pub mod manifest;
+pub mod pico;
+pub mod register_map;
+
+#[cfg(test)]
+mod tests;
+
+/// The only schema version this crate reads.
+pub const VERSION: u32 = 1;
+
+/// The fastest PWM a policy may grant. Linux takes PWM periods in whole
+/// nanoseconds, so anything faster has no period it could write.
+pub const MAX_PWM_FREQUENCY: u32 = 1_000_000_000;
+
+/// The longest debounce period a policy may grant. A debounced read blocks the
+/// guest for several periods while the line settles.
+pub const MAX_DEBOUNCE_MS: u32 = 1000;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Board {
+    Pico,
+    Linux,
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Board::Pico => f.write_str("pico"),
+            Board::Linux => f.write_str("linux"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    High,
+    Low,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pull {
+    Up,
+    Down,
+    #[default]
+    None,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "u8")]
+pub enum SpiMode {
+    Mode0,
+    Mode1,
+    Mode2,
+    Mode3,
+}
+
+impl TryFrom<u8> for SpiMode {
+    type Error = String;
+
+    fn try_from(mode: u8) -> Result<Self, Self::Error> {
+        match mode {
+            0 => Ok(SpiMode::Mode0),
+            1 => Ok(SpiMode::Mode1),
+            2 => Ok(SpiMode::Mode2),
+            3 => Ok(SpiMode::Mode3),
+            _ => Err(format!("SPI mode must be 0, 1, 2 or 3, not {}", mode)),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+/// A host interface a guest may import, named as in the policy's `interfaces` list.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Interface {
+    Spi,
+    I2c,
+    Uart,
+    Pwm,
+    Adc,
+    Gpio,
+    Delay,
+    Logging,
+    Clock,
+    Timer,
+}
+
+impl Interface {
+    /// Interfaces that need no hardware and are granted unless a policy lists its own.
+    pub const BASE: [Interface; 4] = [
+        Interface::Delay,
+        Interface::Logging,
+        Interface::Clock,
+        Interface::Timer,
+    ];
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Interface::Spi => "spi",
+            Interface::I2c => "i2c",
+            Interface::Uart => "uart",
+            Interface::Pwm => "pwm",
+            Interface::Adc => "adc",
+            Interface::Gpio => "gpio",
+            Interface::Delay => "delay",
+            Interface::Logging => "logging",
+            Interface::Clock => "clock",
+            Interface::Timer => "timer",
+        })
+    }
+}
+
+/// An entry of `[gpio]`: an output pin and the level it starts at.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GpioOutput {
+    pub pin: u32,
+    pub initial: Level,
+}
+
+/// An entry of `[gpio_input]`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GpioInput {
+    pub pin: u32,
+    #[serde(default)]
+    pub pull: Pull,
+    pub debounce_ms: Option<u32>,
+}
+
+/// Why a policy was rejected.
+#[derive(Debug)]
+pub enum Error {
+    /// Not valid TOML, or a key that doesn't belong to the schema.
+    Parse(toml::de::Error),
+    MissingVersion,
+    UnsupportedVersion(u32),
+    MissingBoard(Board),
+    WrongBoard {
+        expected: Board,
+        found: Board,
+    },
+    /// Well-formed, but inconsistent. Every problem found is listed.
+    Invalid(Vec<Problem>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(err) => write!(f, "{}", err),
+            Error::MissingVersion => {
+                write!(f, "policy has no `version`; add `version = {}`", VERSION)
+            }
+            Error::UnsupportedVersion(found) => write!(
+                f,
+                "policy version {} is not supported; this build reads version {}",
+                found, VERSION
+            ),
+            Error::MissingBoard(expected) => {
+                write!(f, "policy has no `board`; add `board = \"{}\"`", expected)
+            }
+            Error::WrongBoard { expected, found } => write!(
+                f,
+                "policy is written for board `{}`, but this host is `{}`",
+                found, expected
+            ),
+            Error::Invalid(problems) => {
+                write!(f, "found {} problem(s):", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::Parse(err)
+    }
+}
+
+/// One inconsistency in an otherwise well-formed policy. Keys are dotted paths
+/// into the file, such as `spi.screen.cs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    DuplicatePin {
+        pin: u32,
+        first: String,
+        second: String,
+    },
+    /// Two entries claim the same bus, port or channel.
+    DuplicateDevice {
+        device: String,
+        first: String,
+        second: String,
+    },
+    /// A label granted by two sections, which guests could not tell apart.
+    LabelCollision {
+        label: String,
+        first: &'static str,
+        second: &'static str,
+    },
+    UnknownSpiBus {
+        device: String,
+        bus: String,
+    },
+    /// A block name the board doesn't have.
+    UnknownBlock {
+        key: String,
+        block: String,
+        valid: Vec<String>,
+    },
+    /// A pin the board cannot route to the peripheral it is assigned to.
+    PinFunction {
+        key: String,
+        pin: u8,
+        function: String,
+        valid: Vec<u8>,
+    },
+    /// An entry configuring a label its section doesn't grant.
+    UngrantedLabel {
+        key: String,
+        section: &'static str,
+    },
+    /// A key naming a label that its section doesn't grant.
+    UnknownLabel {
+        key: String,
+        label: String,
+        section: &'static str,
+    },
+    /// Two settings that exclude each other.
+    Conflict {
+        key: String,
+        other: String,
+    },
+    /// A setting that only works together with another one.
+    Requires {
+        key: String,
+        requires: String,
+    },
+    /// A setting that means nothing alongside another, such as the number of
+    /// bits to flip on a fault that flips none.
+    Inapplicable {
+        key: String,
+        context: String,
+    },
+    /// Labels granted through an interface the `interfaces` list leaves out.
+    UnlistedInterface {
+        section: &'static str,
+        interface: Interface,
+    },
+    OutOfRange {
+        key: String,
+        value: u64,
+        min: u64,
+        max: u64,
+    },
+    /// A list that has to name at least one entry.
+    Empty {
+        key: String,
+    },
+    /// Settings a guest may switch between that leave out the one the device
+    /// starts in, so the guest could never configure it back.
+    MissingInitial {
+        key: String,
+        initial: String,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::DuplicatePin { pin, first, second } => {
+                write!(
+                    f,
+                    "pin {} is used by both `{}` and `{}`",
+                    pin, first, second
+                )
+            }
+            Problem::DuplicateDevice {
+                device,
+                first,
+                second,
+            } => write!(f, "`{}` and `{}` both use {}", first, second, device),
+            Problem::LabelCollision {
+                label,
+                first,
+                second,
+            } => write!(
+                f,
+                "label `{}` is granted by both [{}] and [{}]",
+                label, first, second
+            ),
+            Problem::UnknownSpiBus { device, bus } => write!(
+                f,
+                "`spi.{}.bus` is `{}`, which has no [spi_bus.{}] section",
+                device, bus, bus
+            ),
+            Problem::UnknownBlock { key, block, valid } => write!(
+                f,
+                "`{}` is `{}`; expected one of {}",
+                key,
+                block,
+                valid.join(", ")
+            ),
+            Problem::PinFunction {
+                key,
+                pin,
+                function,
+                valid,
+            } => {
+                let valid: Vec<_> = valid.iter().map(|pin| pin.to_string()).collect();
+                write!(
+                    f,
+                    "`{}` is GPIO {}, which cannot be {}; use one of GPIO {}",
+                    key,
+                    pin,
+                    function,
+                    valid.join(", ")
+                )
+            }
+            Problem::UngrantedLabel { key, section } => {
+                write!(
+                    f,
+                    "`{}` configures a label [{}] doesn't grant",
+                    key, section
+                )
+            }
+            Problem::UnknownLabel {
+                key,
+                label,
+                section,
+            } => write!(
+                f,
+                "`{}` is `{}`, which [{}] doesn't grant",
+                key, label, section
+            ),
+            Problem::Conflict { key, other } => {
+                write!(f, "`{}` and `{}` can't both be set", key, other)
+            }
+            Problem::Requires { key, requires } => {
+                write!(f, "`{}` requires `{}` to be set", key, requires)
+            }
+            Problem::Inapplicable { key, context } => {
+                write!(f, "`{}` doesn't apply to {}", key, context)
+            }
+            Problem::UnlistedInterface { section, interface } => write!(
+                f,
+                "[{}] grants labels, but `interfaces` doesn't list \"{}\"",
+                section, interface
+            ),
+            Problem::OutOfRange {
+                key,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "`{}` is {}, outside the allowed {}..={}",
+                key, value, min, max
+            ),
+            Problem::Empty { key } => write!(f, "`{}` is empty", key),
+            Problem::MissingInitial { key, initial } => write!(
+                f,
+                "`{}` leaves out {}, which the device starts in",
+                key, initial
+            ),
+        }
+    }
+}
+
+/// Reads the header first, so a policy for another board or schema version is
+/// reported as such rather than as a list of unknown keys.
+fn parse<T: DeserializeOwned>(source: &str, board: Board) -> Result<T, Error> {
+    #[derive(Deserialize)]
+    struct Header {
+        version: Option<u32>,
+        board: Option<Board>,
+    }
+
+    let header: Header = toml::from_str(source)?;
+    match header.version {
+        None => return Err(Error::MissingVersion),
+        Some(VERSION) => {}
+        Some(found) => return Err(Error::UnsupportedVersion(found)),
+    }
+    match header.board {
+        None => return Err(Error::MissingBoard(board)),
+        Some(found) if found != board => {
+            return Err(Error::WrongBoard {
+                expected: board,
+                found,
+            });
+        }
+        Some(_) => {}
+    }
+
+    Ok(toml::from_str(source)?)
+}
+
+/// Collects problems while a board walks its policy.
+#[derive(Default)]
+struct Checker {
+    problems: Vec<Problem>,
+    pins: BTreeMap<u32, String>,
+    devices: BTreeMap<String, String>,
+    labels: BTreeMap<String, &'static str>,
+}
+
+impl Checker {
+    fn pin(&mut self, pin: u32, key: String) {
+        match self.pins.get(&pin) {
+            Some(first) => self.problems.push(Problem::DuplicatePin {
+                pin,
+                first: first.clone(),
+                second: key,
+            }),
+            None => {
+                self.pins.insert(pin, key);
+            }
+        }
+    }
+
+    fn device(&mut self, device: String, key: String) {
+        match self.devices.get(&device) {
+            Some(first) => self.problems.push(Problem::DuplicateDevice {
+                device,
+                first: first.clone(),
+                second: key,
+            }),
+            None => {
+                self.devices.insert(device, key);
+            }
+        }
+    }
+
+    fn labels<V>(&mut self, section: &'static str, entries: &BTreeMap<String, V>) {
+        for label in entries.keys() {
+            match self.labels.get(label) {
+                Some(first) => self.problems.push(Problem::LabelCollision {
+                    label: label.clone(),
+                    first,
+                    second: section,
+                }),
+                None => {
+                    self.labels.insert(label.clone(), section);
+                }
+            }
+        }
+    }
+
+    fn range(
+        &mut self,
+        key: String,
+        value: impl Into<u64>,
+        min: impl Into<u64>,
+        max: impl Into<u64>,
+    ) {
+        let (value, min, max) = (value.into(), min.into(), max.into());
+        if value < min || value > max {
+            self.problems.push(Problem::OutOfRange {
+                key,
+                value,
+                min,
+                max,
+            });
+        }
+    }
+
+    fn gpio(
+        &mut self,
+        outputs: &BTreeMap<String, GpioOutput>,
+        inputs: &BTreeMap<String, GpioInput>,
+    ) {
+        self.labels("gpio", outputs);
+        self.labels("gpio_input", inputs);
+        for (label, config) in outputs {
+            self.pin(config.pin, format!("gpio.{}.pin", label));
+        }
+        for (label, config) in inputs {
+            self.pin(config.pin, format!("gpio_input.{}.pin", label));
+            if let Some(ms) = config.debounce_ms {
+                self.range(
+                    format!("gpio_input.{}.debounce_ms", label),
+                    ms,
+                    1u32,
+                    MAX_DEBOUNCE_MS,
+                );
+            }
+        }
+    }
+
+    fn granted<V>(
+        &mut self,
+        entries: &BTreeMap<String, V>,
+        section: &'static str,
+        prefix: &str,
+        label: &str,
+    ) {
+        if !entries.contains_key(label) {
+            self.problems.push(Problem::UngrantedLabel {
+                key: format!("{}.{}", prefix, label),
+                section,
+            });
+        }
+    }
+
+    /// An initial frequency has to lie within the range the guest may choose from,
+    /// and the whole range below what the peripheral can run at.
+    fn frequencies(
+        &mut self,
+        section: &str,
+        label: &str,
+        frequency: u32,
+        (min, max): (u32, u32),
+        ceiling: u32,
+    ) {
+        self.range(
+            format!("{}.{}.frequency", section, label),
+            frequency,
+            1u32,
+            ceiling,
+        );
+        self.range(
+            format!("{}.{}.min_frequency", section, label),
+            min,
+            1u32,
+            frequency,
+        );
+        self.range(
+            format!("{}.{}.max_frequency", section, label),
+            max,
+            frequency,
+            ceiling.max(frequency),
+        );
+    }
+
+    /// A list of bus settings, when given, has to include the initial one.
+    fn initial_allowed<T: PartialEq>(
+        &mut self,
+        key: String,
+        allowed: Option<&[T]>,
+        initial: &T,
+        describe: impl FnOnce() -> String,
+    ) {
+        match allowed {
+            Some([]) => self.problems.push(Problem::Empty { key }),
+            Some(allowed) if !allowed.contains(initial) => {
+                self.problems.push(Problem::MissingInitial {
+                    key,
+                    initial: describe(),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    fn i2c_addresses(&mut self, label: &str, addresses: &[u8]) {
+        for &address in addresses {
+            self.range(format!("i2c.{}.addresses", label), address, 0u8, 0x7fu8);
+        }
+    }
+
+    fn duty(&mut self, label: &str, (min, max): (u8, u8)) {
+        self.range(format!("pwm.{}.max_duty_percent", label), max, 0u8, 100u8);
+        self.range(format!("pwm.{}.min_duty_percent", label), min, 0u8, max);
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Invalid(self.problems))
+        }
+    }
+}
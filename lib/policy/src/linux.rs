@@ -0,0 +1,557 @@
+//! Policy for the Linux host, where peripherals are device files and GPIO numbers
+//! are line offsets on the board's GPIO chip.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::num::{NonZeroU32, NonZeroU64};
+use std::path::PathBuf;
+
+use crate::manifest::Manifest;
+use crate::{
+    BitOrder, Board, Checker, Error, GpioInput, GpioOutput, Interface, Level, MAX_PWM_FREQUENCY,
+    Problem, SpiMode,
+};
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    pub version: u32,
+    pub board: Board,
+    /// The interfaces a guest may import; see [`Policy::interfaces`].
+    pub interfaces: Option<BTreeSet<Interface>>,
+    #[serde(default)]
+    pub spi: BTreeMap<String, SpiDevice>,
+    #[serde(default)]
+    pub i2c: BTreeMap<String, I2cBus>,
+    #[serde(default)]
+    pub uart: BTreeMap<String, Uart>,
+    #[serde(default)]
+    pub pwm: BTreeMap<String, Pwm>,
+    #[serde(default)]
+    pub adc: BTreeMap<String, Adc>,
+    #[serde(default)]
+    pub gpio: BTreeMap<String, GpioOutput>,
+    #[serde(default)]
+    pub gpio_input: BTreeMap<String, GpioInput>,
+    /// Devices standing in for the hardware under `--backend sim`.
+    #[serde(default)]
+    pub sim: Sim,
+    /// Faults injected into granted devices, on either backend.
+    #[serde(default)]
+    pub faults: Faults,
+}
+
+/// A spidev device such as `/dev/spidev0.0`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SpiDevice {
+    pub path: String,
+    pub frequency: u32,
+    pub mode: SpiMode,
+    pub max_bytes_per_op: Option<u64>,
+    pub max_ops_per_transaction: Option<u32>,
+    pub max_bytes_per_transaction: Option<u64>,
+    pub min_frequency: Option<u32>,
+    pub max_frequency: Option<u32>,
+    pub modes: Option<Vec<SpiMode>>,
+    pub bit_orders: Option<Vec<BitOrder>>,
+}
+
+impl SpiDevice {
+    /// Without explicit ranges the guest may only re-apply the initial configuration.
+    pub fn frequency_range(&self) -> (u32, u32) {
+        (
+            self.min_frequency.unwrap_or(self.frequency),
+            self.max_frequency.unwrap_or(self.frequency),
+        )
+    }
+
+    pub fn allowed_modes(&self) -> Vec<SpiMode> {
+        self.modes.clone().unwrap_or_else(|| vec![self.mode])
+    }
+
+    pub fn allowed_bit_orders(&self) -> Vec<BitOrder> {
+        self.bit_orders
+            .clone()
+            .unwrap_or_else(|| vec![BitOrder::MsbFirst])
+    }
+}
+
+/// An i2c-dev bus such as `/dev/i2c-1`, limited to `addresses`. Several labels may
+/// share a bus, each with its own addresses.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct I2cBus {
+    pub path: String,
+    pub addresses: Vec<u8>,
+    pub max_bytes_per_op: Option<u64>,
+    pub max_ops_per_transaction: Option<u32>,
+    pub max_bytes_per_transaction: Option<u64>,
+}
+
+/// A serial TTY at a fixed baud rate.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Uart {
+    pub path: String,
+    pub baud_rate: u32,
+    pub max_bytes_per_op: Option<u64>,
+}
+
+/// A channel of `/sys/class/pwm/pwmchip<chip>`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Pwm {
+    pub chip: u32,
+    pub channel: u32,
+    pub frequency: u32,
+    pub min_frequency: Option<u32>,
+    pub max_frequency: Option<u32>,
+    pub min_duty_percent: Option<u8>,
+    pub max_duty_percent: Option<u8>,
+}
+
+impl Pwm {
+    /// Without explicit ranges the guest may only change the duty cycle.
+    pub fn frequency_range(&self) -> (u32, u32) {
+        (
+            self.min_frequency.unwrap_or(self.frequency),
+            self.max_frequency.unwrap_or(self.frequency),
+        )
+    }
+
+    pub fn duty_range(&self) -> (u8, u8) {
+        (
+            self.min_duty_percent.unwrap_or(0),
+            self.max_duty_percent.unwrap_or(100),
+        )
+    }
+}
+
+/// A voltage channel of an IIO device directory.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Adc {
+    pub device: PathBuf,
+    pub channel: u32,
+    pub resolution_bits: u8,
+    pub reference_mv: Option<u32>,
+    pub max_samples_per_burst: Option<u32>,
+}
+
+/// The `[sim]` table. SPI labels without an entry become [`SimSpi::Null`], GPIO
+/// outputs are plain lines and inputs read low unless given a level here.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Sim {
+    #[serde(default)]
+    pub spi: BTreeMap<String, SimSpi>,
+    #[serde(default)]
+    pub gpio_input: BTreeMap<String, SimInput>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "device", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum SimSpi {
+    /// Accepts writes and reads zeros.
+    Null,
+    /// Reads back what was written in the same transfer.
+    Loopback,
+    /// Address byte with bit 7 set for reads, then auto-incrementing data.
+    Registers {
+        #[serde(default)]
+        registers: Vec<SimRegister>,
+    },
+    /// A chip described by a register map file; see [`crate::register_map`].
+    RegisterMap { map: PathBuf },
+    /// An SSD1306 display in 4-wire mode, telling commands from data by the
+    /// `dc` output. The panel is 128x32 unless sized here; frames are printed as
+    /// Unicode art, or saved as PNG files if `frames` names a directory.
+    Ssd1306 {
+        dc: String,
+        width: Option<u8>,
+        height: Option<u8>,
+        frames: Option<PathBuf>,
+        format: Option<FrameFormat>,
+    },
+    /// A BME280 reading 20 degC, 1013.25 hPa and 50 %RH unless given curves.
+    Bme280 {
+        temperature: Option<SimCurve>,
+        pressure: Option<SimCurve>,
+        humidity: Option<SimCurve>,
+    },
+}
+
+/// A simulated quantity over time: a number, a ramp such as
+/// `{ from = 20.0, to = 30.0, seconds = 60.0 }`, or `{ csv = "trace.csv" }`
+/// with `seconds,value` rows.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum SimCurve {
+    Constant(f32),
+    Ramp { from: f32, to: f32, seconds: f32 },
+    Trace { csv: PathBuf },
+}
+
+/// How the SSD1306 simulator renders frames.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameFormat {
+    Png,
+    /// `#` and `.`, one character per pixel.
+    Ascii,
+    /// Half blocks, two pixel rows per line.
+    Unicode,
+}
+
+/// Only the low 7 bits of `address` go over the wire, so datasheet addresses
+/// such as 0xD0 work as written.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SimRegister {
+    pub address: u8,
+    pub value: u8,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SimInput {
+    pub level: Level,
+}
+
+/// The `[faults]` table: rules per SPI or GPIO output label, each pairing a
+/// fault with exactly one trigger.
+///
+/// ```toml
+/// [faults]
+/// seed = 7
+///
+/// [[faults.spi.sensor]]
+/// fault = "bit-flip"
+/// bits = 2
+/// one_in = 50
+///
+/// [[faults.gpio.LED]]
+/// fault = "stuck"
+/// level = "High"
+/// calls = [10]
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Faults {
+    /// Seeds the random triggers; 0 by default, so runs repeat unless changed.
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub spi: BTreeMap<String, Vec<FaultRule<SpiFault>>>,
+    #[serde(default)]
+    pub gpio: BTreeMap<String, Vec<FaultRule<GpioFault>>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FaultRule<F> {
+    pub fault: F,
+    /// Bits to flip, for `bit-flip`; 1 by default.
+    pub bits: Option<u8>,
+    /// Delay added to the call, for `latency`.
+    pub micros: Option<u32>,
+    /// Level the pin sticks at, for `stuck`.
+    pub level: Option<Level>,
+    /// Fire on each call with probability 1/n.
+    pub one_in: Option<NonZeroU32>,
+    /// Fire on every nth call.
+    pub every: Option<NonZeroU64>,
+    /// Fire on these calls, counting from 1.
+    pub calls: Option<Vec<u64>>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpiFault {
+    /// The call reports an overrun after the transfer.
+    Overrun,
+    /// The call fails before touching the bus.
+    ModeFault,
+    ChipSelectFault,
+    /// Random bits of the data read are flipped.
+    BitFlip,
+    /// The data read is cut short, the rest reading 0xFF.
+    ShortRead,
+    Latency,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GpioFault {
+    /// The pin holds `level` from then on, whatever the guest sets.
+    Stuck,
+    Latency,
+}
+
+/// When a [`FaultRule`] fires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaultTrigger {
+    OneIn(u32),
+    Every(u64),
+    Calls(Vec<u64>),
+}
+
+impl<F> FaultRule<F> {
+    /// The rule's trigger, once validated.
+    pub fn trigger(&self) -> FaultTrigger {
+        match (self.one_in, self.every, &self.calls) {
+            (Some(n), _, _) => FaultTrigger::OneIn(n.get()),
+            (_, Some(n), _) => FaultTrigger::Every(n.get()),
+            (_, _, calls) => FaultTrigger::Calls(calls.clone().unwrap_or_default()),
+        }
+    }
+
+    /// Checks the trigger and that exactly the parameters `fault` uses are set.
+    fn check(&self, check: &mut Checker, key: &str, fault: &str, uses: &[&str]) {
+        let set = [
+            ("one_in", self.one_in.is_some()),
+            ("every", self.every.is_some()),
+            ("calls", self.calls.is_some()),
+        ];
+        let mut triggers = set.iter().filter(|(_, set)| *set).map(|(name, _)| *name);
+        match (triggers.next(), triggers.next()) {
+            (None, _) => check.problems.push(Problem::Requires {
+                key: format!("{}.fault", key),
+                requires: format!("{}.one_in`, `every` or `calls", key),
+            }),
+            (Some(first), Some(second)) => check.problems.push(Problem::Conflict {
+                key: format!("{}.{}", key, first),
+                other: format!("{}.{}", key, second),
+            }),
+            _ => {}
+        }
+
+        let params = [
+            ("bits", self.bits.is_some()),
+            ("micros", self.micros.is_some()),
+            ("level", self.level.is_some()),
+        ];
+        for (param, set) in params {
+            if set && !uses.contains(&param) {
+                check.problems.push(Problem::Inapplicable {
+                    key: format!("{}.{}", key, param),
+                    context: format!("`fault = \"{}\"`", fault),
+                });
+            }
+        }
+        if uses.contains(&"micros") && self.micros.is_none() {
+            check.problems.push(Problem::Requires {
+                key: format!("{}.fault", key),
+                requires: format!("{}.micros", key),
+            });
+        }
+        if uses.contains(&"level") && self.level.is_none() {
+            check.problems.push(Problem::Requires {
+                key: format!("{}.fault", key),
+                requires: format!("{}.level", key),
+            });
+        }
+    }
+}
+
+impl Policy {
+    pub fn from_toml(source: &str) -> Result<Self, Error> {
+        let policy: Self = crate::parse(source, Board::Linux)?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// The interfaces the host links for a guest. Without an `interfaces` list,
+    /// those of every section that grants a label, plus [`Interface::BASE`].
+    pub fn interfaces(&self) -> BTreeSet<Interface> {
+        if let Some(listed) = &self.interfaces {
+            return listed.clone();
+        }
+        Interface::BASE
+            .into_iter()
+            .chain(
+                self.sections()
+                    .into_iter()
+                    .filter(|&(_, _, used)| used)
+                    .map(|(_, interface, _)| interface),
+            )
+            .collect()
+    }
+
+    fn sections(&self) -> [(&'static str, Interface, bool); 7] {
+        [
+            ("spi", Interface::Spi, !self.spi.is_empty()),
+            ("i2c", Interface::I2c, !self.i2c.is_empty()),
+            ("uart", Interface::Uart, !self.uart.is_empty()),
+            ("pwm", Interface::Pwm, !self.pwm.is_empty()),
+            ("adc", Interface::Adc, !self.adc.is_empty()),
+            ("gpio", Interface::Gpio, !self.gpio.is_empty()),
+            ("gpio_input", Interface::Gpio, !self.gpio_input.is_empty()),
+        ]
+    }
+
+    /// The labels this policy grants, to check a guest's manifest against.
+    pub fn granted(&self) -> Manifest {
+        Manifest {
+            spi: Manifest::labels(&self.spi),
+            i2c: Manifest::labels(&self.i2c),
+            uart: Manifest::labels(&self.uart),
+            pwm: Manifest::labels(&self.pwm),
+            adc: Manifest::labels(&self.adc),
+            gpio: Manifest::labels(&self.gpio),
+            gpio_input: Manifest::labels(&self.gpio_input),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let mut check = Checker::default();
+
+        check.labels("spi", &self.spi);
+        check.labels("i2c", &self.i2c);
+        check.labels("uart", &self.uart);
+        check.labels("pwm", &self.pwm);
+        check.labels("adc", &self.adc);
+        check.gpio(&self.gpio, &self.gpio_input);
+
+        if let Some(listed) = &self.interfaces {
+            for (section, interface, used) in self.sections() {
+                if used && !listed.contains(&interface) {
+                    check
+                        .problems
+                        .push(Problem::UnlistedInterface { section, interface });
+                }
+            }
+        }
+
+        for (label, config) in &self.spi {
+            check.device(config.path.clone(), format!("spi.{}.path", label));
+            check.frequencies(
+                "spi",
+                label,
+                config.frequency,
+                config.frequency_range(),
+                u32::MAX,
+            );
+            check.initial_allowed(
+                format!("spi.{}.modes", label),
+                config.modes.as_deref(),
+                &config.mode,
+                || format!("`spi.{}.mode`", label),
+            );
+            check.initial_allowed(
+                format!("spi.{}.bit_orders", label),
+                config.bit_orders.as_deref(),
+                &BitOrder::MsbFirst,
+                || "\"msb-first\"".to_string(),
+            );
+        }
+
+        for (label, config) in &self.i2c {
+            check.i2c_addresses(label, &config.addresses);
+        }
+
+        for (label, config) in &self.uart {
+            check.device(config.path.clone(), format!("uart.{}.path", label));
+        }
+
+        for (label, config) in &self.pwm {
+            check.device(
+                format!("pwmchip{}/pwm{}", config.chip, config.channel),
+                format!("pwm.{}", label),
+            );
+            check.frequencies(
+                "pwm",
+                label,
+                config.frequency,
+                config.frequency_range(),
+                MAX_PWM_FREQUENCY,
+            );
+            check.duty(label, config.duty_range());
+        }
+
+        for (label, config) in &self.adc {
+            check.device(
+                format!(
+                    "{}/in_voltage{}_raw",
+                    config.device.display(),
+                    config.channel
+                ),
+                format!("adc.{}", label),
+            );
+            check.range(
+                format!("adc.{}.resolution_bits", label),
+                config.resolution_bits,
+                1u8,
+                16u8,
+            );
+        }
+
+        for (label, device) in &self.sim.spi {
+            check.granted(&self.spi, "spi", "sim.spi", label);
+            if let SimSpi::Ssd1306 {
+                dc,
+                width,
+                height,
+                frames,
+                format,
+            } = device
+            {
+                let key = format!("sim.spi.{}", label);
+                if !self.gpio.contains_key(dc) {
+                    check.problems.push(Problem::UnknownLabel {
+                        key: format!("{}.dc", key),
+                        label: dc.clone(),
+                        section: "gpio",
+                    });
+                }
+                if let Some(width) = width {
+                    check.range(format!("{}.width", key), *width, 1u8, 128u8);
+                }
+                if let Some(height) = height {
+                    check.range(format!("{}.height", key), *height, 1u8, 64u8);
+                }
+                if frames.is_none() && *format == Some(FrameFormat::Png) {
+                    check.problems.push(Problem::Requires {
+                        key: format!("{}.format", key),
+                        requires: format!("{}.frames", key),
+                    });
+                }
+            }
+        }
+        for label in self.sim.gpio_input.keys() {
+            check.granted(&self.gpio_input, "gpio_input", "sim.gpio_input", label);
+        }
+
+        for (label, rules) in &self.faults.spi {
+            check.granted(&self.spi, "spi", "faults.spi", label);
+            for (i, rule) in rules.iter().enumerate() {
+                let (fault, uses): (&str, &[&str]) = match rule.fault {
+                    SpiFault::Overrun => ("overrun", &[]),
+                    SpiFault::ModeFault => ("mode-fault", &[]),
+                    SpiFault::ChipSelectFault => ("chip-select-fault", &[]),
+                    SpiFault::BitFlip => ("bit-flip", &["bits"]),
+                    SpiFault::ShortRead => ("short-read", &[]),
+                    SpiFault::Latency => ("latency", &["micros"]),
+                };
+                let key = format!("faults.spi.{}[{}]", label, i);
+                rule.check(&mut check, &key, fault, uses);
+                if let Some(bits) = rule.bits {
+                    check.range(format!("{}.bits", key), bits, 1u8, 64u8);
+                }
+            }
+        }
+        for (label, rules) in &self.faults.gpio {
+            check.granted(&self.gpio, "gpio", "faults.gpio", label);
+            for (i, rule) in rules.iter().enumerate() {
+                let (fault, uses): (&str, &[&str]) = match rule.fault {
+                    GpioFault::Stuck => ("stuck", &["level"]),
+                    GpioFault::Latency => ("latency", &["micros"]),
+                };
+                let key = format!("faults.gpio.{}[{}]", label, i);
+                rule.check(&mut check, &key, fault, uses);
+            }
+        }
+
+        check.finish()
+    }
+}
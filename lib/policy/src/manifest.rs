@@ -0,0 +1,221 @@
+//! The hardware a guest declares it needs, embedded in its component as the
+//! `embedded-manifest` custom section by `wasi_embedded_hal::manifest!`.
+//!
+//! Checking it against a policy before running (or compiling for the Pico) turns
+//! a label typo into one readable error instead of a panic halfway through boot.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::{Board, Error, linux, pico};
+
+/// Name of the custom section holding the manifest.
+pub const SECTION: &str = "embedded-manifest";
+
+/// Ends the TOML of each `manifest!` invocation within the section.
+pub const CHUNK_END: char = '\0';
+
+/// Labels per policy section, as required by a guest or granted by a policy.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub spi: BTreeSet<String>,
+    #[serde(default)]
+    pub i2c: BTreeSet<String>,
+    #[serde(default)]
+    pub uart: BTreeSet<String>,
+    #[serde(default)]
+    pub pwm: BTreeSet<String>,
+    #[serde(default)]
+    pub adc: BTreeSet<String>,
+    #[serde(default)]
+    pub gpio: BTreeSet<String>,
+    #[serde(default)]
+    pub gpio_input: BTreeSet<String>,
+}
+
+impl Manifest {
+    pub fn from_toml(source: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(source)
+    }
+
+    /// The labels a policy for either board grants, going by its `board` key.
+    pub fn from_policy(source: &str) -> Result<Self, Error> {
+        match pico::Policy::from_toml(source) {
+            Err(Error::WrongBoard {
+                found: Board::Linux,
+                ..
+            }) => Ok(linux::Policy::from_toml(source)?.granted()),
+            policy => Ok(policy?.granted()),
+        }
+    }
+
+    /// Collects the labels of one policy section from its table.
+    pub(crate) fn labels<V>(entries: &BTreeMap<String, V>) -> BTreeSet<String> {
+        entries.keys().cloned().collect()
+    }
+
+    fn sections(&self) -> [(&'static str, &BTreeSet<String>); 7] {
+        [
+            ("spi", &self.spi),
+            ("i2c", &self.i2c),
+            ("uart", &self.uart),
+            ("pwm", &self.pwm),
+            ("adc", &self.adc),
+            ("gpio", &self.gpio),
+            ("gpio_input", &self.gpio_input),
+        ]
+    }
+
+    #[cfg(feature = "component")]
+    fn extend(&mut self, other: Manifest) {
+        self.spi.extend(other.spi);
+        self.i2c.extend(other.i2c);
+        self.uart.extend(other.uart);
+        self.pwm.extend(other.pwm);
+        self.adc.extend(other.adc);
+        self.gpio.extend(other.gpio);
+        self.gpio_input.extend(other.gpio_input);
+    }
+
+    /// Compares what this guest requires with what a policy `granted`.
+    pub fn diff(&self, granted: &Manifest) -> Diff {
+        let mut diff = Diff::default();
+        for ((section, required), (_, granted)) in
+            self.sections().into_iter().zip(granted.sections())
+        {
+            for label in required.difference(granted) {
+                diff.missing.push(format!("{}.{}", section, label));
+            }
+            for label in granted.difference(required) {
+                diff.unused.push(format!("{}.{}", section, label));
+            }
+        }
+        diff
+    }
+
+    /// Reads the manifest from a component or core module. Sections from nested
+    /// modules are merged; a guest built without one yields `None`.
+    #[cfg(feature = "component")]
+    pub fn from_component(wasm: &[u8]) -> Result<Option<Self>, ManifestError> {
+        let mut found: Option<Self> = None;
+        for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+            if let wasmparser::Payload::CustomSection(section) = payload?
+                && section.name() == SECTION
+            {
+                let text =
+                    std::str::from_utf8(section.data()).map_err(|_| ManifestError::NotUtf8)?;
+                found
+                    .get_or_insert_default()
+                    .extend(Self::from_section(text)?);
+            }
+        }
+        Ok(found)
+    }
+
+    /// The linker concatenates every `manifest!` in a module into one section,
+    /// each ending in [`CHUNK_END`], so the chunks are parsed one by one.
+    #[cfg(feature = "component")]
+    fn from_section(text: &str) -> Result<Self, toml::de::Error> {
+        let mut manifest = Self::default();
+        for chunk in text.split(CHUNK_END) {
+            manifest.extend(Self::from_toml(chunk)?);
+        }
+        Ok(manifest)
+    }
+}
+
+/// Labels as dotted keys, such as `spi.screen`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Diff {
+    /// Required by the guest but not granted; the guest cannot run.
+    pub missing: Vec<String>,
+    /// Granted but not declared by the guest.
+    pub unused: Vec<String>,
+}
+
+impl Diff {
+    pub fn is_satisfied(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = self
+            .missing
+            .iter()
+            .map(|key| format!("- {} (required by the guest, missing from the policy)", key))
+            .chain(
+                self.unused
+                    .iter()
+                    .map(|key| format!("+ {} (granted by the policy, unused by the guest)", key)),
+            );
+        if let Some(first) = lines.next() {
+            write!(f, "  {}", first)?;
+        }
+        for line in lines {
+            write!(f, "\n  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "component")]
+#[derive(Debug)]
+pub enum ManifestError {
+    Wasm(wasmparser::BinaryReaderError),
+    NotUtf8,
+    Parse(toml::de::Error),
+    /// The policy lacks labels the guest requires.
+    Unsatisfied(Diff),
+}
+
+#[cfg(feature = "component")]
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Wasm(err) => write!(f, "failed to read the component: {}", err),
+            ManifestError::NotUtf8 => write!(f, "the `{}` section is not UTF-8", SECTION),
+            ManifestError::Parse(err) => write!(f, "invalid guest manifest: {}", err),
+            ManifestError::Unsatisfied(diff) => write!(
+                f,
+                "the policy does not grant everything the guest requires:\n{}",
+                diff
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "component")]
+impl std::error::Error for ManifestError {}
+
+#[cfg(feature = "component")]
+impl From<wasmparser::BinaryReaderError> for ManifestError {
+    fn from(err: wasmparser::BinaryReaderError) -> Self {
+        ManifestError::Wasm(err)
+    }
+}
+
+#[cfg(feature = "component")]
+impl From<toml::de::Error> for ManifestError {
+    fn from(err: toml::de::Error) -> Self {
+        ManifestError::Parse(err)
+    }
+}
+
+/// Checks a guest component against the labels a policy `granted`. Returns the
+/// diff when the guest has a manifest, and `None` for guests built without one.
+#[cfg(feature = "component")]
+pub fn check_component(wasm: &[u8], granted: &Manifest) -> Result<Option<Diff>, ManifestError> {
+    let Some(manifest) = Manifest::from_component(wasm)? else {
+        return Ok(None);
+    };
+    let diff = manifest.diff(granted);
+    if !diff.is_satisfied() {
+        return Err(ManifestError::Unsatisfied(diff));
+    }
+    Ok(Some(diff))
+}
@@ -0,0 +1,273 @@
+//! Policy for the Pico 2, where every peripheral is named by RP2350 block and GPIO number.
+//! It is read by the host's build script and compiled into the firmware.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::manifest::Manifest;
+use crate::{Board, Checker, Error, GpioInput, GpioOutput, MAX_PWM_FREQUENCY, Problem, SpiMode};
+use rp2350::Function;
+
+pub mod rp2350;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    pub version: u32,
+    pub board: Board,
+    #[serde(default)]
+    pub spi_bus: BTreeMap<String, SpiBus>,
+    #[serde(default)]
+    pub spi: BTreeMap<String, SpiDevice>,
+    #[serde(default)]
+    pub i2c: BTreeMap<String, I2cBus>,
+    #[serde(default)]
+    pub uart: BTreeMap<String, Uart>,
+    #[serde(default)]
+    pub pwm: BTreeMap<String, Pwm>,
+    #[serde(default)]
+    pub adc: BTreeMap<String, Adc>,
+    #[serde(default)]
+    pub gpio: BTreeMap<String, GpioOutput>,
+    #[serde(default)]
+    pub gpio_input: BTreeMap<String, GpioInput>,
+}
+
+/// An RP2350 SPI block (keyed by `SPI0`/`SPI1`) and the pins it drives.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SpiBus {
+    pub sck: u8,
+    pub mosi: u8,
+    pub miso: u8,
+}
+
+/// A device on one of the `[spi_bus]` blocks, selected by its own `cs` pin.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SpiDevice {
+    pub bus: String,
+    pub cs: u8,
+    pub frequency: u32,
+    pub mode: SpiMode,
+    pub max_bytes_per_op: Option<u64>,
+    pub max_ops_per_transaction: Option<u32>,
+    pub max_bytes_per_transaction: Option<u64>,
+    pub min_frequency: Option<u32>,
+    pub max_frequency: Option<u32>,
+    pub modes: Option<Vec<SpiMode>>,
+}
+
+impl SpiDevice {
+    /// Without explicit ranges the guest may only re-apply the initial configuration.
+    pub fn frequency_range(&self) -> (u32, u32) {
+        (
+            self.min_frequency.unwrap_or(self.frequency),
+            self.max_frequency.unwrap_or(self.frequency),
+        )
+    }
+
+    pub fn allowed_modes(&self) -> Vec<SpiMode> {
+        self.modes.clone().unwrap_or_else(|| vec![self.mode])
+    }
+}
+
+/// An RP2350 I2C block (`I2C0`/`I2C1`) granted as one bus, limited to `addresses`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct I2cBus {
+    pub block: String,
+    pub sda: u8,
+    pub scl: u8,
+    pub frequency: u32,
+    pub addresses: Vec<u8>,
+    pub max_bytes_per_op: Option<u64>,
+    pub max_ops_per_transaction: Option<u32>,
+    pub max_bytes_per_transaction: Option<u64>,
+}
+
+/// An RP2350 UART block (`UART0`/`UART1`) and its pins.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Uart {
+    pub block: String,
+    pub tx: u8,
+    pub rx: u8,
+    pub baud_rate: u32,
+    pub max_bytes_per_op: Option<u64>,
+}
+
+/// A PWM output pin; the channel takes over the whole slice the pin belongs to.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Pwm {
+    pub pin: u8,
+    pub frequency: u32,
+    pub min_frequency: Option<u32>,
+    pub max_frequency: Option<u32>,
+    pub min_duty_percent: Option<u8>,
+    pub max_duty_percent: Option<u8>,
+}
+
+impl Pwm {
+    pub fn slice(&self) -> u8 {
+        rp2350::pwm_slice(self.pin)
+    }
+
+    /// Without explicit ranges the guest may only change the duty cycle.
+    pub fn frequency_range(&self) -> (u32, u32) {
+        (
+            self.min_frequency.unwrap_or(self.frequency),
+            self.max_frequency.unwrap_or(self.frequency),
+        )
+    }
+
+    pub fn duty_range(&self) -> (u8, u8) {
+        (
+            self.min_duty_percent.unwrap_or(0),
+            self.max_duty_percent.unwrap_or(100),
+        )
+    }
+}
+
+/// An analog input on one of the ADC-capable pins (GPIO 26 to 29).
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Adc {
+    pub pin: u8,
+    pub reference_mv: Option<u32>,
+    pub max_samples_per_burst: Option<u32>,
+}
+
+impl Policy {
+    pub fn from_toml(source: &str) -> Result<Self, Error> {
+        let policy: Self = crate::parse(source, Board::Pico)?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// The labels this policy grants, to check a guest's manifest against.
+    pub fn granted(&self) -> Manifest {
+        Manifest {
+            spi: Manifest::labels(&self.spi),
+            i2c: Manifest::labels(&self.i2c),
+            uart: Manifest::labels(&self.uart),
+            pwm: Manifest::labels(&self.pwm),
+            adc: Manifest::labels(&self.adc),
+            gpio: Manifest::labels(&self.gpio),
+            gpio_input: Manifest::labels(&self.gpio_input),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let mut check = Checker::default();
+
+        check.labels("spi", &self.spi);
+        check.labels("i2c", &self.i2c);
+        check.labels("uart", &self.uart);
+        check.labels("pwm", &self.pwm);
+        check.labels("adc", &self.adc);
+        check.gpio(&self.gpio, &self.gpio_input);
+
+        for (label, config) in &self.gpio {
+            check.range(
+                format!("gpio.{}.pin", label),
+                config.pin,
+                0u8,
+                rp2350::GPIO_COUNT - 1,
+            );
+        }
+        for (label, config) in &self.gpio_input {
+            check.range(
+                format!("gpio_input.{}.pin", label),
+                config.pin,
+                0u8,
+                rp2350::GPIO_COUNT - 1,
+            );
+        }
+
+        for (block, bus) in &self.spi_bus {
+            let key = format!("spi_bus.{}", block);
+            let number = check.rp_block(&rp2350::SPI_BLOCKS, block, key.clone());
+            check.rp_function(bus.sck, format!("{}.sck", key), Function::SpiSck, number);
+            check.rp_function(bus.mosi, format!("{}.mosi", key), Function::SpiTx, number);
+            check.rp_function(bus.miso, format!("{}.miso", key), Function::SpiRx, number);
+        }
+
+        for (label, config) in &self.spi {
+            if !self.spi_bus.contains_key(&config.bus) {
+                check.problems.push(Problem::UnknownSpiBus {
+                    device: label.clone(),
+                    bus: config.bus.clone(),
+                });
+            }
+            // Chip selects are driven as plain outputs, so any free pin will do
+            check.rp_pin(config.cs, format!("spi.{}.cs", label));
+            check.frequencies(
+                "spi",
+                label,
+                config.frequency,
+                config.frequency_range(),
+                u32::MAX,
+            );
+            check.initial_allowed(
+                format!("spi.{}.modes", label),
+                config.modes.as_deref(),
+                &config.mode,
+                || format!("`spi.{}.mode`", label),
+            );
+        }
+
+        for (label, config) in &self.i2c {
+            let key = format!("i2c.{}", label);
+            check.device(config.block.clone(), format!("{}.block", key));
+            let number =
+                check.rp_block(&rp2350::I2C_BLOCKS, &config.block, format!("{}.block", key));
+            check.rp_function(config.sda, format!("{}.sda", key), Function::I2cSda, number);
+            check.rp_function(config.scl, format!("{}.scl", key), Function::I2cScl, number);
+            check.i2c_addresses(label, &config.addresses);
+        }
+
+        for (label, config) in &self.uart {
+            let key = format!("uart.{}", label);
+            check.device(config.block.clone(), format!("{}.block", key));
+            let number = check.rp_block(
+                &rp2350::UART_BLOCKS,
+                &config.block,
+                format!("{}.block", key),
+            );
+            check.rp_function(config.tx, format!("{}.tx", key), Function::UartTx, number);
+            check.rp_function(config.rx, format!("{}.rx", key), Function::UartRx, number);
+        }
+
+        for (label, config) in &self.pwm {
+            // Every pin has a PWM output, but both outputs of a slice share its frequency
+            let key = format!("pwm.{}.pin", label);
+            check.rp_pin(config.pin, key.clone());
+            check.device(format!("PWM slice {}", config.slice()), key);
+            check.frequencies(
+                "pwm",
+                label,
+                config.frequency,
+                config.frequency_range(),
+                MAX_PWM_FREQUENCY,
+            );
+            check.duty(label, config.duty_range());
+        }
+
+        for (label, config) in &self.adc {
+            let key = format!("adc.{}.pin", label);
+            if !rp2350::ADC_PINS.contains(&config.pin) {
+                check.problems.push(Problem::PinFunction {
+                    key: key.clone(),
+                    pin: config.pin,
+                    function: "an ADC input".to_string(),
+                    valid: rp2350::ADC_PINS.to_vec(),
+                });
+            }
+            check.rp_pin(config.pin, key);
+        }
+
+        check.finish()
+    }
+}
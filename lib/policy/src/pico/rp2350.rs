@@ -0,0 +1,166 @@
+//! GPIO function select table of the RP2350A (GPIO 0 to 29), the chip on the Pico 2.
+//! Only the functions the host can hand to a guest are listed.
+
+use crate::{Checker, Problem};
+
+pub const GPIO_COUNT: u8 = 30;
+
+/// The ADC inputs, ADC0 to ADC3.
+pub const ADC_PINS: [u8; 4] = [26, 27, 28, 29];
+
+pub const SPI_BLOCKS: [&str; 2] = ["SPI0", "SPI1"];
+pub const I2C_BLOCKS: [&str; 2] = ["I2C0", "I2C1"];
+pub const UART_BLOCKS: [&str; 2] = ["UART0", "UART1"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    SpiRx,
+    SpiTx,
+    SpiSck,
+    I2cSda,
+    I2cScl,
+    UartTx,
+    UartRx,
+}
+
+impl Function {
+    fn describe(self, block: u8) -> String {
+        let (peripheral, signal) = match self {
+            Function::SpiRx => ("SPI", "RX (miso)"),
+            Function::SpiTx => ("SPI", "TX (mosi)"),
+            Function::SpiSck => ("SPI", "SCK"),
+            Function::I2cSda => ("I2C", "SDA"),
+            Function::I2cScl => ("I2C", "SCL"),
+            Function::UartTx => ("UART", "TX"),
+            Function::UartRx => ("UART", "RX"),
+        };
+        format!("{}{} {}", peripheral, block, signal)
+    }
+}
+
+/// The block whose `function` the pin can carry, if any. The pattern repeats
+/// every 8 pins for SPI and UART and every 4 pins for I2C.
+pub fn block(function: Function, pin: u8) -> Option<u8> {
+    if pin >= GPIO_COUNT {
+        return None;
+    }
+    match (function, pin % 4) {
+        (Function::SpiRx, 0) | (Function::SpiSck, 2) | (Function::SpiTx, 3) => Some((pin / 8) % 2),
+        (Function::I2cSda, 0 | 2) | (Function::I2cScl, 1 | 3) => Some((pin / 2) % 2),
+        (Function::UartTx, 0) | (Function::UartRx, 1) => Some(((pin + 4) / 8) % 2),
+        _ => None,
+    }
+}
+
+/// Every pin that can carry `function` for `block`.
+pub fn pins(function: Function, block: u8) -> Vec<u8> {
+    (0..GPIO_COUNT)
+        .filter(|&pin| self::block(function, pin) == Some(block))
+        .collect()
+}
+
+/// GPIO n drives output A (even n) or B (odd n) of slice (n / 2) % 8.
+pub fn pwm_slice(pin: u8) -> u8 {
+    (pin / 2) % 8
+}
+
+/// The number of a block name such as `SPI1`, if it is one of `blocks`.
+pub fn block_number(blocks: &[&str], name: &str) -> Option<u8> {
+    blocks
+        .iter()
+        .position(|&block| block == name)
+        .map(|index| index as u8)
+}
+
+impl Checker {
+    /// Any pin at all, such as a chip select or a plain GPIO.
+    pub(crate) fn rp_pin(&mut self, pin: u8, key: String) {
+        self.range(key.clone(), pin, 0u8, GPIO_COUNT - 1);
+        self.pin(pin.into(), key);
+    }
+
+    /// A pin wired to a peripheral block, which only some pins can reach. With an
+    /// unknown block (already reported) only the pin itself is checked.
+    pub(crate) fn rp_function(
+        &mut self,
+        pin: u8,
+        key: String,
+        function: Function,
+        block: Option<u8>,
+    ) {
+        if let Some(block) = block
+            && pin < GPIO_COUNT
+            && self::block(function, pin) != Some(block)
+        {
+            self.problems.push(Problem::PinFunction {
+                key: key.clone(),
+                pin,
+                function: function.describe(block),
+                valid: pins(function, block),
+            });
+        }
+        self.rp_pin(pin, key);
+    }
+
+    /// Checks a block name and returns its number.
+    pub(crate) fn rp_block(&mut self, blocks: &[&str], name: &str, key: String) -> Option<u8> {
+        let number = block_number(blocks, name);
+        if number.is_none() {
+            self.problems.push(Problem::UnknownBlock {
+                key,
+                block: name.to_string(),
+                valid: blocks.iter().map(|block| block.to_string()).collect(),
+            });
+        }
+        number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The F1 (SPI), F2 (UART) and F3 (I2C) columns of the RP2350 datasheet's
+    /// GPIO function table, for the RP2350A pins.
+    #[test]
+    fn pins_match_the_datasheet() {
+        let table: [(Function, u8, &[u8]); 14] = [
+            (Function::SpiRx, 0, &[0, 4, 16, 20]),
+            (Function::SpiSck, 0, &[2, 6, 18, 22]),
+            (Function::SpiTx, 0, &[3, 7, 19, 23]),
+            (Function::SpiRx, 1, &[8, 12, 24, 28]),
+            (Function::SpiSck, 1, &[10, 14, 26]),
+            (Function::SpiTx, 1, &[11, 15, 27]),
+            (Function::UartTx, 0, &[0, 12, 16, 28]),
+            (Function::UartRx, 0, &[1, 13, 17, 29]),
+            (Function::UartTx, 1, &[4, 8, 20, 24]),
+            (Function::UartRx, 1, &[5, 9, 21, 25]),
+            (Function::I2cSda, 0, &[0, 4, 8, 12, 16, 20, 24, 28]),
+            (Function::I2cScl, 0, &[1, 5, 9, 13, 17, 21, 25, 29]),
+            (Function::I2cSda, 1, &[2, 6, 10, 14, 18, 22, 26]),
+            (Function::I2cScl, 1, &[3, 7, 11, 15, 19, 23, 27]),
+        ];
+        for (function, block, expected) in table {
+            assert_eq!(
+                pins(function, block),
+                expected,
+                "{}",
+                function.describe(block)
+            );
+        }
+    }
+
+    #[test]
+    fn pins_past_the_package_have_no_function() {
+        assert_eq!(block(Function::I2cSda, GPIO_COUNT), None);
+        assert_eq!(block(Function::UartTx, 32), None);
+    }
+
+    #[test]
+    fn pwm_slices_wrap_every_16_pins() {
+        assert_eq!(pwm_slice(0), 0);
+        assert_eq!(pwm_slice(15), 7);
+        assert_eq!(pwm_slice(16), 0);
+        assert_eq!(pwm_slice(29), 6);
+    }
+}
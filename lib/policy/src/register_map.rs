@@ -0,0 +1,260 @@
+//! Register maps for `device = "register-map"` under `[sim.spi]`: a TOML file
+//! per chip describing how it frames SPI transfers and what its registers hold,
+//! so simple peripherals can be simulated without writing a model for them.
+//!
+//! ```toml
+//! address_bits = 6   # the register address is the low 6 bits of the first byte
+//! read_bit = 7       # bit 7 set marks a read (or `write_bit` for the reverse)
+//! increment_bit = 6  # auto-increment only when bit 6 is set
+//! bytes = 1          # register width
+//!
+//! [[registers]]
+//! address = 0x00
+//! name = "DEVID"
+//! reset = 0xE5
+//! access = "read-only"
+//!
+//! [[registers]]
+//! address = 0x30
+//! name = "INT_SOURCE"
+//! reset = 0x83
+//! access = "read-only"
+//! clear_on_read = true
+//! ```
+//!
+//! Addresses the map doesn't list read as zero and ignore writes.
+
+use serde::Deserialize;
+
+use crate::{Checker, Error, Problem};
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterMap {
+    /// Low bits of the first byte that hold the register address; 7 by default.
+    pub address_bits: Option<u8>,
+    /// Bit of the first byte that is set for reads; bit 7 unless `write_bit` is given.
+    pub read_bit: Option<u8>,
+    /// Bit of the first byte that is set for writes, for chips that clear it to read.
+    pub write_bit: Option<u8>,
+    /// Whether the address advances after every register; true by default.
+    pub auto_increment: Option<bool>,
+    /// Bit of the first byte that turns auto-increment on for that transfer only.
+    pub increment_bit: Option<u8>,
+    /// Register width in bytes, 1 to 4; 1 by default.
+    pub bytes: Option<u8>,
+    /// Order of the bytes of wider registers on the wire.
+    #[serde(default)]
+    pub byte_order: ByteOrder,
+    #[serde(default)]
+    pub registers: Vec<Register>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Register {
+    pub address: u8,
+    /// Only used in error messages.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub reset: u32,
+    #[serde(default)]
+    pub access: Access,
+    /// Reading the register sets it back to zero.
+    #[serde(default)]
+    pub clear_on_read: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Access {
+    #[default]
+    ReadWrite,
+    /// Writes are ignored.
+    ReadOnly,
+    /// Reads return zero.
+    WriteOnly,
+}
+
+impl RegisterMap {
+    pub fn from_toml(source: &str) -> Result<Self, Error> {
+        let map: Self = toml::from_str(source)?;
+        map.validate()?;
+        Ok(map)
+    }
+
+    pub fn address_bits(&self) -> u8 {
+        self.address_bits.unwrap_or(7)
+    }
+
+    pub fn bytes(&self) -> u8 {
+        self.bytes.unwrap_or(1)
+    }
+
+    /// The direction bit and whether it being set means a read.
+    pub fn direction_bit(&self) -> (u8, bool) {
+        match (self.read_bit, self.write_bit) {
+            (_, Some(bit)) => (bit, false),
+            (bit, None) => (bit.unwrap_or(7), true),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let mut check = Checker::default();
+        let address_bits = self.address_bits();
+        check.range("address_bits".to_string(), address_bits, 1u8, 7u8);
+
+        if self.read_bit.is_some() && self.write_bit.is_some() {
+            check.problems.push(Problem::Conflict {
+                key: "read_bit".to_string(),
+                other: "write_bit".to_string(),
+            });
+        }
+        if self.increment_bit.is_some() && self.auto_increment.is_some() {
+            check.problems.push(Problem::Conflict {
+                key: "increment_bit".to_string(),
+                other: "auto_increment".to_string(),
+            });
+        }
+        let (direction, _) = self.direction_bit();
+        let key = if self.write_bit.is_some() {
+            "write_bit"
+        } else {
+            "read_bit"
+        };
+        let mut bits = vec![(key, direction)];
+        bits.extend(self.increment_bit.map(|bit| ("increment_bit", bit)));
+        for (key, bit) in bits {
+            check.range(key.to_string(), bit, address_bits, 7u8);
+            check.device(format!("bit {}", bit), key.to_string());
+        }
+
+        check.range("bytes".to_string(), self.bytes(), 1u8, 4u8);
+        let max = u64::MAX >> (64 - 8 * u32::from(self.bytes().clamp(1, 4)));
+        for (i, register) in self.registers.iter().enumerate() {
+            let key = match &register.name {
+                Some(name) => format!("registers.{}", name),
+                None => format!("registers[{}]", i),
+            };
+            check.range(
+                format!("{}.address", key),
+                register.address,
+                0u8,
+                (1u64 << address_bits.min(8)) - 1,
+            );
+            check.range(format!("{}.reset", key), register.reset, 0u64, max);
+            check.device(
+                format!("register {:#04x}", register.address),
+                format!("{}.address", key),
+            );
+        }
+
+        check.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A name, a map and what it must be rejected with.
+    type Case = (&'static str, &'static str, fn(&Problem) -> bool);
+
+    fn problems(source: &str) -> Vec<Problem> {
+        match RegisterMap::from_toml(source) {
+            Err(Error::Invalid(problems)) => problems,
+            other => panic!("expected problems, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn the_documented_map_is_accepted() {
+        let source = "address_bits = 6\nread_bit = 7\nincrement_bit = 6\nbytes = 1\n\
+                      [[registers]]\naddress = 0x00\nname = \"DEVID\"\nreset = 0xE5\naccess = \"read-only\"\n\
+                      [[registers]]\naddress = 0x30\nname = \"INT_SOURCE\"\nreset = 0x83\n\
+                      access = \"read-only\"\nclear_on_read = true\n";
+        let map = RegisterMap::from_toml(source).unwrap();
+        assert_eq!(map.direction_bit(), (7, true));
+        assert_eq!(map.registers[1].access, Access::ReadOnly);
+        assert!(map.registers[1].clear_on_read);
+
+        let map = RegisterMap::from_toml("write_bit = 7\n").unwrap();
+        assert_eq!((map.address_bits(), map.bytes()), (7, 1));
+        assert_eq!(map.direction_bit(), (7, false));
+    }
+
+    #[test]
+    fn inconsistent_maps_are_rejected() {
+        #[rustfmt::skip]
+        let cases: [Case; 10] = [
+            (
+                "both direction bits",
+                "read_bit = 7\nwrite_bit = 7\n",
+                |p| matches!(p, Problem::Conflict { key, other } if key == "read_bit" && other == "write_bit"),
+            ),
+            (
+                "an increment bit and fixed auto-increment",
+                "address_bits = 6\nincrement_bit = 6\nauto_increment = false\n",
+                |p| matches!(p, Problem::Conflict { key, .. } if key == "increment_bit"),
+            ),
+            (
+                "no address bits",
+                "address_bits = 0\n",
+                |p| matches!(p, Problem::OutOfRange { key, min: 1, max: 7, .. } if key == "address_bits"),
+            ),
+            (
+                "an address filling the whole byte",
+                "address_bits = 8\n",
+                |p| matches!(p, Problem::OutOfRange { key, value: 8, .. } if key == "address_bits"),
+            ),
+            (
+                "an increment bit inside the address",
+                "address_bits = 6\nincrement_bit = 5\n",
+                |p| matches!(p, Problem::OutOfRange { key, min: 6, .. } if key == "increment_bit"),
+            ),
+            (
+                "the increment and direction on one bit",
+                "address_bits = 6\nincrement_bit = 7\n",
+                |p| matches!(p, Problem::DuplicateDevice { device, .. } if device == "bit 7"),
+            ),
+            (
+                "registers wider than 4 bytes",
+                "bytes = 5\n",
+                |p| matches!(p, Problem::OutOfRange { key, .. } if key == "bytes"),
+            ),
+            (
+                "a reset value wider than its register",
+                "bytes = 2\n[[registers]]\naddress = 1\nname = \"CTRL\"\nreset = 0x10000\n",
+                |p| matches!(p, Problem::OutOfRange { key, max: 0xffff, .. } if key == "registers.CTRL.reset"),
+            ),
+            (
+                "an address the framing can't reach",
+                "address_bits = 4\n[[registers]]\naddress = 0x10\n",
+                |p| matches!(p, Problem::OutOfRange { key, max: 0xf, .. } if key == "registers[0].address"),
+            ),
+            (
+                "two registers at one address",
+                "[[registers]]\naddress = 3\n[[registers]]\naddress = 3\nname = \"AGAIN\"\n",
+                |p| matches!(p, Problem::DuplicateDevice { device, .. } if device == "register 0x03"),
+            ),
+        ];
+
+        for (name, source, expected) in cases {
+            let problems = problems(source);
+            assert!(
+                problems.iter().any(expected),
+                "{}: got {:?}",
+                name,
+                problems
+            );
+        }
+    }
+}
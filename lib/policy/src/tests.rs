@@ -0,0 +1,244 @@
+use crate::{Board, Error, Problem, linux, pico};
+
+const LINUX: &str = "version = 1\nboard = \"linux\"\n";
+const PICO: &str = "version = 1\nboard = \"pico\"\n";
+
+/// A name, a policy body and what it must be rejected with.
+type Case<E> = (&'static str, Board, &'static str, fn(&E) -> bool);
+
+fn from_toml(board: Board, source: &str) -> Result<(), Error> {
+    match board {
+        Board::Linux => linux::Policy::from_toml(source).map(drop),
+        Board::Pico => pico::Policy::from_toml(source).map(drop),
+    }
+}
+
+fn problems(board: Board, body: &str) -> Vec<Problem> {
+    let header = match board {
+        Board::Linux => LINUX,
+        Board::Pico => PICO,
+    };
+    match from_toml(board, &format!("{}{}", header, body)) {
+        Err(Error::Invalid(problems)) => problems,
+        other => panic!("expected problems, got {:?}", other),
+    }
+}
+
+#[test]
+fn shipped_policies_are_accepted() {
+    pico::Policy::from_toml(include_str!("../../../host/policy.toml")).unwrap();
+    linux::Policy::from_toml(include_str!("../../../linux-host/policy.toml")).unwrap();
+}
+
+/// One policy per [`Problem`] variant, each inconsistent in exactly one way.
+#[test]
+fn each_problem_is_reported() {
+    #[rustfmt::skip]
+    let cases: [Case<Problem>; 19] = [
+        (
+            "pin used by an output and an input",
+            Board::Linux,
+            "[gpio.a]\npin = 5\ninitial = \"Low\"\n[gpio_input.b]\npin = 5\n",
+            |p| matches!(p, Problem::DuplicatePin { pin: 5, .. }),
+        ),
+        (
+            "two ports on one tty",
+            Board::Linux,
+            "[uart.a]\npath = \"/dev/ttyS0\"\nbaud_rate = 9600\n\
+             [uart.b]\npath = \"/dev/ttyS0\"\nbaud_rate = 9600\n",
+            |p| matches!(p, Problem::DuplicateDevice { device, .. } if device == "/dev/ttyS0"),
+        ),
+        (
+            "label granted by two sections",
+            Board::Linux,
+            "[spi.x]\npath = \"/dev/spidev0.0\"\nfrequency = 1000\nmode = 0\n\
+             [uart.x]\npath = \"/dev/ttyS0\"\nbaud_rate = 9600\n",
+            |p| matches!(p, Problem::LabelCollision { label, first: "spi", second: "uart" } if label == "x"),
+        ),
+        (
+            "device on a bus without a section",
+            Board::Pico,
+            "[spi.d]\nbus = \"SPI0\"\ncs = 5\nfrequency = 1000\nmode = 0\n",
+            |p| matches!(p, Problem::UnknownSpiBus { bus, .. } if bus == "SPI0"),
+        ),
+        (
+            "block the chip doesn't have",
+            Board::Pico,
+            "[uart.u]\nblock = \"UART2\"\ntx = 0\nrx = 1\nbaud_rate = 9600\n",
+            |p| matches!(p, Problem::UnknownBlock { block, .. } if block == "UART2"),
+        ),
+        (
+            "miso on a pin SPI0 can't reach",
+            Board::Pico,
+            "[spi_bus.SPI0]\nsck = 2\nmosi = 3\nmiso = 5\n",
+            |p| matches!(p, Problem::PinFunction { pin: 5, valid, .. } if *valid == [0, 4, 16, 20]),
+        ),
+        (
+            "sim level for an ungranted input",
+            Board::Linux,
+            "[sim.gpio_input.BUTTON]\nlevel = \"High\"\n",
+            |p| matches!(p, Problem::UngrantedLabel { key, section: "gpio_input" } if key == "sim.gpio_input.BUTTON"),
+        ),
+        (
+            "display dc line that isn't an output",
+            Board::Linux,
+            "[spi.oled]\npath = \"/dev/spidev0.0\"\nfrequency = 1000\nmode = 0\n\
+             [sim.spi.oled]\ndevice = \"ssd1306\"\ndc = \"DC\"\n",
+            |p| matches!(p, Problem::UnknownLabel { label, section: "gpio", .. } if label == "DC"),
+        ),
+        (
+            "fault with two triggers",
+            Board::Linux,
+            "[gpio.LED]\npin = 1\ninitial = \"Low\"\n\
+             [[faults.gpio.LED]]\nfault = \"stuck\"\nlevel = \"High\"\none_in = 2\nevery = 3\n",
+            |p| matches!(p, Problem::Conflict { .. }),
+        ),
+        (
+            "stuck fault without a level",
+            Board::Linux,
+            "[gpio.LED]\npin = 1\ninitial = \"Low\"\n\
+             [[faults.gpio.LED]]\nfault = \"stuck\"\ncalls = [1]\n",
+            |p| matches!(p, Problem::Requires { requires, .. } if requires.ends_with(".level")),
+        ),
+        (
+            "level on a latency fault",
+            Board::Linux,
+            "[gpio.LED]\npin = 1\ninitial = \"Low\"\n\
+             [[faults.gpio.LED]]\nfault = \"latency\"\nmicros = 10\nlevel = \"High\"\nevery = 2\n",
+            |p| matches!(p, Problem::Inapplicable { key, .. } if key.ends_with(".level")),
+        ),
+        (
+            "outputs without gpio in the interfaces list",
+            Board::Linux,
+            "interfaces = [\"delay\"]\n[gpio.LED]\npin = 1\ninitial = \"Low\"\n",
+            |p| matches!(p, Problem::UnlistedInterface { section: "gpio", .. }),
+        ),
+        (
+            "10-bit I2C address",
+            Board::Linux,
+            "[i2c.s]\npath = \"/dev/i2c-1\"\naddresses = [0x80]\n",
+            |p| matches!(p, Problem::OutOfRange { value: 0x80, max: 0x7f, .. }),
+        ),
+        (
+            "modes the device can't start in",
+            Board::Linux,
+            "[spi.s]\npath = \"/dev/spidev0.0\"\nfrequency = 1000\nmode = 0\nmodes = [3]\n",
+            |p| matches!(p, Problem::MissingInitial { key, .. } if key == "spi.s.modes"),
+        ),
+        (
+            "bit orders without the MSB-first the device starts in",
+            Board::Linux,
+            "[spi.s]\npath = \"/dev/spidev0.0\"\nfrequency = 1000\nmode = 0\nbit_orders = [\"lsb-first\"]\n",
+            |p| matches!(p, Problem::MissingInitial { key, .. } if key == "spi.s.bit_orders"),
+        ),
+        (
+            "no modes at all",
+            Board::Pico,
+            "[spi_bus.SPI0]\nsck = 2\nmosi = 3\nmiso = 4\n\
+             [spi.d]\nbus = \"SPI0\"\ncs = 5\nfrequency = 1000\nmode = 0\nmodes = []\n",
+            |p| matches!(p, Problem::Empty { key } if key == "spi.d.modes"),
+        ),
+        (
+            "debounce period that would overflow in microseconds",
+            Board::Pico,
+            "[gpio_input.BUTTON]\npin = 6\ndebounce_ms = 4_294_968\n",
+            |p| matches!(p, Problem::OutOfRange { key, max: 1000, .. } if key == "gpio_input.BUTTON.debounce_ms"),
+        ),
+        (
+            "PWM faster than a 1 ns period",
+            Board::Linux,
+            "[pwm.p]\nchip = 0\nchannel = 0\nfrequency = 2_000_000_000\n",
+            |p| matches!(p, Problem::OutOfRange { key, .. } if key == "pwm.p.frequency"),
+        ),
+        (
+            "PWM range reaching past 1 GHz",
+            Board::Pico,
+            "[pwm.p]\npin = 0\nfrequency = 1000\nmax_frequency = 4_000_000_000\n",
+            |p| matches!(p, Problem::OutOfRange { key, .. } if key == "pwm.p.max_frequency"),
+        ),
+    ];
+
+    for (name, board, body, expected) in cases {
+        let problems = problems(board, body);
+        assert!(
+            problems.len() == 1 && expected(&problems[0]),
+            "{}: got {:?}",
+            name,
+            problems
+        );
+    }
+}
+
+#[test]
+fn header_and_schema_errors() {
+    #[rustfmt::skip]
+    let cases: [Case<Error>; 5] = [
+        (
+            "unknown key",
+            Board::Linux,
+            "version = 1\nboard = \"linux\"\n[gpio.LED]\npin = 1\ninitial = \"Low\"\ncolour = \"red\"\n",
+            |e| matches!(e, Error::Parse(_)),
+        ),
+        (
+            "no version",
+            Board::Linux,
+            "board = \"linux\"\n",
+            |e| matches!(e, Error::MissingVersion),
+        ),
+        (
+            "future version",
+            Board::Pico,
+            "version = 2\nboard = \"pico\"\n",
+            |e| matches!(e, Error::UnsupportedVersion(2)),
+        ),
+        (
+            "no board",
+            Board::Pico,
+            "version = 1\n",
+            |e| matches!(e, Error::MissingBoard(Board::Pico)),
+        ),
+        (
+            "policy for the Pico",
+            Board::Linux,
+            "version = 1\nboard = \"pico\"\n",
+            |e| matches!(e, Error::WrongBoard { expected: Board::Linux, found: Board::Pico }),
+        ),
+    ];
+
+    for (name, board, source, expected) in cases {
+        match from_toml(board, source) {
+            Err(err) if expected(&err) => {}
+            other => panic!("{}: got {:?}", name, other),
+        }
+    }
+}
+
+/// A core module holding `data` as its only, manifest section.
+#[cfg(feature = "component")]
+fn module_with_manifest(data: &str) -> Vec<u8> {
+    let name = crate::manifest::SECTION;
+    let payload = [&[name.len() as u8], name.as_bytes(), data.as_bytes()].concat();
+    assert!(
+        payload.len() < 0x80,
+        "section size must fit one LEB128 byte"
+    );
+    [
+        b"\0asm\x01\0\0\0".as_slice(),
+        &[0, payload.len() as u8],
+        &payload,
+    ]
+    .concat()
+}
+
+#[cfg(feature = "component")]
+#[test]
+fn manifests_from_several_invocations_are_merged() {
+    use crate::manifest::Manifest;
+
+    // What the linker leaves of a helper crate's `manifest!` and the guest's own
+    let wasm =
+        module_with_manifest("gpio = [\"DC\", ]\n\0spi = [\"screen\", ]\ngpio = [\"RES\", ]\n\0");
+    let manifest = Manifest::from_component(&wasm).unwrap().unwrap();
+    assert_eq!(manifest.spi, ["screen".to_string()].into());
+    assert_eq!(manifest.gpio, ["DC".to_string(), "RES".to_string()].into());
+}
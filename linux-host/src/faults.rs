@@ -0,0 +1,119 @@
+//! Wraps opened devices in the fault injectors the policy's `[faults]` table
+//! asks for, on either backend.
+
+use std::time::Duration;
+
+use ::sim::{VirtualClock, VirtualDelay};
+use embedded_hal::delay::DelayNs;
+use fault::{FaultyOutputPin, FaultySpi, PinFault, SpiFault, Trigger};
+use policy::Level;
+use policy::linux::{self, FaultRule, FaultTrigger};
+
+use crate::Devices;
+
+/// Injected latency passes on the clock the guest runs on.
+struct FaultDelay(Option<VirtualDelay>);
+
+impl DelayNs for FaultDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        match &mut self.0 {
+            Some(delay) => delay.delay_ns(ns),
+            None => std::thread::sleep(Duration::from_nanos(ns.into())),
+        }
+    }
+}
+
+fn trigger<F>(rule: &FaultRule<F>) -> Trigger {
+    match rule.trigger() {
+        FaultTrigger::OneIn(n) => Trigger::OneIn(n),
+        FaultTrigger::Every(n) => Trigger::Every(n),
+        FaultTrigger::Calls(calls) => Trigger::Calls(calls),
+    }
+}
+
+fn spi_fault(rule: &FaultRule<linux::SpiFault>) -> SpiFault {
+    match rule.fault {
+        linux::SpiFault::Overrun => SpiFault::Overrun,
+        linux::SpiFault::ModeFault => SpiFault::ModeFault,
+        linux::SpiFault::ChipSelectFault => SpiFault::ChipSelectFault,
+        linux::SpiFault::BitFlip => SpiFault::BitFlip {
+            bits: rule.bits.unwrap_or(1),
+        },
+        linux::SpiFault::ShortRead => SpiFault::ShortRead,
+        linux::SpiFault::Latency => SpiFault::Latency {
+            micros: rule.micros.unwrap_or(0),
+        },
+    }
+}
+
+fn pin_fault(rule: &FaultRule<linux::GpioFault>) -> PinFault {
+    match rule.fault {
+        linux::GpioFault::Stuck => PinFault::Stuck {
+            high: rule.level == Some(Level::High),
+        },
+        linux::GpioFault::Latency => PinFault::Latency {
+            micros: rule.micros.unwrap_or(0),
+        },
+    }
+}
+
+/// Wraps every device with fault rules, logging each fault as it fires.
+pub fn inject(
+    mut devices: Devices,
+    faults: linux::Faults,
+    virtual_clock: Option<&VirtualClock>,
+) -> Devices {
+    let seed = faults.seed.unwrap_or(0);
+    let delay = || FaultDelay(virtual_clock.map(|clock| VirtualDelay::new(clock.clone())));
+
+    devices.spi = devices
+        .spi
+        .into_iter()
+        .map(|hardware| {
+            let Some(rules) = faults.spi.get(&hardware.name) else {
+                return hardware;
+            };
+            let label = hardware.name.clone();
+            let mut device =
+                FaultySpi::new(hardware.device, delay(), fault::label_seed(seed, &label))
+                    .on_inject(move |injection| {
+                        println!(
+                            "[Fault] spi.{} call {}: {:?}",
+                            label, injection.call, injection.fault
+                        )
+                    });
+            for rule in rules {
+                device = device.inject(spi_fault(rule), trigger(rule));
+            }
+            spi::SpiHardware {
+                device: Box::new(device),
+                ..hardware
+            }
+        })
+        .collect();
+
+    devices.gpio_pins = devices
+        .gpio_pins
+        .into_iter()
+        .map(|(name, pin)| {
+            let Some(rules) = faults.gpio.get(&name) else {
+                return (name, pin);
+            };
+            let label = name.clone();
+            let mut pin = FaultyOutputPin::new(pin, delay(), fault::label_seed(seed, &label))
+                .on_inject(move |injection| {
+                    println!(
+                        "[Fault] gpio.{} call {}: {:?}",
+                        label, injection.call, injection.fault
+                    )
+                });
+            for rule in rules {
+                pin = pin.inject(pin_fault(rule), trigger(rule));
+            }
+            let pin: Box<dyn gpio::ErasedOutputPin + Send + 'static> = Box::new(pin);
+            (name, pin)
+        })
+        .collect();
+
+    devices
+}
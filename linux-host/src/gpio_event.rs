@@ -0,0 +1,72 @@
+//! Input pins requested through gpio-cdev line events, so the guest can block
+//! on an edge in the kernel instead of polling across the Wasm boundary.
+
+use std::os::fd::AsRawFd;
+use std::time::{Duration, Instant};
+
+use gpio::{Edge, Error};
+use linux_embedded_hal::gpio_cdev::{EventType, LineEventHandle};
+
+pub struct EventPin {
+    handle: LineEventHandle,
+}
+
+impl EventPin {
+    pub fn new(handle: LineEventHandle) -> Self {
+        Self { handle }
+    }
+
+    /// Waits up to `timeout` for the kernel to queue an event on the line.
+    fn poll(&self, timeout: Duration) -> bool {
+        let mut fd = libc::pollfd {
+            fd: self.handle.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        // SAFETY: `fd` points to a single valid pollfd for the duration of the call
+        let ready = unsafe { libc::poll(&mut fd, 1, timeout_ms) };
+        ready > 0 && fd.revents & libc::POLLIN != 0
+    }
+}
+
+impl gpio::ErasedInputPin for EventPin {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        self.handle
+            .get_value()
+            .map(|v| v == 1)
+            .map_err(|e| Error::Hardware(e.to_string()))
+    }
+}
+
+impl gpio::ErasedEdgePin for EventPin {
+    fn wait_for_edge(&mut self, edge: Edge, timeout_ms: u32) -> Result<bool, Error> {
+        // Only edges from now on count, not ones queued while the guest was busy
+        while self.poll(Duration::ZERO) {
+            if self.handle.get_event().is_err() {
+                break;
+            }
+        }
+
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.into());
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !self.poll(remaining) {
+                return Ok(false);
+            }
+
+            let event = self
+                .handle
+                .get_event()
+                .map_err(|e| Error::Hardware(e.to_string()))?;
+            if matches!(
+                (edge, event.event_type()),
+                (Edge::Any, _)
+                    | (Edge::Rising, EventType::RisingEdge)
+                    | (Edge::Falling, EventType::FallingEdge)
+            ) {
+                return Ok(true);
+            }
+        }
+    }
+}
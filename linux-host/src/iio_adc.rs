@@ -0,0 +1,98 @@
+//! Analog inputs read through the kernel's IIO sysfs files
+//! (`<device>/in_voltageN_raw`). Any directory with the same layout works,
+//! so a fake tree can stand in for real hardware.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use adc::Error;
+
+pub struct IioChannel {
+    raw: PathBuf,
+}
+
+impl IioChannel {
+    /// Checks that the channel can be read before handing it to the guest.
+    pub fn open(device: &Path, channel: u32) -> io::Result<Self> {
+        let iio = Self {
+            raw: device.join(format!("in_voltage{}_raw", channel)),
+        };
+        iio.read_raw()?;
+        Ok(iio)
+    }
+
+    fn read_raw(&self) -> io::Result<u16> {
+        let text = fs::read_to_string(&self.raw)?;
+        let value: i64 = text
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(value.clamp(0, u16::MAX.into()) as u16)
+    }
+}
+
+/// The full-scale voltage from the channel's scale (millivolts per LSB), trying the
+/// per-channel file before the one shared by all channels of the device.
+pub fn reference_mv(device: &Path, channel: u32, resolution_bits: u8) -> io::Result<u32> {
+    let scale = fs::read_to_string(device.join(format!("in_voltage{}_scale", channel)))
+        .or_else(|_| fs::read_to_string(device.join("in_voltage_scale")))?;
+    let mv_per_lsb: f64 = scale
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok((mv_per_lsb * f64::from(1u32 << resolution_bits)).round() as u32)
+}
+
+impl adc::ErasedAdc for IioChannel {
+    fn read(&mut self) -> Result<u16, Error> {
+        self.read_raw().map_err(|e| Error::Other(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adc::ErasedAdc;
+
+    fn fake_device(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (file, contents) in files {
+            fs::write(dir.path().join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn reads_raw_values_from_the_tree() {
+        let dev = fake_device(&[
+            ("in_voltage2_raw", "2048\n"),
+            ("in_voltage_scale", "0.805664062\n"),
+        ]);
+        let mut channel = IioChannel::open(dev.path(), 2).unwrap();
+        assert_eq!(channel.read().unwrap(), 2048);
+
+        fs::write(dev.path().join("in_voltage2_raw"), "-5\n").unwrap();
+        assert_eq!(channel.read().unwrap(), 0);
+        fs::write(dev.path().join("in_voltage2_raw"), "70000\n").unwrap();
+        assert_eq!(channel.read().unwrap(), u16::MAX);
+    }
+
+    #[test]
+    fn open_fails_without_the_channel_file() {
+        let dev = fake_device(&[("in_voltage0_raw", "1\n")]);
+        assert!(IioChannel::open(dev.path(), 1).is_err());
+    }
+
+    #[test]
+    fn converts_the_scale_to_a_full_scale_voltage() {
+        let dev = fake_device(&[
+            ("in_voltage0_raw", "0\n"),
+            ("in_voltage_scale", "0.805664062\n"),
+            ("in_voltage1_scale", "0.5\n"),
+        ]);
+        assert_eq!(reference_mv(dev.path(), 0, 12).unwrap(), 3300);
+        assert_eq!(reference_mv(dev.path(), 1, 12).unwrap(), 2048);
+        assert_eq!(reference_mv(dev.path(), 1, 10).unwrap(), 512);
+    }
+}
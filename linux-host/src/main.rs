@@ -1,132 +1,292 @@
 use std::collections::BTreeMap;
 use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use anyhow::Context;
-use clap::Parser;
-use serde::Deserialize;
-use wasmtime::{
-    Config, Engine, Store,
-    component::{Component, HasSelf, Linker, ResourceTable},
-};
+use anyhow::{Context, bail};
+use clap::{Parser, ValueEnum};
+use policy::{BitOrder, Level, Pull, SpiMode, linux, manifest};
+use wasmtime::{Config, Engine, component::Component};
 
-use delay::{DelayCtx, DelayView};
-use gpio::{GpioCtx, GpioView};
-use spi::{SpiCtx, SpiView};
+use ::sim::{VirtualClock, VirtualDelay};
+use gpio::GpioCtx;
+use runtime::{EmbeddedHost, Interface};
 
-use linux_embedded_hal::gpio_cdev::{Chip, LineRequestFlags};
-use linux_embedded_hal::{CdevPin, Delay, SpidevDevice};
+use linux_embedded_hal::gpio_cdev::{Chip, EventRequestFlags, LineRequestFlags};
+use linux_embedded_hal::{CdevPin, Delay, I2cdev, SpidevDevice};
 use spidev::{SpiModeFlags, Spidev, SpidevOptions};
 
+mod faults;
+mod gpio_event;
+mod iio_adc;
+mod serial;
+mod sim;
+mod sysfs_pwm;
+mod traces;
+
 wasmtime::component::bindgen!({
     path: "../guest-physics/wit",
     world: "app",
 });
 
-#[derive(Deserialize)]
-struct HostPolicy {
-    spi: BTreeMap<String, SpiPolicy>,
-    gpio: BTreeMap<String, GpioPolicy>,
+fn spi_mode(mode: SpiMode) -> spi::Mode {
+    match mode {
+        SpiMode::Mode0 => spi::Mode::Mode0,
+        SpiMode::Mode1 => spi::Mode::Mode1,
+        SpiMode::Mode2 => spi::Mode::Mode2,
+        SpiMode::Mode3 => spi::Mode::Mode3,
+    }
 }
 
-#[derive(Deserialize)]
-struct SpiPolicy {
-    path: String,
-    frequency: u32,
-    mode: u8,
+fn bit_order(order: BitOrder) -> spi::BitOrder {
+    match order {
+        BitOrder::MsbFirst => spi::BitOrder::MsbFirst,
+        BitOrder::LsbFirst => spi::BitOrder::LsbFirst,
+    }
 }
 
-#[derive(Deserialize)]
-struct GpioPolicy {
-    pin: u32,
-    initial: String,
+fn spi_bus_config(config: &linux::SpiDevice) -> spi::BusConfig {
+    spi::BusConfig {
+        frequency: config.frequency,
+        mode: spi_mode(config.mode),
+        bit_order: spi::BitOrder::MsbFirst,
+    }
 }
 
-struct HostState {
-    spi_ctx: SpiCtx,
-    gpio_ctx: GpioCtx,
-    delay_ctx: DelayCtx,
+fn spi_bounds(config: &linux::SpiDevice) -> spi::SpiConfigBounds {
+    let (min_frequency, max_frequency) = config.frequency_range();
+    spi::SpiConfigBounds {
+        min_frequency,
+        max_frequency,
+        modes: config.allowed_modes().into_iter().map(spi_mode).collect(),
+        bit_orders: config
+            .allowed_bit_orders()
+            .into_iter()
+            .map(bit_order)
+            .collect(),
+    }
 }
 
-impl my::debug::logging::Host for HostState {
-    fn log(&mut self, msg: String) {
-        println!("[Guest Log] {}", msg);
+fn spi_limits(config: &linux::SpiDevice) -> spi::SpiLimits {
+    let defaults = spi::SpiLimits::default();
+    spi::SpiLimits {
+        max_bytes_per_op: config.max_bytes_per_op.unwrap_or(defaults.max_bytes_per_op),
+        max_ops_per_transaction: config
+            .max_ops_per_transaction
+            .unwrap_or(defaults.max_ops_per_transaction),
+        max_bytes_per_transaction: config
+            .max_bytes_per_transaction
+            .unwrap_or(defaults.max_bytes_per_transaction),
     }
 }
 
-// Add this implementation block for the Linux host
-impl my::clock::time::Host for HostState {
-    fn now_ms(&mut self) -> u64 {
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64
+fn spidev_options(config: &spi::BusConfig) -> SpidevOptions {
+    let mut mode = match config.mode {
+        spi::Mode::Mode0 => SpiModeFlags::SPI_MODE_0,
+        spi::Mode::Mode1 => SpiModeFlags::SPI_MODE_1,
+        spi::Mode::Mode2 => SpiModeFlags::SPI_MODE_2,
+        spi::Mode::Mode3 => SpiModeFlags::SPI_MODE_3,
+    };
+    if config.bit_order == spi::BitOrder::LsbFirst {
+        mode |= SpiModeFlags::SPI_LSB_FIRST;
     }
+
+    SpidevOptions::new()
+        .bits_per_word(8)
+        .max_speed_hz(config.frequency)
+        .mode(mode)
+        .build()
 }
 
-impl SpiView for HostState {
-    fn spi_ctx(&mut self) -> &mut SpiCtx {
-        &mut self.spi_ctx
+fn i2c_limits(config: &linux::I2cBus) -> i2c::I2cLimits {
+    let defaults = i2c::I2cLimits::default();
+    i2c::I2cLimits {
+        max_bytes_per_op: config.max_bytes_per_op.unwrap_or(defaults.max_bytes_per_op),
+        max_ops_per_transaction: config
+            .max_ops_per_transaction
+            .unwrap_or(defaults.max_ops_per_transaction),
+        max_bytes_per_transaction: config
+            .max_bytes_per_transaction
+            .unwrap_or(defaults.max_bytes_per_transaction),
     }
 }
-impl GpioView for HostState {
-    fn gpio_ctx(&mut self) -> &mut GpioCtx {
-        &mut self.gpio_ctx
+
+fn uart_limits(config: &linux::Uart) -> uart::UartLimits {
+    let defaults = uart::UartLimits::default();
+    uart::UartLimits {
+        max_bytes_per_op: config.max_bytes_per_op.unwrap_or(defaults.max_bytes_per_op),
     }
 }
-impl DelayView for HostState {
-    fn delay_ctx(&mut self) -> &mut DelayCtx {
-        &mut self.delay_ctx
+
+fn pwm_bounds(config: &linux::Pwm) -> pwm::PwmBounds {
+    let (min_frequency, max_frequency) = config.frequency_range();
+    let (min_duty_percent, max_duty_percent) = config.duty_range();
+    pwm::PwmBounds {
+        min_frequency,
+        max_frequency,
+        min_duty_percent,
+        max_duty_percent,
     }
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about)]
-pub struct HostArguments {
-    #[arg(index = 1)]
-    pub component_path: String,
+fn adc_limits(config: &linux::Adc) -> adc::AdcLimits {
+    let defaults = adc::AdcLimits::default();
+    adc::AdcLimits {
+        max_samples_per_burst: config
+            .max_samples_per_burst
+            .unwrap_or(defaults.max_samples_per_burst),
+    }
+}
 
-    #[arg(long = "policy-file")]
-    pub policy_file: String,
+/// gpio-cdev has no names for the kernel's GPIOHANDLE_REQUEST_BIAS_* flags, so spell them out.
+fn input_request_flags(pull: Pull) -> LineRequestFlags {
+    let bias = match pull {
+        Pull::Up => 1 << 5,
+        Pull::Down => 1 << 6,
+        Pull::None => 0,
+    };
+    LineRequestFlags::INPUT | LineRequestFlags::from_bits_retain(bias)
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = HostArguments::parse();
+fn runtime_interface(interface: policy::Interface) -> Interface {
+    match interface {
+        policy::Interface::Spi => Interface::Spi,
+        policy::Interface::I2c => Interface::I2c,
+        policy::Interface::Uart => Interface::Uart,
+        policy::Interface::Pwm => Interface::Pwm,
+        policy::Interface::Adc => Interface::Adc,
+        policy::Interface::Gpio => Interface::Gpio,
+        policy::Interface::Delay => Interface::Delay,
+        policy::Interface::Logging => Interface::Logging,
+        policy::Interface::Clock => Interface::Clock,
+        policy::Interface::Timer => Interface::Timer,
+    }
+}
 
-    let policy_content = fs::read_to_string(&args.policy_file).with_context(|| {
-        format!(
-            "Failed to find or read policy file at '{}'",
-            args.policy_file
-        )
-    })?;
-    let policy: HostPolicy = toml::from_str(&policy_content)
-        .with_context(|| format!("Failed to parse TOML in policy file '{}'", args.policy_file))?;
+/// Everything a backend opened for the labels in the policy.
+pub struct Devices {
+    pub spi: Vec<spi::SpiHardware>,
+    pub i2c: Vec<i2c::I2cHardware>,
+    pub uart: Vec<uart::UartHardware>,
+    pub pwm: Vec<pwm::PwmHardware>,
+    pub adc: Vec<adc::AdcHardware>,
+    pub gpio_pins: BTreeMap<String, Box<dyn gpio::ErasedOutputPin + Send + 'static>>,
+    pub gpio_inputs: BTreeMap<String, Box<dyn gpio::ErasedEdgePin + Send + 'static>>,
+}
 
-    let mut spi_hardware: Vec<(String, Box<dyn spi::ErasedSpiDevice + Send + 'static>)> =
-        Vec::new();
+fn open_hardware(policy: linux::Policy) -> anyhow::Result<Devices> {
+    let mut spi_hardware: Vec<spi::SpiHardware> = Vec::new();
 
     for (name, config) in policy.spi {
         let mut dev = Spidev::open(&config.path)
             .with_context(|| format!("Failed to open SPI device '{}' at path '{}'. Does this device exist on your machine?", name, config.path))?;
 
-        let mode = match config.mode {
-            0 => SpiModeFlags::SPI_MODE_0,
-            1 => SpiModeFlags::SPI_MODE_1,
-            2 => SpiModeFlags::SPI_MODE_2,
-            3 => SpiModeFlags::SPI_MODE_3,
-            _ => SpiModeFlags::SPI_MODE_0,
-        };
+        dev.configure(&spidev_options(&spi_bus_config(&config)))
+            .with_context(|| format!("Failed to configure SPI device '{}'", name))?;
 
-        let options = SpidevOptions::new()
-            .bits_per_word(8)
-            .max_speed_hz(config.frequency)
-            .mode(mode)
-            .build();
+        let spi_device = spi::Reconfigurable::new(
+            SpidevDevice(dev),
+            |dev: &mut SpidevDevice, bus: &spi::BusConfig| {
+                dev.configure(&spidev_options(bus))
+                    .map_err(|e| spi::Error::Other(e.to_string()))
+            },
+        );
+        spi_hardware.push(spi::SpiHardware {
+            name,
+            limits: spi_limits(&config),
+            bounds: spi_bounds(&config),
+            device: Box::new(spi_device),
+        });
+    }
 
-        dev.configure(&options)
-            .with_context(|| format!("Failed to configure SPI device '{}'", name))?;
+    let mut i2c_hardware: Vec<i2c::I2cHardware> = Vec::new();
+
+    for (name, config) in policy.i2c {
+        let bus = I2cdev::new(&config.path).with_context(|| {
+            format!(
+                "Failed to open I2C bus '{}' at path '{}'. Is the i2c-dev module loaded?",
+                name, config.path
+            )
+        })?;
+
+        i2c_hardware.push(i2c::I2cHardware {
+            name,
+            limits: i2c_limits(&config),
+            addresses: config.addresses,
+            bus: Box::new(bus),
+        });
+    }
 
-        let spi_device = SpidevDevice(dev);
-        spi_hardware.push((name, Box::new(spi_device)));
+    let mut uart_hardware: Vec<uart::UartHardware> = Vec::new();
+
+    for (name, config) in policy.uart {
+        let port = serial::SerialTty::open(&config.path, config.baud_rate).with_context(|| {
+            format!(
+                "Failed to open serial port '{}' at path '{}'",
+                name, config.path
+            )
+        })?;
+
+        uart_hardware.push(uart::UartHardware {
+            name,
+            limits: uart_limits(&config),
+            port: Box::new(port),
+        });
+    }
+
+    let mut pwm_hardware: Vec<pwm::PwmHardware> = Vec::new();
+
+    for (name, config) in policy.pwm {
+        let channel = sysfs_pwm::SysfsPwm::open(config.chip, config.channel, config.frequency)
+            .with_context(|| {
+                format!(
+                    "Failed to set up PWM channel '{}' (pwmchip{}/pwm{}). Is the PWM overlay enabled?",
+                    name, config.chip, config.channel
+                )
+            })?;
+
+        pwm_hardware.push(pwm::PwmHardware {
+            name,
+            bounds: pwm_bounds(&config),
+            channel: Box::new(channel),
+        });
+    }
+
+    let mut adc_hardware: Vec<adc::AdcHardware> = Vec::new();
+
+    for (name, config) in policy.adc {
+        let channel =
+            iio_adc::IioChannel::open(&config.device, config.channel).with_context(|| {
+                format!(
+                    "Failed to read ADC channel '{}' (in_voltage{}_raw in '{}')",
+                    name,
+                    config.channel,
+                    config.device.display()
+                )
+            })?;
+
+        // Without an explicit reference, derive it from the driver's scale
+        let reference_mv = match config.reference_mv {
+            Some(mv) => mv,
+            None => {
+                let scale =
+                    iio_adc::reference_mv(&config.device, config.channel, config.resolution_bits);
+                scale.with_context(|| {
+                    format!(
+                        "ADC channel '{}' has no reference_mv and no readable IIO scale",
+                        name
+                    )
+                })?
+            }
+        };
+
+        adc_hardware.push(adc::AdcHardware {
+            name,
+            reference_mv,
+            resolution_bits: config.resolution_bits,
+            limits: adc_limits(&config),
+            channel: Box::new(channel),
+        });
     }
 
     // Setup Linux GPIO Devices based on policy
@@ -144,7 +304,10 @@ fn main() -> anyhow::Result<()> {
             .get_line(config.pin)
             .with_context(|| format!("Failed to find GPIO pin {}", config.pin))?;
 
-        let default_val = if config.initial == "High" { 1 } else { 0 };
+        let default_val = match config.initial {
+            Level::High => 1,
+            Level::Low => 0,
+        };
 
         // Request exclusive access to the pin as an output
         let handle = line
@@ -163,40 +326,225 @@ fn main() -> anyhow::Result<()> {
         gpio_pins.insert(name, Box::new(pin));
     }
 
-    // Initialize Wasmtime HostState using your custom Contexts
-    let state = HostState {
-        spi_ctx: SpiCtx {
-            table: ResourceTable::new(),
-            hardware: spi_hardware,
-        },
-        gpio_ctx: GpioCtx { pins: gpio_pins },
-        delay_ctx: DelayCtx {
-            delay: Box::new(Delay),
-        },
-    };
+    let mut gpio_inputs: BTreeMap<String, Box<dyn gpio::ErasedEdgePin + Send + 'static>> =
+        BTreeMap::new();
 
-    // Wasmtime Setup
-    let engine = Engine::new(&Config::new())?;
-    let mut linker = Linker::new(&engine);
+    for (name, config) in policy.gpio_input {
+        let line = chip
+            .get_line(config.pin)
+            .with_context(|| format!("Failed to find GPIO pin {}", config.pin))?;
+
+        // Request line events rather than a plain handle so the guest can wait for edges
+        let handle = line
+            .events(
+                input_request_flags(config.pull),
+                EventRequestFlags::BOTH_EDGES,
+                "wasm-host",
+            )
+            .with_context(|| {
+                format!(
+                    "Failed to request GPIO pin {} as input. Is it already in use?",
+                    config.pin
+                )
+            })?;
 
-    // Bind custom libraries to the linker
-    spi::add_to_linker(&mut linker)?;
-    gpio::add_to_linker(&mut linker)?;
-    delay::add_to_linker(&mut linker)?;
-    my::debug::logging::add_to_linker::<HostState, HasSelf<HostState>>(&mut linker, |state| state)?;
-    my::clock::time::add_to_linker::<HostState, HasSelf<HostState>>(&mut linker, |state| state)?;
+        let pin = gpio_event::EventPin::new(handle);
 
-    let mut store = Store::new(&engine, state);
+        match config.debounce_ms {
+            Some(ms) => {
+                let period_us = ms
+                    .checked_mul(1000)
+                    .with_context(|| format!("Debounce period of {} ms is too long", ms))?;
+                gpio_inputs.insert(name, Box::new(gpio::Debounced::new(pin, Delay, period_us)))
+            }
+            None => gpio_inputs.insert(name, Box::new(pin)),
+        };
+    }
+
+    Ok(Devices {
+        spi: spi_hardware,
+        i2c: i2c_hardware,
+        uart: uart_hardware,
+        pwm: pwm_hardware,
+        adc: adc_hardware,
+        gpio_pins,
+        gpio_inputs,
+    })
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+pub struct HostArguments {
+    #[arg(index = 1)]
+    pub component_path: String,
+
+    #[arg(long = "policy-file")]
+    pub policy_file: String,
+
+    /// Where the policy's labels are bound: real devices, or the `[sim]` table
+    #[arg(long = "backend", value_enum, default_value_t = Backend::Hardware)]
+    pub backend: Backend,
+
+    /// Link imports the policy doesn't grant as stubs that trap when called
+    #[arg(long = "allow-missing")]
+    pub allow_missing: bool,
+
+    /// Real time, or simulated time that delays advance instantly (not with `--backend hardware`)
+    #[arg(long = "clock", value_enum, default_value_t = ClockMode::Real)]
+    pub clock: ClockMode,
+
+    /// Write every SPI call and pin change the guest makes to this trace file
+    #[arg(long = "record")]
+    pub record: Option<String>,
+
+    /// The trace file `--backend replay` plays back
+    #[arg(long = "trace")]
+    pub trace: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Backend {
+    Hardware,
+    Sim,
+    /// Answer the guest from a `--record`ed trace, stopping it where it diverges
+    Replay,
+}
 
-    // Load component (with context)
-    let component = Component::from_file(&engine, &args.component_path)
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockMode {
+    Real,
+    Virtual,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = HostArguments::parse();
+    if args.clock == ClockMode::Virtual && matches!(args.backend, Backend::Hardware) {
+        bail!("--clock virtual needs --backend sim or replay; real devices run in real time");
+    }
+    if matches!(args.backend, Backend::Replay) != args.trace.is_some() {
+        bail!("--backend replay and --trace <file> go together");
+    }
+
+    let policy_content = fs::read_to_string(&args.policy_file).with_context(|| {
+        format!(
+            "Failed to find or read policy file at '{}'",
+            args.policy_file
+        )
+    })?;
+    let mut policy = linux::Policy::from_toml(&policy_content)
+        .with_context(|| format!("Invalid policy file '{}'", args.policy_file))?;
+
+    // Refuse a guest that needs hardware the policy doesn't grant before touching any of it
+    let component_bytes = fs::read(&args.component_path)
         .with_context(|| format!("Failed to find Wasm component at '{}'", args.component_path))?;
+    match manifest::check_component(&component_bytes, &policy.granted())
+        .with_context(|| format!("Guest '{}' cannot run", args.component_path))?
+    {
+        Some(diff) if !diff.unused.is_empty() => {
+            println!(
+                "Note: the guest doesn't use everything the policy grants:\n{}",
+                diff
+            )
+        }
+        Some(_) => {}
+        None => println!("Note: the guest has no manifest; its labels are checked when opened"),
+    }
+
+    // Wasmtime Setup
+    let replaying = matches!(args.backend, Backend::Replay);
+    let mut config = Config::new();
+    // A diverging replay stops the guest by interrupting it at its next call or loop
+    config.epoch_interruption(replaying);
+    let engine = Engine::new(&config)?;
+    let component = Component::new(&engine, &component_bytes)
+        .with_context(|| format!("Invalid Wasm component '{}'", args.component_path))?;
+
+    // Link only the interfaces the policy grants
+    let interfaces: Vec<Interface> = policy
+        .interfaces()
+        .into_iter()
+        .map(runtime_interface)
+        .collect();
+    let mut linker = EmbeddedHost::gated_linker(&engine, &interfaces)?;
+    if let Err(denied) = EmbeddedHost::check_imports(&engine, &component, &interfaces) {
+        if !args.allow_missing {
+            return Err(anyhow::Error::new(denied)).with_context(|| {
+                format!(
+                    "Guest '{}' cannot run; add the interfaces to `interfaces` in '{}' or pass --allow-missing",
+                    args.component_path, args.policy_file
+                )
+            });
+        }
+        println!("Note: {}; calls to them trap (--allow-missing)", denied);
+        EmbeddedHost::stub_imports(&mut linker, &component, &denied)?;
+    }
+
+    let faults = std::mem::take(&mut policy.faults);
+    let virtual_clock = (args.clock == ClockMode::Virtual).then(VirtualClock::new);
+    let divergences = Arc::new(AtomicUsize::new(0));
+    let devices = match (args.backend, &args.trace) {
+        (Backend::Hardware, _) => open_hardware(policy)?,
+        (Backend::Sim, _) => sim::open(policy, virtual_clock.as_ref())?,
+        (Backend::Replay, Some(path)) => {
+            let engine = engine.clone();
+            traces::open(policy, path, divergences.clone(), move || {
+                engine.increment_epoch()
+            })?
+        }
+        (Backend::Replay, None) => unreachable!("checked above"),
+    };
+    let devices = faults::inject(devices, faults, virtual_clock.as_ref());
+    let devices = match &args.record {
+        Some(path) => traces::record(devices, path, virtual_clock.as_ref())?,
+        None => devices,
+    };
+
+    let builder = EmbeddedHost::builder()
+        .spi(devices.spi)
+        .i2c(devices.i2c)
+        .uart(devices.uart)
+        .pwm(devices.pwm)
+        .adc(devices.adc)
+        .gpio(
+            GpioCtx::new(devices.gpio_pins, devices.gpio_inputs).on_first_denial(|label, err| {
+                println!("[Host] GPIO request for '{}' denied: {:?}", label, err)
+            }),
+        )
+        .logger(|msg| println!("[Guest Log] {}", msg));
+    let host = match virtual_clock {
+        Some(clock) => builder
+            .delay(VirtualDelay::new(clock.clone()))
+            .clock(move || clock.now_micros()),
+        None => builder.delay(Delay).clock(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64
+        }),
+    }
+    .build();
 
     println!("Instantiating component...");
-    let app = App::instantiate(&mut store, &component, &linker)?;
+    let (mut store, app) = host.instantiate(&linker, &component, |store, component, linker| {
+        // Runs until the first epoch increment, i.e. the first divergence
+        store.set_epoch_deadline(1);
+        App::instantiate(store, component, linker)
+    })?;
 
     println!("Calling guest run()...");
-    app.call_run(&mut store)?;
+    let run = app.call_run(&mut store);
+
+    // Replay devices report the calls the guest never made when dropped
+    drop(store);
+    let diverged = divergences.load(Ordering::Relaxed);
+    if diverged > 0 {
+        bail!(
+            "the guest diverged from trace '{}' {} time(s) and was stopped",
+            args.trace.unwrap_or_default(),
+            diverged
+        );
+    }
+    run?;
     println!("Guest finished.");
 
     Ok(())
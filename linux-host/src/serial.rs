@@ -0,0 +1,86 @@
+//! Serial TTYs (including pseudo-terminals) granted to the guest as UART ports.
+
+use std::io::{ErrorKind, Read, Write};
+use std::time::Duration;
+
+use serialport::{SerialPort, TTYPort};
+use uart::Error;
+
+pub struct SerialTty {
+    port: TTYPort,
+}
+
+impl SerialTty {
+    pub fn open(path: &str, baud_rate: u32) -> serialport::Result<Self> {
+        let port = serialport::new(path, baud_rate).open_native()?;
+        Ok(Self { port })
+    }
+}
+
+fn map_io_error(err: std::io::Error) -> Error {
+    Error::Other(err.to_string())
+}
+
+impl uart::ErasedUart for SerialTty {
+    fn read(&mut self, buf: &mut [u8], timeout_ms: u32) -> Result<usize, Error> {
+        self.port
+            .set_timeout(Duration::from_millis(timeout_ms.into()))
+            .map_err(|e| Error::Other(e.to_string()))?;
+
+        match self.port.read(buf) {
+            Ok(read) => Ok(read),
+            Err(e) if e.kind() == ErrorKind::TimedOut => Ok(0),
+            Err(e) => Err(map_io_error(e)),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.port.write_all(data).map_err(map_io_error)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.port.flush().map_err(map_io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uart::ErasedUart;
+
+    fn pty_pair() -> (SerialTty, SerialTty) {
+        let (master, slave) = TTYPort::pair().expect("open pty pair");
+        (SerialTty { port: master }, SerialTty { port: slave })
+    }
+
+    fn read_exact(port: &mut SerialTty, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        let mut read = 0;
+        while read < len {
+            let n = port.read(&mut buf[read..], 1000).unwrap();
+            assert_ne!(n, 0, "timed out after {read} bytes");
+            read += n;
+        }
+        buf
+    }
+
+    #[test]
+    fn round_trips_through_a_pty_pair() {
+        let (mut master, mut slave) = pty_pair();
+
+        master.write(b"ping").unwrap();
+        master.flush().unwrap();
+        assert_eq!(read_exact(&mut slave, 4), b"ping");
+
+        slave.write(b"pong").unwrap();
+        slave.flush().unwrap();
+        assert_eq!(read_exact(&mut master, 4), b"pong");
+    }
+
+    #[test]
+    fn read_returns_nothing_when_the_timeout_elapses() {
+        let (_master, mut slave) = pty_pair();
+        let mut buf = [0u8; 8];
+        assert_eq!(slave.read(&mut buf, 20).unwrap(), 0);
+    }
+}
@@ -0,0 +1,267 @@
+//! `--backend sim`: binds the policy's SPI and GPIO labels to the simulated
+//! devices of its `[sim]` table instead of opening `/dev`, so guests run end to
+//! end on any machine. Limits and configuration bounds still come from the policy.
+//!
+//! With `--clock virtual` the devices read the same [`VirtualClock`] as the
+//! guest, so sensor curves and conversion times follow simulated time.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, bail};
+use policy::Level;
+use policy::linux::{self, FrameFormat, SimCurve, SimSpi};
+use policy::register_map::{self, ByteOrder, RegisterMap};
+use sim::bme280::{Bme280, Curve, Environment};
+use sim::registers::{Access, Framing, Increment, Register, RegisterSpi};
+use sim::ssd1306::{FrameSink, Ssd1306};
+use sim::{
+    Clock, Line, LoopbackSpi, NullSpi, SimInputPin, SimOutputPin, SimSpiDevice, VirtualClock,
+};
+
+use crate::{Devices, spi_bounds, spi_limits};
+
+/// Opens the simulated devices, on `virtual_clock` if given and otherwise on
+/// wall time since this call.
+pub fn open(
+    policy: linux::Policy,
+    virtual_clock: Option<&VirtualClock>,
+) -> anyhow::Result<Devices> {
+    let unsimulated = [
+        ("i2c", policy.i2c.is_empty()),
+        ("uart", policy.uart.is_empty()),
+        ("pwm", policy.pwm.is_empty()),
+        ("adc", policy.adc.is_empty()),
+    ];
+    for (section, empty) in unsimulated {
+        if !empty {
+            println!(
+                "Note: --backend sim has no [{}] devices; the guest cannot open them",
+                section
+            );
+        }
+    }
+
+    let mut bindings = policy.sim;
+    let start = Instant::now();
+    let clock = || -> Clock {
+        match virtual_clock {
+            Some(clock) => clock.clock(),
+            None => Box::new(move || start.elapsed().as_micros() as u64),
+        }
+    };
+
+    // Created first so simulated devices can watch the pins the guest drives
+    let lines: BTreeMap<String, Line> = policy
+        .gpio
+        .iter()
+        .map(|(name, config)| (name.clone(), Line::new(config.initial == Level::High)))
+        .collect();
+
+    let mut spi_hardware: Vec<spi::SpiHardware> = Vec::new();
+
+    for (name, config) in policy.spi {
+        let device = match bindings.spi.remove(&name).unwrap_or(SimSpi::Null) {
+            SimSpi::Null => configurable(NullSpi),
+            SimSpi::Loopback => configurable(LoopbackSpi),
+            SimSpi::Registers { registers } => configurable(RegisterSpi::new(
+                registers.iter().map(|r| (r.address, r.value)),
+            )),
+            SimSpi::RegisterMap { map } => configurable(
+                register_spi(&map)
+                    .with_context(|| format!("Invalid register map {}", map.display()))?,
+            ),
+            SimSpi::Ssd1306 {
+                dc,
+                width,
+                height,
+                frames,
+                format,
+            } => {
+                let format = format.unwrap_or(if frames.is_some() {
+                    FrameFormat::Png
+                } else {
+                    FrameFormat::Unicode
+                });
+                configurable(Ssd1306::new(
+                    lines[&dc].clone(),
+                    usize::from(width.unwrap_or(128)),
+                    usize::from(height.unwrap_or(32)),
+                    frame_sink(&name, frames, format)?,
+                ))
+            }
+            SimSpi::Bme280 {
+                temperature,
+                pressure,
+                humidity,
+            } => {
+                let environment = Environment {
+                    temperature: curve(temperature, 20.0)?,
+                    pressure: curve(pressure, 1013.25)?,
+                    humidity: curve(humidity, 50.0)?,
+                };
+                configurable(Bme280::new(environment, clock()))
+            }
+        };
+        spi_hardware.push(spi::SpiHardware {
+            limits: spi_limits(&config),
+            bounds: spi_bounds(&config),
+            name,
+            device,
+        });
+    }
+
+    let mut gpio_pins: BTreeMap<String, Box<dyn gpio::ErasedOutputPin + Send + 'static>> =
+        BTreeMap::new();
+
+    for (name, line) in lines {
+        gpio_pins.insert(name, Box::new(SimOutputPin::new(line)));
+    }
+
+    let mut gpio_inputs: BTreeMap<String, Box<dyn gpio::ErasedEdgePin + Send + 'static>> =
+        BTreeMap::new();
+
+    for name in policy.gpio_input.into_keys() {
+        let level = bindings.gpio_input.remove(&name).map(|input| input.level);
+        let line = Line::new(level == Some(Level::High));
+        let mut pin = SimInputPin::new(line);
+        if let Some(clock) = virtual_clock {
+            pin = pin.on_clock(clock.clone());
+        }
+        gpio_inputs.insert(name, Box::new(pin));
+    }
+
+    Ok(Devices {
+        spi: spi_hardware,
+        i2c: Vec::new(),
+        uart: Vec::new(),
+        pwm: Vec::new(),
+        adc: Vec::new(),
+        gpio_pins,
+        gpio_inputs,
+    })
+}
+
+/// Simulated buses take any configuration the policy's bounds let through.
+fn configurable<D: sim::SimSpi + Send + 'static>(
+    device: D,
+) -> Box<dyn spi::ConfigurableSpiDevice + Send + 'static> {
+    Box::new(spi::Reconfigurable::new(
+        SimSpiDevice::new(device),
+        |_: &mut SimSpiDevice<D>, _: &spi::BusConfig| Ok(()),
+    ))
+}
+
+fn register_spi(path: &Path) -> anyhow::Result<RegisterSpi> {
+    let map = RegisterMap::from_toml(&fs::read_to_string(path)?)?;
+    let (direction_bit, read_when_set) = map.direction_bit();
+    let framing = Framing {
+        address_bits: map.address_bits(),
+        direction_bit,
+        read_when_set,
+        increment: match (map.increment_bit, map.auto_increment) {
+            (Some(bit), _) => Increment::WithBit(bit),
+            (None, Some(false)) => Increment::Never,
+            (None, _) => Increment::Always,
+        },
+        bytes: map.bytes(),
+        big_endian: map.byte_order == ByteOrder::Big,
+    };
+    let registers = map.registers.iter().map(|register| Register {
+        address: register.address,
+        reset: register.reset,
+        access: match register.access {
+            register_map::Access::ReadWrite => Access::ReadWrite,
+            register_map::Access::ReadOnly => Access::ReadOnly,
+            register_map::Access::WriteOnly => Access::WriteOnly,
+        },
+        clear_on_read: register.clear_on_read,
+    });
+    Ok(RegisterSpi::with_map(framing, registers))
+}
+
+fn curve(config: Option<SimCurve>, default: f32) -> anyhow::Result<Curve> {
+    Ok(match config {
+        None => Curve::Constant(default),
+        Some(SimCurve::Constant(value)) => Curve::Constant(value),
+        Some(SimCurve::Ramp { from, to, seconds }) => Curve::Ramp { from, to, seconds },
+        Some(SimCurve::Trace { csv }) => Curve::Trace(
+            read_trace(&csv).with_context(|| format!("Failed to load trace {}", csv.display()))?,
+        ),
+    })
+}
+
+/// Reads `seconds,value` rows, skipping blank lines, `#` comments and a header.
+fn read_trace(path: &Path) -> anyhow::Result<Vec<(f32, f32)>> {
+    let text = fs::read_to_string(path)?;
+    let mut points: Vec<(f32, f32)> = Vec::new();
+    let mut header = true;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line.split_once(',').and_then(|(time, value)| {
+            Some((time.trim().parse().ok()?, value.trim().parse().ok()?))
+        });
+        let Some((time, value)) = parsed else {
+            if header {
+                header = false;
+                continue;
+            }
+            bail!(
+                "line {}: expected `seconds,value`, found `{}`",
+                index + 1,
+                line
+            );
+        };
+        header = false;
+        if points.last().is_some_and(|&(last, _)| time < last) {
+            bail!("line {}: time {} goes backwards", index + 1, time);
+        }
+        points.push((time, value));
+    }
+    if points.is_empty() {
+        bail!("no samples");
+    }
+    Ok(points)
+}
+
+/// Numbers the frames of the display `label`, saving each one to `frames` or
+/// printing it when no directory is given.
+fn frame_sink(
+    label: &str,
+    frames: Option<PathBuf>,
+    format: FrameFormat,
+) -> anyhow::Result<FrameSink> {
+    if let Some(dir) = &frames {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create frame directory {}", dir.display()))?;
+    }
+    let label = label.to_string();
+    let mut count: u32 = 0;
+    Ok(Box::new(move |frame| {
+        count += 1;
+        let (bytes, extension) = match format {
+            FrameFormat::Png => (frame.to_png(), "png"),
+            FrameFormat::Ascii => (frame.to_ascii().into_bytes(), "txt"),
+            FrameFormat::Unicode => (frame.to_unicode().into_bytes(), "txt"),
+        };
+        match &frames {
+            Some(dir) => {
+                let path = dir.join(format!("{}-{:05}.{}", label, count, extension));
+                if let Err(err) = fs::write(&path, bytes) {
+                    eprintln!("Failed to write frame {}: {}", path.display(), err);
+                }
+            }
+            None => println!(
+                "[{} frame {}]\n{}",
+                label,
+                count,
+                String::from_utf8_lossy(&bytes)
+            ),
+        }
+    }))
+}
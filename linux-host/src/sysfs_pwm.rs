@@ -0,0 +1,94 @@
+//! PWM channels driven through the kernel's sysfs interface
+//! (`/sys/class/pwm/pwmchipN/pwmM`), which takes times in nanoseconds.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use pwm::Error;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+pub struct SysfsPwm {
+    dir: PathBuf,
+    period_ns: u64,
+    duty_ns: u64,
+}
+
+impl SysfsPwm {
+    /// Exports the channel if needed and starts it disabled at `frequency` with a 0% duty cycle.
+    pub fn open(chip: u32, channel: u32, frequency: u32) -> io::Result<Self> {
+        let chip_dir = PathBuf::from(format!("/sys/class/pwm/pwmchip{}", chip));
+        let dir = chip_dir.join(format!("pwm{}", channel));
+        if !dir.exists() {
+            fs::write(chip_dir.join("export"), channel.to_string())?;
+        }
+
+        let mut pwm = Self {
+            dir,
+            period_ns: 0,
+            duty_ns: 0,
+        };
+        pwm.write("enable", 0)?;
+        pwm.write("duty_cycle", 0)?;
+        let period = period_ns(frequency)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, too_fast(frequency)))?;
+        pwm.write("period", period)?;
+        pwm.period_ns = period;
+        Ok(pwm)
+    }
+
+    fn write(&self, attribute: &str, value: u64) -> io::Result<()> {
+        fs::write(self.dir.join(attribute), value.to_string())
+    }
+
+    fn write_attr(&self, attribute: &str, value: u64) -> Result<(), Error> {
+        self.write(attribute, value)
+            .map_err(|e| Error::Other(format!("{}: {}", attribute, e)))
+    }
+}
+
+/// None when the period would round down to 0 ns.
+fn period_ns(hz: u32) -> Option<u64> {
+    match NANOS_PER_SEC / u64::from(hz.max(1)) {
+        0 => None,
+        period => Some(period),
+    }
+}
+
+fn too_fast(hz: u32) -> String {
+    format!("{} Hz is faster than sysfs PWM periods can express", hz)
+}
+
+impl pwm::ErasedPwm for SysfsPwm {
+    fn set_duty_cycle_fraction(&mut self, num: u16, denom: u16) -> Result<(), Error> {
+        let duty_ns = self.period_ns * u64::from(num) / u64::from(denom.max(1));
+        self.write_attr("duty_cycle", duty_ns)?;
+        self.duty_ns = duty_ns;
+        Ok(())
+    }
+}
+
+impl pwm::PwmChannel for SysfsPwm {
+    fn set_frequency(&mut self, hz: u32) -> Result<u32, Error> {
+        let period = period_ns(hz).ok_or_else(|| Error::Other(too_fast(hz)))?;
+        let duty = self.duty_ns * period / self.period_ns.max(1);
+
+        // The kernel rejects a duty cycle longer than the period, so order the writes accordingly
+        if period < self.period_ns {
+            self.write_attr("duty_cycle", duty)?;
+            self.write_attr("period", period)?;
+        } else {
+            self.write_attr("period", period)?;
+            self.write_attr("duty_cycle", duty)?;
+        }
+
+        self.period_ns = period;
+        self.duty_ns = duty;
+        Ok((NANOS_PER_SEC / period) as u32)
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        self.write_attr("enable", enabled.into())
+    }
+}
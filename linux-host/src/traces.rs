@@ -0,0 +1,183 @@
+//! `--record` writes every SPI call and pin change the guest makes to a trace
+//! file; `--backend replay` answers the guest from such a file instead of
+//! devices, so a session captured on the board can be rerun anywhere.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{LineWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use ::sim::VirtualClock;
+use anyhow::{Context, bail};
+use policy::linux;
+use trace::{Clock, Divergence, RecordingInputPin, RecordingOutputPin, RecordingSpi, Sink, Trace};
+
+use crate::{Devices, spi_bounds, spi_limits};
+
+/// Wraps every SPI device and pin in a recorder writing to `path`, timestamped
+/// on `virtual_clock` if given and otherwise on wall time since this call.
+pub fn record(
+    mut devices: Devices,
+    path: &str,
+    virtual_clock: Option<&VirtualClock>,
+) -> anyhow::Result<Devices> {
+    let labels = devices
+        .spi
+        .iter()
+        .map(|hardware| &hardware.name)
+        .chain(devices.gpio_pins.keys())
+        .chain(devices.gpio_inputs.keys());
+    for label in labels {
+        if !trace::is_recordable(label) {
+            bail!(
+                "Cannot record label '{}'; labels in a trace must be one word without spaces",
+                label
+            );
+        }
+    }
+
+    let mut file = LineWriter::new(
+        File::create(path).with_context(|| format!("Failed to create trace file '{}'", path))?,
+    );
+    writeln!(file, "# micros section label call")?;
+    let file = Arc::new(Mutex::new(file));
+    let sink = || -> Sink {
+        let file = file.clone();
+        Box::new(move |event| {
+            let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Err(err) = writeln!(file, "{}", event) {
+                eprintln!("Failed to write trace: {}", err);
+            }
+        })
+    };
+    let start = Instant::now();
+    let clock = || -> Clock {
+        match virtual_clock {
+            Some(clock) => clock.clock(),
+            None => Box::new(move || start.elapsed().as_micros() as u64),
+        }
+    };
+
+    devices.spi = devices
+        .spi
+        .into_iter()
+        .map(|hardware| spi::SpiHardware {
+            device: Box::new(RecordingSpi::new(
+                &hardware.name,
+                hardware.device,
+                clock(),
+                sink(),
+            )),
+            ..hardware
+        })
+        .collect();
+    devices.gpio_pins = devices
+        .gpio_pins
+        .into_iter()
+        .map(|(name, pin)| {
+            let pin: Box<dyn gpio::ErasedOutputPin + Send + 'static> =
+                Box::new(RecordingOutputPin::new(&name, pin, clock(), sink()));
+            (name, pin)
+        })
+        .collect();
+    devices.gpio_inputs = devices
+        .gpio_inputs
+        .into_iter()
+        .map(|(name, pin)| {
+            let pin: Box<dyn gpio::ErasedEdgePin + Send + 'static> =
+                Box::new(RecordingInputPin::new(&name, pin, clock(), sink()));
+            (name, pin)
+        })
+        .collect();
+    Ok(devices)
+}
+
+/// Binds the policy's SPI and GPIO labels to the calls recorded for them in
+/// `path`. Every divergence is logged and counted in `divergences`, including
+/// the calls left over when the devices are dropped, and calls `stop`: past a
+/// divergence the replay only answers with made-up data, and a guest that
+/// loops forever would never hand back control.
+pub fn open(
+    policy: linux::Policy,
+    path: &str,
+    divergences: Arc<AtomicUsize>,
+    stop: impl Fn() + Clone + Send + 'static,
+) -> anyhow::Result<Devices> {
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read trace file '{}'", path))?;
+    let mut trace =
+        Trace::parse(&source).with_context(|| format!("Invalid trace file '{}'", path))?;
+
+    let unreplayed = [
+        ("i2c", policy.i2c.is_empty()),
+        ("uart", policy.uart.is_empty()),
+        ("pwm", policy.pwm.is_empty()),
+        ("adc", policy.adc.is_empty()),
+    ];
+    for (section, empty) in unreplayed {
+        if !empty {
+            println!(
+                "Note: --backend replay has no [{}] devices; the guest cannot open them",
+                section
+            );
+        }
+    }
+
+    let report = || {
+        let divergences = divergences.clone();
+        let stop = stop.clone();
+        move |divergence: &Divergence| {
+            divergences.fetch_add(1, Ordering::Relaxed);
+            println!("[Replay] {}", divergence);
+            stop();
+        }
+    };
+
+    let spi_hardware = policy
+        .spi
+        .into_iter()
+        .map(|(name, config)| {
+            let device = trace.spi(&name).on_divergence(report());
+            spi::SpiHardware {
+                limits: spi_limits(&config),
+                bounds: spi_bounds(&config),
+                name,
+                device: Box::new(device),
+            }
+        })
+        .collect();
+
+    let mut gpio_pins: BTreeMap<String, Box<dyn gpio::ErasedOutputPin + Send + 'static>> =
+        BTreeMap::new();
+    for name in policy.gpio.into_keys() {
+        let pin = trace.output(&name).on_divergence(report());
+        gpio_pins.insert(name, Box::new(pin));
+    }
+
+    let mut gpio_inputs: BTreeMap<String, Box<dyn gpio::ErasedEdgePin + Send + 'static>> =
+        BTreeMap::new();
+    for name in policy.gpio_input.into_keys() {
+        let pin = trace.input(&name).on_divergence(report());
+        gpio_inputs.insert(name, Box::new(pin));
+    }
+
+    let unclaimed = trace.unclaimed();
+    if !unclaimed.is_empty() {
+        println!(
+            "Note: the trace has calls for {}, which the policy doesn't grant",
+            unclaimed.join(", ")
+        );
+    }
+
+    Ok(Devices {
+        spi: spi_hardware,
+        i2c: Vec::new(),
+        uart: Vec::new(),
+        pwm: Vec::new(),
+        adc: Vec::new(),
+        gpio_pins,
+        gpio_inputs,
+    })
+}